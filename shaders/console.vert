@@ -0,0 +1,24 @@
+#version 450
+
+// Per-vertex
+layout(location = 0) in vec2 in_position;
+
+// Per-instance
+layout(location = 1) in vec2 in_offset;
+layout(location = 2) in vec2 in_size;
+layout(location = 3) in vec4 in_color;
+
+// Output
+layout(location = 0) out vec2 frag_texture_coord;
+layout(location = 1) out vec4 frag_color;
+
+out gl_PerVertex {
+	vec4 gl_Position;
+};
+
+void main() {
+	frag_texture_coord = in_position;
+	frag_color = in_color;
+
+	gl_Position = vec4(in_offset + in_position * in_size, 0, 1);
+}
@@ -0,0 +1,52 @@
+#version 450
+
+layout(set = 1, binding = 0) uniform sampler2D texture_sampler;
+layout(set = 2, binding = 0) uniform sampler2D scene_sampler;
+
+layout(push_constant) uniform FuzzParams {
+	int fuzz_position;
+	float fuzz_scale;
+} fuzz_params;
+
+layout(location = 0) in vec2 frag_texture_coord;
+layout(location = 1) in float frag_light_level;
+layout(location = 2) flat in int frag_translation;
+
+layout(location = 0) out vec4 out_color;
+
+// The original's fuzz offset table, moving each pixel one row up or down
+const int FUZZ_TABLE_SIZE = 50;
+const int fuzz_offsets[FUZZ_TABLE_SIZE] = int[](
+	1, -1, 1, -1, 1, 1, -1,
+	1, 1, -1, 1, 1, 1, -1,
+	1, 1, 1, -1, -1, -1, -1,
+	1, -1, -1, 1, 1, 1, 1, -1,
+	1, -1, 1, 1, -1, -1, 1,
+	1, -1, -1, -1, -1, 1, 1,
+	1, 1, -1, 1, 1, -1, 1
+);
+
+void main() {
+	// Only the shape of the sprite is used
+	ivec2 size = textureSize(texture_sampler, 0);
+	ivec2 texel = ivec2(floor(frag_texture_coord * vec2(size)));
+	texel -= size * ivec2(floor(vec2(texel) / vec2(size)));
+
+	if (texelFetch(texture_sampler, texel, 0).g < 0.5)
+		discard;
+
+	// The original copied the pixel above or below through colormap 6. This does the same
+	// with a copy of the frame drawn so far. Pixels are scaled to the original's 200 rows, so
+	// the pattern doesn't vanish at high resolutions.
+	ivec2 pixel = ivec2(gl_FragCoord.xy * fuzz_params.fuzz_scale);
+	int index = (pixel.x * 200 + pixel.y + fuzz_params.fuzz_position) % FUZZ_TABLE_SIZE;
+
+	ivec2 scene_size = textureSize(scene_sampler, 0);
+	ivec2 source = ivec2(gl_FragCoord.xy);
+	source.y += int(round(float(fuzz_offsets[index]) / fuzz_params.fuzz_scale));
+	source.y = clamp(source.y, 0, scene_size.y - 1);
+
+	// Colormap 6 is the light level that is 6 steps down from full brightness
+	vec3 color = texelFetch(scene_sampler, source, 0).rgb;
+	out_color = vec4(color * (1.0 - 6.0 / 32.0), 1.0);
+}
@@ -11,6 +11,7 @@ layout(location = 2) in float in_light_level;
 
 layout(location = 0) out vec2 frag_texture_coord;
 layout(location = 1) out float frag_light_level;
+layout(location = 2) flat out int frag_translation;
 
 out gl_PerVertex {
 	vec4 gl_Position;
@@ -19,5 +20,6 @@ out gl_PerVertex {
 void main() {
 	frag_texture_coord = in_texture_coord;
 	frag_light_level = in_light_level;
+	frag_translation = 0;
 	gl_Position = ubo.proj * ubo.view * vec4(in_position, 1);
 }
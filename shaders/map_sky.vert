@@ -7,7 +7,7 @@ layout(set = 0, binding = 0) uniform UniformBufferObject {
 
 layout(location = 0) in vec3 in_position;
 
-layout(location = 0) out vec2 frag_texture_coord;
+layout(location = 0) out vec3 frag_direction;
 
 out gl_PerVertex {
 	vec4 gl_Position;
@@ -15,5 +15,8 @@ out gl_PerVertex {
 
 void main() {
 	gl_Position = ubo.proj * ubo.view * vec4(in_position, 1);
-	frag_texture_coord = (ubo.view * vec4(in_position, 1)).xy / gl_Position.z;
+
+	// The view matrix is a rotation and a translation, so the camera position can be recovered
+	vec3 camera_position = -transpose(mat3(ubo.view)) * ubo.view[3].xyz;
+	frag_direction = in_position - camera_position;
 }
@@ -2,19 +2,82 @@
 
 layout(set = 1, binding = 0) uniform sampler2D texture_sampler;
 
+layout(set = 2, binding = 0) uniform sampler2D colormap_sampler;
+layout(set = 2, binding = 1) uniform sampler2D palette_sampler;
+layout(set = 2, binding = 2) uniform PaletteParams {
+	int colormap_lighting;
+	int filtering;
+	int palette;
+	int fixed_colormap;
+} palette_params;
+layout(set = 2, binding = 3) uniform sampler2D translation_sampler;
+
 layout(location = 0) in vec2 frag_texture_coord;
 layout(location = 1) in float frag_light_level;
+layout(location = 2) flat in int frag_translation;
 
 layout(location = 0) out vec4 out_color;
 
+// Textures hold palette indices in the red channel and alpha in the green channel
+vec4 texel_color(ivec2 texel, int colormap, float light_level) {
+	ivec2 size = textureSize(texture_sampler, 0);
+	texel -= size * ivec2(floor(vec2(texel) / vec2(size)));
+
+	vec2 texel_data = texelFetch(texture_sampler, texel, 0).rg;
+	int index = int(texel_data.r * 255.0 + 0.5);
+	// Player colours
+	index = int(texelFetch(translation_sampler, ivec2(index, frag_translation), 0).r * 255.0 + 0.5);
+	vec3 color;
+
+	if (palette_params.colormap_lighting != 0 || palette_params.fixed_colormap >= 0) {
+		index = int(texelFetch(colormap_sampler, ivec2(index, colormap), 0).r * 255.0 + 0.5);
+		color = texelFetch(palette_sampler, ivec2(index, palette_params.palette), 0).rgb;
+	} else {
+		color = texelFetch(palette_sampler, ivec2(index, palette_params.palette), 0).rgb;
+		color *= light_level;
+	}
+
+	return vec4(color, texel_data.g);
+}
+
 void main() {
+	// Light level and distance pick one of 32 colormaps, 0 being the brightest
 	float light_factor = 60.0 - 64.0 * frag_light_level;
 	float distance_factor = 80.0 / ((0.0625 / gl_FragCoord.w) + 1.0);
-	float light_level = 1.0 - (light_factor - distance_factor) / 32.0;
-	light_level = clamp(light_level, 0.0, 1.0);
+	float colormap = light_factor - distance_factor;
+	int colormap_index = int(clamp(colormap, 0.0, 31.0));
+	float light_level = clamp(1.0 - colormap / 32.0, 0.0, 1.0);
+
+	// Invulnerability and light amplification override the lighting
+	if (palette_params.fixed_colormap >= 0) {
+		colormap_index = palette_params.fixed_colormap;
+		light_level = 1.0;
+	}
+
+	// Filtering has to be done after the palette lookup, so it's done here instead of by the sampler
+	vec2 coord = frag_texture_coord * vec2(textureSize(texture_sampler, 0));
+
+	if (palette_params.filtering != 0) {
+		coord -= 0.5;
+		ivec2 texel = ivec2(floor(coord));
+		vec2 blend = fract(coord);
 
-	vec4 texture_texel = texture(texture_sampler, frag_texture_coord);
-	out_color = vec4(texture_texel.rgb * light_level, texture_texel.a);
+		out_color = mix(
+			mix(
+				texel_color(texel, colormap_index, light_level),
+				texel_color(texel + ivec2(1, 0), colormap_index, light_level),
+				blend.x
+			),
+			mix(
+				texel_color(texel + ivec2(0, 1), colormap_index, light_level),
+				texel_color(texel + ivec2(1, 1), colormap_index, light_level),
+				blend.x
+			),
+			blend.y
+		);
+	} else {
+		out_color = texel_color(ivec2(floor(coord)), colormap_index, light_level);
+	}
 
 	if (out_color.a < 0.5)
 		discard;
@@ -1,22 +1,43 @@
 #version 450
 
 layout(set = 1, binding = 0) uniform sampler2D texture_sampler;
-layout(set = 1, binding = 1) uniform FragParams {
-	float yaw;
-	float pitch;
-	vec2 screenSize;
-} fp;
 
-layout(location = 0) in vec2 frag_texture_coord;
+layout(set = 2, binding = 0) uniform sampler2D colormap_sampler;
+layout(set = 2, binding = 1) uniform sampler2D palette_sampler;
+layout(set = 2, binding = 2) uniform PaletteParams {
+	int colormap_lighting;
+	int filtering;
+	int palette;
+	int fixed_colormap;
+} palette_params;
+
+layout(location = 0) in vec3 frag_direction;
 
 layout(location = 0) out vec4 out_color;
 
+const float PI = 3.14159265358979;
+
 void main() {
-	vec2 texCoords = gl_FragCoord.xy / fp.screenSize;
-	float ratio = fp.screenSize.x / fp.screenSize.y;
-	texCoords.x = (1.0 - texCoords.x) + (fp.yaw + 45.0) / 360.0 * 4;
-	texCoords.y = (texCoords.y + fp.pitch / 60.0) * ratio;
+	ivec2 size = textureSize(texture_sampler, 0);
+
+	// As in the original, 1024 columns go around the full circle, and rows are pixels of its
+	// 200-row screen, 160 of them spanning 45 degrees up or down. The mapping is done on the
+	// direction in the world, so it holds for any field of view.
+	float angle = atan(frag_direction.y, frag_direction.x) / (2.0 * PI);
+	float slope = frag_direction.z / max(length(frag_direction.xy), 0.0001);
+
+	// Skies taller than 128 extend upwards, with the horizon staying 28 rows above the bottom
+	float texture_mid = float(size.y) - 28.0;
+	ivec2 texel = ivec2(floor(vec2(angle * 1024.0, texture_mid - slope * 160.0)));
+	texel.x -= size.x * int(floor(float(texel.x) / float(size.x)));
+	texel.y = clamp(texel.y, 0, size.y - 1);
+
+	// The sky is always full bright, so it only needs the palette
+	int index = int(texelFetch(texture_sampler, texel, 0).r * 255.0 + 0.5);
+
+	if (palette_params.colormap_lighting != 0) {
+		index = int(texelFetch(colormap_sampler, ivec2(index, 0), 0).r * 255.0 + 0.5);
+	}
 
-	vec4 texture_texel = texture(texture_sampler, texCoords);
-	out_color = vec4(texture_texel.rgb, 1.0);
+	out_color = vec4(texelFetch(palette_sampler, ivec2(index, palette_params.palette), 0).rgb, 1.0);
 }
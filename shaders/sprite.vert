@@ -15,10 +15,12 @@ layout(location = 1) in vec2 in_texture_coord;
 layout(location = 2) in float in_flip;
 layout(location = 3) in float in_light_level;
 layout(location = 4) in mat4 in_matrix;
+layout(location = 8) in int in_translation;
 
 // Output
 layout(location = 0) out vec2 frag_texture_coord;
 layout(location = 1) out float frag_light_level;
+layout(location = 2) flat out int frag_translation;
 
 out gl_PerVertex {
 	vec4 gl_Position;
@@ -27,6 +29,7 @@ out gl_PerVertex {
 void main() {
 	frag_texture_coord = vec2(in_texture_coord.x * in_flip, in_texture_coord.y);
 	frag_light_level = in_light_level;
+	frag_translation = in_translation;
 
 	gl_Position = matrices.proj * matrices.view * in_matrix * vec4(in_position, 1);
 }
@@ -125,6 +125,16 @@ impl<A: Asset> AssetStorage<A> {
 		self.names.get(name).and_then(WeakHandle::upgrade)
 	}
 
+	pub fn name_for(&self, handle: &AssetHandle<A>) -> Option<&str> {
+		self.names.iter().find_map(|(name, weak)| {
+			if weak.upgrade().as_ref() == Some(handle) {
+				Some(name.as_str())
+			} else {
+				None
+			}
+		})
+	}
+
 	#[inline]
 	pub fn insert(&mut self, data: A::Data) -> AssetHandle<A> {
 		let handle = self.allocate_handle();
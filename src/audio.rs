@@ -1,36 +1,220 @@
-use anyhow::Context;
-use crossbeam_channel::Sender;
-use rodio::{
-	source::{ChannelVolume, Done},
-	Sample, Source,
+use anyhow::{anyhow, bail, ensure, Context};
+use byteorder::{WriteBytesExt, LE};
+use minimp3_sys::{
+	mp3dec_decode_frame, mp3dec_frame_info_t, mp3dec_init, mp3dec_t, MINIMP3_MAX_SAMPLES_PER_FRAME,
 };
+use rodio::{source::UniformSourceIterator, Decoder, DeviceTrait, Source};
 use std::{
+	fmt,
+	fs::File,
+	io::{BufWriter, Cursor, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+	str::FromStr,
 	sync::{
-		atomic::{AtomicBool, AtomicUsize, Ordering},
+		atomic::{AtomicBool, Ordering},
 		Arc, Mutex,
 	},
-	thread::Builder,
 	time::Duration,
 };
 
-pub fn init() -> anyhow::Result<Sender<Box<dyn Source<Item = f32> + Send>>> {
-	let (sender, receiver) = crossbeam_channel::unbounded();
+pub const OUTPUT_RATE: u32 = 44100;
 
-	Builder::new()
-		.name("audio".to_owned())
-		.spawn(move || {
-			let device = rodio::default_output_device().unwrap();
+// Frames to mix at a time
+const MIX_FRAMES: usize = 256;
 
-			// Play a dummy sound to force the sound engine to initialise itself
-			rodio::play_raw(&device, rodio::source::Empty::new());
+/// Where the output of the mixer goes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AudioBackend {
+	/// Play on the default audio device.
+	Device,
+	/// Mix along with the game tics, but throw away the result.
+	Null,
+	/// Mix along with the game tics and write the result to a WAV file.
+	Wav,
+}
+
+impl FromStr for AudioBackend {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<AudioBackend> {
+		Ok(match name.to_ascii_lowercase().as_str() {
+			"device" => AudioBackend::Device,
+			"null" => AudioBackend::Null,
+			"wav" => AudioBackend::Wav,
+			_ => bail!("Unknown audio backend: {}", name),
+		})
+	}
+}
+
+impl fmt::Display for AudioBackend {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match self {
+			AudioBackend::Device => "device",
+			AudioBackend::Null => "null",
+			AudioBackend::Wav => "wav",
+		})
+	}
+}
+
+/// Sends the output of a mixer to a backend, until it is dropped.
+/// The device mixes on its own, the other backends mix when the game advances them.
+pub struct AudioOutput {
+	backend: AudioBackend,
+	buffer: Vec<f32>,
+	frames_mixed: u64,
+	mixer: Mixer,
+	path: PathBuf,
+	running: Arc<AtomicBool>,
+	time_mixed: Duration,
+	writer: Option<WavWriter>,
+}
 
-			for source in receiver {
+impl AudioOutput {
+	/// Starts the backend. `path` is only used by the WAV backend.
+	pub fn new(mixer: &Mixer, backend: AudioBackend, path: &Path) -> anyhow::Result<AudioOutput> {
+		let running = Arc::new(AtomicBool::new(true));
+		let mut writer = None;
+
+		match backend {
+			AudioBackend::Device => {
+				let device = rodio::default_output_device()
+					.ok_or_else(|| anyhow!("No audio output device found"))?;
+
+				// Rodio panics if these fail, so check them first
+				device.name().context("Couldn't get audio device name")?;
+				device
+					.default_output_format()
+					.context("Couldn't get audio device output format")?;
+
+				let source = MixerSource {
+					buffer: Vec::new(),
+					buffer_position: 0,
+					mixer: mixer.clone(),
+					running: running.clone(),
+				};
 				rodio::play_raw(&device, source);
 			}
+			AudioBackend::Null => {}
+			AudioBackend::Wav => {
+				writer = Some(
+					WavWriter::new(path)
+						.context(format!("Couldn't create WAV file {}", path.display()))?,
+				);
+			}
+		}
+
+		Ok(AudioOutput {
+			backend,
+			buffer: vec![0.0; MIX_FRAMES * 2],
+			frames_mixed: 0,
+			mixer: mixer.clone(),
+			path: path.to_owned(),
+			running,
+			time_mixed: Duration::default(),
+			writer,
 		})
-		.context("Couldn't spawn audio thread")?;
+	}
+
+	#[inline]
+	pub fn backend(&self) -> AudioBackend {
+		self.backend
+	}
 
-	Ok(sender)
+	#[inline]
+	pub fn path(&self) -> &Path {
+		&self.path
+	}
+
+	/// Mixes `time` worth of output, if the backend doesn't mix on its own.
+	/// Called once per game tic, so that the output is the same however fast the game runs.
+	pub fn advance(&mut self, time: Duration) {
+		if self.backend == AudioBackend::Device || !self.running.load(Ordering::Relaxed) {
+			return;
+		}
+
+		self.time_mixed += time;
+		let frames_due = (self.time_mixed.as_nanos() * OUTPUT_RATE as u128 / 1_000_000_000) as u64;
+
+		while self.frames_mixed < frames_due {
+			let frames = ((frames_due - self.frames_mixed) as usize).min(MIX_FRAMES);
+			let buffer = &mut self.buffer[..frames * 2];
+			self.mixer.mix(buffer);
+			self.frames_mixed += frames as u64;
+
+			if let Some(writer) = &mut self.writer {
+				if let Err(err) = writer.write(buffer) {
+					log::error!("Audio output failed: {:#}", err);
+					self.writer = None;
+				}
+			}
+		}
+	}
+
+	/// Stops the backend, and finishes the WAV file if there is one.
+	pub fn stop(&mut self) {
+		self.running.store(false, Ordering::Relaxed);
+
+		if let Some(writer) = self.writer.take() {
+			if let Err(err) = writer.finish() {
+				log::error!("Couldn't finish writing WAV file: {:#}", err);
+			}
+		}
+	}
+}
+
+impl Drop for AudioOutput {
+	fn drop(&mut self) {
+		self.stop();
+	}
+}
+
+/// Writes 16-bit stereo samples at `OUTPUT_RATE` to a WAV file.
+struct WavWriter {
+	file: BufWriter<File>,
+	data_size: u32,
+}
+
+impl WavWriter {
+	fn new(path: &Path) -> anyhow::Result<WavWriter> {
+		let mut file = BufWriter::new(File::create(path)?);
+
+		// The sizes are filled in by finish
+		file.write_all(b"RIFF")?;
+		file.write_u32::<LE>(0)?;
+		file.write_all(b"WAVE")?;
+
+		file.write_all(b"fmt ")?;
+		file.write_u32::<LE>(16)?;
+		file.write_u16::<LE>(1)?; // PCM
+		file.write_u16::<LE>(2)?; // Channels
+		file.write_u32::<LE>(OUTPUT_RATE)?;
+		file.write_u32::<LE>(OUTPUT_RATE * 4)?; // Bytes per second
+		file.write_u16::<LE>(4)?; // Bytes per frame
+		file.write_u16::<LE>(16)?; // Bits per sample
+
+		file.write_all(b"data")?;
+		file.write_u32::<LE>(0)?;
+
+		Ok(WavWriter { file, data_size: 0 })
+	}
+
+	fn write(&mut self, samples: &[f32]) -> anyhow::Result<()> {
+		for &sample in samples {
+			self.file.write_i16::<LE>((sample * 32767.0) as i16)?;
+		}
+
+		self.data_size += samples.len() as u32 * 2;
+		Ok(())
+	}
+
+	fn finish(mut self) -> anyhow::Result<()> {
+		self.file.seek(SeekFrom::Start(4))?;
+		self.file.write_u32::<LE>(self.data_size + 36)?;
+		self.file.seek(SeekFrom::Start(40))?;
+		self.file.write_u32::<LE>(self.data_size)?;
+		self.file.flush()?;
+		Ok(())
+	}
 }
 
 /*pub struct Audio {}
@@ -45,48 +229,114 @@ impl Drop for Audio {
 	fn drop(&mut self) {}
 }*/
 
-pub struct Sound {
-	pub data: Arc<[i16]>,
-	pub sample_rate: u32,
+/// Names the compressed audio format of a lump, if it looks like one.
+/// These are common in PWADs, and are decoded with rodio.
+pub fn compressed_format(data: &[u8]) -> Option<&'static str> {
+	match data {
+		[b'O', b'g', b'g', b'S', ..] => Some("Ogg"),
+		[b'f', b'L', b'a', b'C', ..] => Some("FLAC"),
+		[b'I', b'D', b'3', ..] => Some("MP3"),
+		[0xFF, second, ..] if second & 0xE0 == 0xE0 => Some("MP3"),
+		_ => None,
+	}
 }
 
-pub struct SoundSource {
-	current: usize,
-	data: Arc<[i16]>,
-	duration: Duration,
+/// Decodes Ogg Vorbis or FLAC data with rodio, or MP3 data with minimp3.
+pub fn decode_compressed(data: Arc<[u8]>) -> anyhow::Result<Box<dyn Source<Item = i16> + Send>> {
+	if compressed_format(&data) == Some("MP3") {
+		Ok(Box::new(Mp3Decoder::new(data)?))
+	} else {
+		Ok(Box::new(
+			Decoder::new(Cursor::new(data)).context("Couldn't decode audio")?,
+		))
+	}
+}
+
+/// Decodes MP3 data one frame at a time.
+struct Mp3Decoder {
+	channels: u16,
+	data: Arc<[u8]>,
+	decoder: Box<mp3dec_t>,
+	frame: Vec<i16>,
+	frame_position: usize,
+	position: usize,
 	sample_rate: u32,
 }
 
-impl SoundSource {
-	pub fn new(sound: &Sound) -> Self {
-		let duration_ns = 1_000_000_000u64
-			.checked_mul(sound.data.len() as u64)
-			.unwrap() / sound.sample_rate as u64;
-		let duration = Duration::new(
-			duration_ns / 1_000_000_000,
-			(duration_ns % 1_000_000_000) as u32,
-		);
+impl Mp3Decoder {
+	fn new(data: Arc<[u8]>) -> anyhow::Result<Mp3Decoder> {
+		// mp3dec_init initialises the parts that need it
+		let mut decoder = Box::new(unsafe { std::mem::zeroed::<mp3dec_t>() });
+		unsafe { mp3dec_init(&mut *decoder) };
 
-		let sample_rate = sound.sample_rate;
+		let mut mp3 = Mp3Decoder {
+			channels: 0,
+			data,
+			decoder,
+			frame: Vec::with_capacity(MINIMP3_MAX_SAMPLES_PER_FRAME as usize),
+			frame_position: 0,
+			position: 0,
+			sample_rate: 0,
+		};
 
-		SoundSource {
-			current: 0,
-			data: sound.data.clone(),
-			duration,
-			sample_rate,
+		ensure!(mp3.decode_frame(), "No MP3 frames found");
+		Ok(mp3)
+	}
+
+	/// Decodes the next frame that has samples, returns false at the end of the data.
+	fn decode_frame(&mut self) -> bool {
+		let mut pcm = [0i16; MINIMP3_MAX_SAMPLES_PER_FRAME as usize];
+
+		loop {
+			let remaining = &self.data[self.position..];
+			let mut info = mp3dec_frame_info_t {
+				frame_bytes: 0,
+				frame_offset: 0,
+				channels: 0,
+				hz: 0,
+				layer: 0,
+				bitrate_kbps: 0,
+			};
+
+			let samples = unsafe {
+				mp3dec_decode_frame(
+					&mut *self.decoder,
+					remaining.as_ptr(),
+					remaining.len().min(i32::MAX as usize) as i32,
+					pcm.as_mut_ptr(),
+					&mut info,
+				)
+			};
+
+			// Frames without samples are other data that was skipped
+			if info.frame_bytes <= 0 {
+				return false;
+			}
+
+			self.position += info.frame_bytes as usize;
+
+			if samples > 0 && info.channels > 0 {
+				self.channels = info.channels as u16;
+				self.sample_rate = info.hz as u32;
+				self.frame.clear();
+				self.frame
+					.extend_from_slice(&pcm[..samples as usize * info.channels as usize]);
+				self.frame_position = 0;
+				return true;
+			}
 		}
 	}
 }
 
-impl Source for SoundSource {
+impl Source for Mp3Decoder {
 	#[inline]
 	fn current_frame_len(&self) -> Option<usize> {
-		None
+		Some(self.frame.len() - self.frame_position)
 	}
 
 	#[inline]
 	fn channels(&self) -> u16 {
-		1
+		self.channels
 	}
 
 	#[inline]
@@ -96,82 +346,338 @@ impl Source for SoundSource {
 
 	#[inline]
 	fn total_duration(&self) -> Option<Duration> {
-		Some(self.duration)
+		None
 	}
 }
 
-impl Iterator for SoundSource {
+impl Iterator for Mp3Decoder {
 	type Item = i16;
 
 	#[inline]
 	fn next(&mut self) -> Option<Self::Item> {
-		let item = self.data.get(self.current);
-		self.current += 1;
-		item.copied()
+		if self.frame_position == self.frame.len() && !self.decode_frame() {
+			return None;
+		}
+
+		let sample = self.frame[self.frame_position];
+		self.frame_position += 1;
+		Some(sample)
+	}
+}
+
+/// Plays Ogg Vorbis, FLAC or MP3 data. Looping decodes it again from the start.
+pub struct DecoderSource {
+	data: Arc<[u8]>,
+	decoder: Box<dyn Source<Item = i16> + Send>,
+	looping: bool,
+}
+
+impl DecoderSource {
+	pub fn new(data: Arc<[u8]>, looping: bool) -> anyhow::Result<DecoderSource> {
+		Ok(DecoderSource {
+			decoder: decode_compressed(data.clone())?,
+			data,
+			looping,
+		})
+	}
+}
+
+impl Source for DecoderSource {
+	#[inline]
+	fn current_frame_len(&self) -> Option<usize> {
+		self.decoder.current_frame_len()
+	}
+
+	#[inline]
+	fn channels(&self) -> u16 {
+		self.decoder.channels()
+	}
+
+	#[inline]
+	fn sample_rate(&self) -> u32 {
+		self.decoder.sample_rate()
 	}
 
 	#[inline]
-	fn size_hint(&self) -> (usize, Option<usize>) {
-		let len = self.data.len();
-		(len, Some(len))
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
+
+impl Iterator for DecoderSource {
+	type Item = f32;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		let sample = match self.decoder.next() {
+			Some(sample) => sample,
+			None if self.looping => {
+				self.decoder = decode_compressed(self.data.clone()).ok()?;
+				self.decoder.next()?
+			}
+			None => return None,
+		};
+
+		Some(sample as f32 / 32768.0)
 	}
 }
 
-pub struct SoundController {
-	controls: Arc<Controls>,
-	is_playing: Arc<AtomicUsize>,
+pub struct Sound {
+	pub data: Arc<[i16]>,
+	pub sample_rate: u32,
+}
+
+/// Identifies a sound that was started on the mixer.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ChannelHandle(u64);
+
+struct Channel {
+	data: Arc<[i16]>,
+	handle: ChannelHandle,
+	/// Position in the sound data, in samples.
+	position: f64,
+	priority: i32,
+	/// Sound samples per output sample.
+	step: f64,
+	volumes: [f32; 2],
 }
 
-struct Controls {
-	stopped: AtomicBool,
-	volumes: Mutex<[f32; 2]>,
+struct MixerState {
+	channels: Vec<Option<Channel>>,
+	master_volume: f32,
+	music: Option<Box<dyn Iterator<Item = f32> + Send>>,
+	music_volume: f32,
+	next_handle: u64,
+	sfx_volume: f32,
 }
 
-impl SoundController {
-	#[inline]
-	pub fn new<S>(source: S) -> (SoundController, impl Source<Item = S::Item>)
-	where
-		S: Source + Send + 'static,
-		S::Item: Sample,
-		S::Item: Send,
-	{
-		let controller = SoundController {
-			controls: Arc::new(Controls {
-				stopped: AtomicBool::new(false),
-				volumes: Mutex::new([1.0, 1.0]),
-			}),
-			is_playing: Arc::new(AtomicUsize::new(1)),
+/// Mixes sounds and music into a stereo stream.
+/// There is a limited number of channels, so sounds can replace others that are less important.
+#[derive(Clone)]
+pub struct Mixer {
+	state: Arc<Mutex<MixerState>>,
+}
+
+impl Mixer {
+	pub fn new() -> Mixer {
+		Mixer {
+			state: Arc::new(Mutex::new(MixerState {
+				channels: (0..8).map(|_| None).collect(),
+				master_volume: 1.0,
+				music: None,
+				music_volume: 1.0,
+				next_handle: 0,
+				sfx_volume: 1.0,
+			})),
+		}
+	}
+
+	/// Starts playing a sound with the given left and right volumes, if there is a channel for it.
+	/// Lower priority numbers are more important. If every channel is in use, the quietest sound
+	/// that isn't more important is replaced. Singular sounds stop other instances of themselves.
+	pub fn play(
+		&self,
+		sound: &Sound,
+		priority: i32,
+		singular: bool,
+		volumes: [f32; 2],
+	) -> Option<ChannelHandle> {
+		let mut state = self.state.lock().unwrap();
+
+		if singular {
+			for slot in state.channels.iter_mut() {
+				if matches!(slot, Some(channel) if Arc::ptr_eq(&channel.data, &sound.data)) {
+					*slot = None;
+				}
+			}
+		}
+
+		let index = match state.channels.iter().position(Option::is_none) {
+			Some(index) => index,
+			None => state
+				.channels
+				.iter()
+				.enumerate()
+				.filter_map(|(index, slot)| slot.as_ref().map(|channel| (index, channel)))
+				.filter(|(_, channel)| channel.priority >= priority)
+				.min_by(|(_, a), (_, b)| {
+					let a = a.volumes[0] + a.volumes[1];
+					let b = b.volumes[0] + b.volumes[1];
+					a.partial_cmp(&b).unwrap()
+				})
+				.map(|(index, _)| index)?,
 		};
 
-		let controls = controller.controls.clone();
-		let source = ChannelVolume::new(source.stoppable(), vec![1.0, 1.0]).periodic_access(
-			Duration::from_millis(5),
-			move |src| {
-				if controls.stopped.load(Ordering::SeqCst) {
-					src.inner_mut().stop();
-				} else {
-					let volumes = controls.volumes.lock().unwrap();
-					src.set_volume(0, volumes[0]);
-					src.set_volume(1, volumes[1]);
+		let handle = ChannelHandle(state.next_handle);
+		state.next_handle += 1;
+		state.channels[index] = Some(Channel {
+			data: sound.data.clone(),
+			handle,
+			position: 0.0,
+			priority,
+			step: sound.sample_rate as f64 / OUTPUT_RATE as f64,
+			volumes,
+		});
+
+		Some(handle)
+	}
+
+	pub fn is_playing(&self, handle: ChannelHandle) -> bool {
+		let state = self.state.lock().unwrap();
+		state
+			.channels
+			.iter()
+			.any(|slot| matches!(slot, Some(channel) if channel.handle == handle))
+	}
+
+	pub fn set_volumes(&self, handle: ChannelHandle, volumes: [f32; 2]) {
+		let mut state = self.state.lock().unwrap();
+
+		if let Some(channel) = state
+			.channels
+			.iter_mut()
+			.flatten()
+			.find(|channel| channel.handle == handle)
+		{
+			channel.volumes = volumes;
+		}
+	}
+
+	pub fn stop(&self, handle: ChannelHandle) {
+		let mut state = self.state.lock().unwrap();
+
+		for slot in state.channels.iter_mut() {
+			if matches!(slot, Some(channel) if channel.handle == handle) {
+				*slot = None;
+			}
+		}
+	}
+
+	/// Changes the number of channels, stopping sounds on the channels that are removed.
+	pub fn set_channel_count(&self, count: usize) {
+		let mut state = self.state.lock().unwrap();
+		state.channels.resize_with(count, || None);
+	}
+
+	/// Replaces the music, which is mixed in with its own volume.
+	pub fn set_music(&self, source: Option<Box<dyn Source<Item = f32> + Send>>) {
+		let mut state = self.state.lock().unwrap();
+		state.music = source.map(|source| {
+			Box::new(UniformSourceIterator::new(source, 2, OUTPUT_RATE))
+				as Box<dyn Iterator<Item = f32> + Send>
+		});
+	}
+
+	pub fn set_master_volume(&self, volume: f32) {
+		self.state.lock().unwrap().master_volume = volume;
+	}
+
+	pub fn set_music_volume(&self, volume: f32) {
+		self.state.lock().unwrap().music_volume = volume;
+	}
+
+	pub fn set_sfx_volume(&self, volume: f32) {
+		self.state.lock().unwrap().sfx_volume = volume;
+	}
+
+	/// Fills the buffer with interleaved stereo samples at `OUTPUT_RATE`.
+	pub fn mix(&self, buffer: &mut [f32]) {
+		let mut state = self.state.lock().unwrap();
+		let state = &mut *state;
+
+		for frame in buffer.chunks_exact_mut(2) {
+			let mut output = [0.0f32; 2];
+
+			for slot in state.channels.iter_mut() {
+				let channel = match slot {
+					Some(channel) => channel,
+					None => continue,
+				};
+
+				let index = channel.position as usize;
+
+				if index >= channel.data.len() {
+					*slot = None;
+					continue;
 				}
-			},
-		);
-		let source = Done::new(source, controller.is_playing.clone());
-		(controller, source)
+
+				// Linear interpolation between the sound's own samples
+				let fraction = channel.position.fract() as f32;
+				let current = channel.data[index] as f32;
+				let next = channel.data.get(index + 1).copied().unwrap_or(0) as f32;
+				let sample = (current + (next - current) * fraction) / 32768.0;
+
+				output[0] += sample * channel.volumes[0];
+				output[1] += sample * channel.volumes[1];
+				channel.position += channel.step;
+			}
+
+			output[0] *= state.sfx_volume;
+			output[1] *= state.sfx_volume;
+
+			if let Some(music) = &mut state.music {
+				match (music.next(), music.next()) {
+					(Some(left), Some(right)) => {
+						output[0] += left * state.music_volume;
+						output[1] += right * state.music_volume;
+					}
+					_ => state.music = None,
+				}
+			}
+
+			frame[0] = (output[0] * state.master_volume).clamp(-1.0, 1.0);
+			frame[1] = (output[1] * state.master_volume).clamp(-1.0, 1.0);
+		}
+	}
+}
+
+/// Plays the output of the mixer on an audio device.
+struct MixerSource {
+	buffer: Vec<f32>,
+	buffer_position: usize,
+	mixer: Mixer,
+	running: Arc<AtomicBool>,
+}
+
+impl Source for MixerSource {
+	#[inline]
+	fn current_frame_len(&self) -> Option<usize> {
+		None
 	}
 
 	#[inline]
-	pub fn stop(&self) {
-		self.controls.stopped.store(true, Ordering::SeqCst);
+	fn channels(&self) -> u16 {
+		2
 	}
 
 	#[inline]
-	pub fn is_done(&self) -> bool {
-		self.is_playing.load(Ordering::Relaxed) == 0
+	fn sample_rate(&self) -> u32 {
+		OUTPUT_RATE
 	}
 
 	#[inline]
-	pub fn set_volumes(&self, volumes: [f32; 2]) {
-		*self.controls.volumes.lock().unwrap() = volumes;
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
+
+impl Iterator for MixerSource {
+	type Item = f32;
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.buffer_position == self.buffer.len() {
+			// Ending the source removes it from the device
+			if !self.running.load(Ordering::Relaxed) {
+				return None;
+			}
+
+			self.buffer.resize(MIX_FRAMES * 2, 0.0);
+			self.mixer.mix(&mut self.buffer);
+			self.buffer_position = 0;
+		}
+
+		let sample = self.buffer[self.buffer_position];
+		self.buffer_position += 1;
+		Some(sample)
 	}
 }
@@ -0,0 +1,163 @@
+use anyhow::{anyhow, bail, Context};
+use byteorder::{NetworkEndian as NE, ReadBytesExt, WriteBytesExt};
+use clap::{App, Arg};
+use std::{
+	io::{BufRead, Cursor, Read, Write},
+	net::{ToSocketAddrs, UdpSocket},
+	time::{Duration, Instant},
+};
+
+// These must match the definitions in the game's net and protocol modules, the tests in the
+// protocol module check this
+pub(crate) const DEFAULT_PORT: u16 = 5029;
+const UNSEQUENCED: u32 = 0xFFFFFFFF;
+const CLIENT_RCON: u8 = 2;
+const SERVER_RCON_OUTPUT: u8 = 8;
+
+// Commands such as map changes can take a while before any output arrives
+const FIRST_REPLY_TIMEOUT: Duration = Duration::from_secs(10);
+const NEXT_REPLY_TIMEOUT: Duration = Duration::from_millis(250);
+
+fn main() -> anyhow::Result<()> {
+	let arg_matches = App::new("ferret-rcon")
+		.about("Sends commands to the remote console of a Ferret server")
+		.version(clap::crate_version!())
+		.arg(
+			Arg::with_name("ADDRESS")
+				.help("Address of the server")
+				.required(true),
+		)
+		.arg(
+			Arg::with_name("COMMAND")
+				.help("Command to run, if not given, commands are read from standard input")
+				.multiple(true),
+		)
+		.arg(
+			Arg::with_name("password")
+				.help("Rcon password of the server, FERRET_RCON_PASSWORD is used if not given")
+				.short("p")
+				.long("password")
+				.value_name("PASSWORD"),
+		)
+		.get_matches();
+
+	let password = match arg_matches.value_of("password") {
+		Some(password) => password.to_owned(),
+		None => std::env::var("FERRET_RCON_PASSWORD")
+			.context("No password given, use --password or FERRET_RCON_PASSWORD")?,
+	};
+
+	let address = arg_matches.value_of("ADDRESS").unwrap();
+	let addr = match address.to_socket_addrs() {
+		Ok(mut addrs) => addrs.next(),
+		Err(_) => (address, DEFAULT_PORT)
+			.to_socket_addrs()
+			.context(format!("Could not resolve address: {}", address))?
+			.next(),
+	}
+	.ok_or(anyhow!("No address found for {}", address))?;
+
+	let bind_addr = if addr.is_ipv4() {
+		"0.0.0.0:0"
+	} else {
+		"[::]:0"
+	};
+	let socket = UdpSocket::bind(bind_addr).context("Could not create socket")?;
+	socket.connect(addr)?;
+
+	if let Some(command) = arg_matches.values_of("COMMAND") {
+		let command = command.collect::<Vec<_>>().join(" ");
+		run_command(&socket, &password, &command)?;
+	} else {
+		let stdin = std::io::stdin();
+
+		for line in stdin.lock().lines() {
+			run_command(&socket, &password, &line?)?;
+		}
+	}
+
+	Ok(())
+}
+
+fn run_command(socket: &UdpSocket, password: &str, command: &str) -> anyhow::Result<()> {
+	socket
+		.send(&write_command(password, command)?)
+		.context("Could not send command")?;
+
+	let mut buf = [0u8; 8192];
+	let mut timeout = FIRST_REPLY_TIMEOUT;
+	let start_time = Instant::now();
+
+	loop {
+		socket.set_read_timeout(Some(timeout))?;
+
+		let size = match socket.recv(&mut buf) {
+			Ok(size) => size,
+			Err(err)
+				if err.kind() == std::io::ErrorKind::WouldBlock
+					|| err.kind() == std::io::ErrorKind::TimedOut =>
+			{
+				if timeout == FIRST_REPLY_TIMEOUT {
+					bail!(
+						"No reply from the server after {} s",
+						(Instant::now() - start_time).as_secs()
+					);
+				}
+
+				return Ok(());
+			}
+			Err(err) => return Err(err).context("Could not receive reply"),
+		};
+
+		match read_output(&buf[..size]) {
+			Ok(lines) => {
+				for line in lines {
+					println!("{}", line);
+				}
+			}
+			Err(err) => eprintln!("Received a malformed packet: {}", err),
+		}
+
+		timeout = NEXT_REPLY_TIMEOUT;
+	}
+}
+
+pub(crate) fn write_command(password: &str, command: &str) -> anyhow::Result<Vec<u8>> {
+	let mut writer = Cursor::new(Vec::new());
+	writer.write_u32::<NE>(UNSEQUENCED)?;
+	writer.write_u8(CLIENT_RCON)?;
+	write_string(&mut writer, password)?;
+	write_string(&mut writer, command)?;
+	Ok(writer.into_inner())
+}
+
+pub(crate) fn read_output(data: &[u8]) -> anyhow::Result<Vec<String>> {
+	let mut reader = Cursor::new(data);
+	let mut lines = Vec::new();
+
+	if reader.read_u32::<NE>()? != UNSEQUENCED {
+		// Game traffic, not for us
+		return Ok(lines);
+	}
+
+	while reader.position() < data.len() as u64 {
+		let message_type = reader.read_u8()?;
+
+		if message_type != SERVER_RCON_OUTPUT {
+			bail!("unexpected message type {}", message_type);
+		}
+
+		let length = reader.read_u32::<NE>()? as usize;
+		let mut text = vec![0u8; length];
+		reader.read_exact(&mut text)?;
+		lines.push(String::from_utf8(text)?);
+	}
+
+	Ok(lines)
+}
+
+fn write_string(writer: &mut Cursor<Vec<u8>>, text: &str) -> anyhow::Result<()> {
+	writer.write_u32::<NE>(text.len() as u32)?;
+	writer.write_all(text.as_bytes())?;
+	Ok(())
+}
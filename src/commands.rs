@@ -2,7 +2,15 @@ use anyhow::{bail, Context};
 use crossbeam_channel::{Receiver, Sender};
 use lazy_static::lazy_static;
 use regex::{Captures, Regex};
-use std::{io::BufRead, thread::Builder};
+use specs::World;
+use std::{
+	collections::{HashMap, VecDeque},
+	io::BufRead,
+	path::Path,
+	rc::Rc,
+	str::FromStr,
+	thread::Builder,
+};
 
 pub fn init() -> anyhow::Result<(Sender<String>, Receiver<String>)> {
 	let (sender, receiver) = crossbeam_channel::unbounded();
@@ -32,70 +40,502 @@ pub fn init() -> anyhow::Result<(Sender<String>, Receiver<String>)> {
 	Ok((sender, receiver))
 }
 
-/*pub struct CommandList<T> {
-	commands: HashMap<String, Command<T>>,
+// Guards against aliases or scripts that run themselves forever
+const MAX_COMMANDS_PER_FRAME: usize = 1000;
+
+// Names this close to an unknown command are suggested instead
+const MAX_SUGGESTION_DISTANCE: usize = 2;
+
+/// Which world a command runs in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CommandTarget {
+	Client,
+	Server,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum ArgKind {
+	Required,
+	Optional,
+	Rest,
+}
+
+type WorldFunc = Box<dyn Fn(&Args<'_>, &mut World) -> anyhow::Result<()>>;
+type BuiltinFunc = fn(&mut CommandList, &Args<'_>) -> anyhow::Result<()>;
+
+enum Handler {
+	Builtin(BuiltinFunc),
+	World(CommandTarget, WorldFunc),
+}
+
+pub struct Command {
+	name: &'static str,
+	help: &'static str,
+	args: Vec<(&'static str, ArgKind)>,
+	handler: Handler,
+}
+
+impl Command {
+	pub fn new<F>(name: &'static str, help: &'static str, target: CommandTarget, func: F) -> Command
+	where
+		F: Fn(&Args<'_>, &mut World) -> anyhow::Result<()> + 'static,
+	{
+		Command {
+			name,
+			help,
+			args: Vec::new(),
+			handler: Handler::World(target, Box::new(func)),
+		}
+	}
+
+	fn builtin(name: &'static str, help: &'static str, func: BuiltinFunc) -> Command {
+		Command {
+			name,
+			help,
+			args: Vec::new(),
+			handler: Handler::Builtin(func),
+		}
+	}
+
+	pub fn with_arg(self, name: &'static str) -> Command {
+		assert!(self.args.iter().all(|(_, kind)| *kind == ArgKind::Required));
+		self.with(name, ArgKind::Required)
+	}
+
+	pub fn with_optional_arg(self, name: &'static str) -> Command {
+		assert!(self.args.iter().all(|(_, kind)| *kind != ArgKind::Rest));
+		self.with(name, ArgKind::Optional)
+	}
+
+	/// Takes any number of arguments, including none.
+	pub fn with_rest_args(self, name: &'static str) -> Command {
+		assert!(self.args.iter().all(|(_, kind)| *kind != ArgKind::Rest));
+		self.with(name, ArgKind::Rest)
+	}
+
+	fn with(mut self, name: &'static str, kind: ArgKind) -> Command {
+		self.args.push((name, kind));
+		self
+	}
+
+	pub fn usage(&self) -> String {
+		let mut usage = self.name.to_owned();
+
+		for (name, kind) in self.args.iter() {
+			match kind {
+				ArgKind::Required => usage += &format!(" {}", name),
+				ArgKind::Optional => usage += &format!(" [{}]", name),
+				ArgKind::Rest => usage += &format!(" [{}...]", name),
+			}
+		}
+
+		usage
+	}
+
+	fn accepts(&self, count: usize) -> bool {
+		let required = self
+			.args
+			.iter()
+			.filter(|(_, kind)| *kind == ArgKind::Required)
+			.count();
+		let rest = self.args.iter().any(|(_, kind)| *kind == ArgKind::Rest);
+
+		count >= required && (rest || count <= self.args.len())
+	}
+}
+
+/// The arguments a command was called with, not including its name.
+pub struct Args<'a> {
+	command: &'a Command,
+	args: &'a [String],
+}
+
+impl<'a> Args<'a> {
+	/// Parses an argument, which must be present.
+	pub fn get<T: FromStr>(&self, index: usize) -> anyhow::Result<T>
+	where
+		T::Err: Into<anyhow::Error>,
+	{
+		match self.get_optional(index)? {
+			Some(value) => Ok(value),
+			None => bail!("Usage: {}", self.command.usage()),
+		}
+	}
+
+	/// Parses an argument, if it was given.
+	pub fn get_optional<T: FromStr>(&self, index: usize) -> anyhow::Result<Option<T>>
+	where
+		T::Err: Into<anyhow::Error>,
+	{
+		let value = match self.args.get(index) {
+			Some(value) => value,
+			None => return Ok(None),
+		};
+		let name = self
+			.command
+			.args
+			.get(index)
+			.or_else(|| self.command.args.last())
+			.map_or("argument", |(name, _)| name);
+
+		value
+			.parse()
+			.map(Some)
+			.map_err(Into::into)
+			.context(format!("Invalid {}: {}", name, value))
+	}
+
+	/// The arguments from `index` onwards.
+	pub fn rest(&self, index: usize) -> &'a [String] {
+		self.args.get(index..).unwrap_or(&[])
+	}
+}
+
+/// Registered commands and aliases, and the buffer of commands still to run.
+pub struct CommandList {
+	aliases: HashMap<String, String>,
+	buffer: VecDeque<Vec<String>>,
+	commands: HashMap<&'static str, Rc<Command>>,
+	quit: bool,
+	wait: u32,
 }
 
-impl<T> CommandList<T> {
-	pub fn new() -> CommandList<T> {
-		CommandList {
+impl CommandList {
+	pub fn new() -> CommandList {
+		let mut list = CommandList {
+			aliases: HashMap::new(),
+			buffer: VecDeque::new(),
 			commands: HashMap::new(),
+			quit: false,
+			wait: 0,
+		};
+
+		list.add(
+			Command::builtin("alias", "Defines a command that runs other commands", alias)
+				.with_optional_arg("NAME")
+				.with_rest_args("COMMANDS"),
+		);
+		list.add(
+			Command::builtin("echo", "Prints text to the console", echo).with_rest_args("TEXT"),
+		);
+		list.add(Command::builtin("exec", "Runs the commands in a file", exec).with_arg("FILE"));
+		list.add(
+			Command::builtin("help", "Lists the commands, or describes one", help)
+				.with_optional_arg("COMMAND"),
+		);
+		list.add(Command::builtin("quit", "Quits the game", quit));
+		list.add(
+			Command::builtin(
+				"wait",
+				"Runs the remaining commands after this many frames, 1 if not given",
+				wait,
+			)
+			.with_optional_arg("FRAMES"),
+		);
+
+		list
+	}
+
+	/// Registers a command, panicking if the name is already taken.
+	pub fn add(&mut self, command: Command) {
+		if self.commands.contains_key(command.name) {
+			panic!("Duplicate command name: {}", command.name);
 		}
+
+		self.commands.insert(command.name, Rc::new(command));
+	}
+
+	/// Names of all commands and aliases, sorted.
+	pub fn names(&self) -> Vec<&str> {
+		let mut names: Vec<&str> = self
+			.commands
+			.keys()
+			.copied()
+			.chain(self.aliases.keys().map(String::as_str))
+			.collect();
+		names.sort_unstable();
+		names
+	}
+
+	/// Adds a line of commands to the end of the buffer.
+	pub fn push(&mut self, text: &str) -> anyhow::Result<()> {
+		let tokens = tokenize(text).context("Invalid syntax")?;
+		self.buffer
+			.extend(split_commands(&tokens).map(<[String]>::to_vec));
+		Ok(())
+	}
+
+	/// Adds the commands in a file to the end of the buffer.
+	pub fn push_file(&mut self, path: &Path) -> anyhow::Result<()> {
+		let text =
+			std::fs::read_to_string(path).context(format!("Could not read {}", path.display()))?;
+		self.push(&text)
 	}
 
-	pub fn add<F: Fn(&mut T, Vec<String>) + Sync + 'static>(
-		mut self,
-		name: &str,
-		func: F,
-	) -> CommandList<T> {
-		self.commands.insert(
-			name.to_owned(),
-			Command {
-				func: Box::new(func),
+	/// Adds a line of commands to the front of the buffer, so they run next.
+	fn push_front(&mut self, text: &str) -> anyhow::Result<()> {
+		let tokens = tokenize(text).context("Invalid syntax")?;
+
+		for command in split_commands(&tokens).rev() {
+			self.buffer.push_front(command.to_vec());
+		}
+
+		Ok(())
+	}
+
+	/// Runs the buffered commands until the buffer is empty or a `wait` is reached.
+	/// Returns whether to quit.
+	pub fn execute(
+		&mut self,
+		mut client_world: Option<&mut World>,
+		mut server_world: Option<&mut World>,
+	) -> bool {
+		if self.wait > 0 {
+			self.wait -= 1;
+
+			if self.wait > 0 {
+				return false;
+			}
+		}
+
+		let mut count = 0;
+
+		while let Some(args) = self.buffer.pop_front() {
+			count += 1;
+
+			if count > MAX_COMMANDS_PER_FRAME {
+				log::error!("Too many commands in one frame, is there an alias loop?");
+				self.buffer.clear();
+				break;
+			}
+
+			if let Err(err) = self.run(
+				&args,
+				client_world.as_deref_mut(),
+				server_world.as_deref_mut(),
+			) {
+				log::error!("{:#}", err);
+			}
+
+			if self.wait > 0 {
+				break;
+			}
+		}
+
+		std::mem::take(&mut self.quit)
+	}
+
+	/// Runs a line of commands right away, ahead of anything already buffered.
+	/// Commands after a `wait` are left in the buffer. Returns whether to quit.
+	pub fn execute_now(
+		&mut self,
+		text: &str,
+		client_world: Option<&mut World>,
+		server_world: Option<&mut World>,
+	) -> bool {
+		let buffer = std::mem::take(&mut self.buffer);
+		let wait = std::mem::take(&mut self.wait);
+
+		if let Err(err) = self.push(text) {
+			log::error!("{:#}", err);
+		}
+
+		let quit = self.execute(client_world, server_world);
+		let remaining = std::mem::replace(&mut self.buffer, buffer);
+		self.buffer.extend(remaining);
+		self.wait = self.wait.max(wait);
+		quit
+	}
+
+	fn run(
+		&mut self,
+		args: &[String],
+		client_world: Option<&mut World>,
+		server_world: Option<&mut World>,
+	) -> anyhow::Result<()> {
+		if let Some(text) = self.aliases.get(&args[0]) {
+			let text = text.clone();
+			return self.push_front(&text);
+		}
+
+		let command = match self.commands.get(args[0].as_str()) {
+			Some(command) => command.clone(),
+			None => bail!("{}", self.unknown_command(&args[0])),
+		};
+
+		if !command.accepts(args.len() - 1) {
+			bail!("Usage: {}", command.usage());
+		}
+
+		let args = Args {
+			command: &command,
+			args: &args[1..],
+		};
+
+		match &command.handler {
+			Handler::Builtin(func) => func(self, &args),
+			Handler::World(CommandTarget::Client, func) => match client_world {
+				Some(world) => func(&args, world),
+				None => bail!("{} needs a local client", command.name),
 			},
-		);
+			Handler::World(CommandTarget::Server, func) => match server_world {
+				Some(world) => func(&args, world),
+				None => bail!("{} needs a local server", command.name),
+			},
+		}
+	}
 
-		self
+	/// Error message for an unknown command, suggesting similar names.
+	fn unknown_command(&self, name: &str) -> String {
+		let mut suggestions: Vec<&str> = self
+			.commands
+			.keys()
+			.copied()
+			.chain(self.aliases.keys().map(String::as_str))
+			.filter(|candidate| {
+				candidate.starts_with(name)
+					|| edit_distance(candidate, name) <= MAX_SUGGESTION_DISTANCE
+			})
+			.collect();
+		suggestions.sort();
+
+		if suggestions.is_empty() {
+			format!("Unknown command: {}", name)
+		} else {
+			format!(
+				"Unknown command: {}, did you mean {}?",
+				name,
+				suggestions.join(", ")
+			)
+		}
 	}
+}
+
+fn alias(list: &mut CommandList, args: &Args<'_>) -> anyhow::Result<()> {
+	let name: String = match args.get_optional(0)? {
+		Some(name) => name,
+		None => {
+			let mut aliases: Vec<_> = list.aliases.iter().collect();
+			aliases.sort();
 
-	/*pub fn keys(&self) -> Vec<&String> {
-		self.commands.keys().collect::<Vec<_>>()
-	}*/
+			for (name, text) in aliases {
+				log::info!("{}: {}", name, text);
+			}
 
-	pub fn execute(&self, args: Vec<String>, system: &mut T) {
-		match self.commands.get(&args[0]) {
-			Some(val) => val.call(system, args),
-			None => debug!("Received invalid command: {}", args[0]),
+			return Ok(());
 		}
+	};
+
+	if args.rest(1).is_empty() {
+		match list.aliases.get(&name) {
+			Some(text) => log::info!("{}: {}", name, text),
+			None => bail!("No alias named {}", name),
+		}
+
+		return Ok(());
 	}
 
-	pub fn print_commands(&self) {
-		let mut names = self.commands.keys().collect::<Vec<&String>>();
-		names.sort();
+	if list.commands.contains_key(name.as_str()) {
+		bail!("{} is a command, it can't be an alias", name);
+	}
+
+	let text = args.rest(1).join(" ");
+
+	if text.is_empty() {
+		list.aliases.remove(&name);
+	} else {
+		tokenize(&text).context("Invalid syntax")?;
+		list.aliases.insert(name, text);
+	}
+
+	Ok(())
+}
 
-		for name in names {
-			info!("{}", name);
+fn echo(_list: &mut CommandList, args: &Args<'_>) -> anyhow::Result<()> {
+	log::info!("{}", args.rest(0).join(" "));
+	Ok(())
+}
+
+fn exec(list: &mut CommandList, args: &Args<'_>) -> anyhow::Result<()> {
+	let path: String = args.get(0)?;
+	let text = std::fs::read_to_string(&path).context(format!("Could not read {}", path))?;
+	list.push_front(&text)
+}
+
+fn help(list: &mut CommandList, args: &Args<'_>) -> anyhow::Result<()> {
+	let name: String = match args.get_optional(0)? {
+		Some(name) => name,
+		None => {
+			let mut commands: Vec<_> = list.commands.values().collect();
+			commands.sort_by_key(|command| command.name);
+
+			for command in commands {
+				log::info!("{}: {}", command.usage(), command.help);
+			}
+
+			return Ok(());
 		}
+	};
+
+	if let Some(text) = list.aliases.get(&name) {
+		log::info!("{} is an alias for: {}", name, text);
+	} else if let Some(command) = list.commands.get(name.as_str()) {
+		log::info!("Usage: {}", command.usage());
+		log::info!("{}", command.help);
+	} else {
+		bail!("{}", list.unknown_command(&name));
 	}
+
+	Ok(())
 }
 
-struct Command<T> {
-	func: Box<dyn Fn(&mut T, Vec<String>) + Sync + 'static>,
+fn quit(list: &mut CommandList, _args: &Args<'_>) -> anyhow::Result<()> {
+	list.quit = true;
+	Ok(())
 }
 
-impl<T> Command<T> {
-	pub fn call(&self, system: &mut T, args: Vec<String>) {
-		(self.func)(system, args);
+fn wait(list: &mut CommandList, args: &Args<'_>) -> anyhow::Result<()> {
+	list.wait = args.get_optional(0)?.unwrap_or(1);
+	Ok(())
+}
+
+/// Splits tokens into separate commands at the semicolons.
+fn split_commands(tokens: &[String]) -> impl DoubleEndedIterator<Item = &[String]> {
+	tokens
+		.split(|tok| tok == ";")
+		.filter(|command| !command.is_empty())
+}
+
+/// Levenshtein distance between two strings.
+fn edit_distance(a: &str, b: &str) -> usize {
+	let b: Vec<char> = b.chars().collect();
+	let mut row: Vec<usize> = (0..=b.len()).collect();
+
+	for (i, ca) in a.chars().enumerate() {
+		let mut diagonal = row[0];
+		row[0] = i + 1;
+
+		for (j, cb) in b.iter().enumerate() {
+			let above = row[j + 1];
+			row[j + 1] = if ca == *cb {
+				diagonal
+			} else {
+				1 + diagonal.min(above).min(row[j])
+			};
+			diagonal = above;
+		}
 	}
-}*/
+
+	row[b.len()]
+}
 
 pub fn tokenize(mut text: &str) -> anyhow::Result<Vec<String>> {
 	lazy_static! {
 		// Whitespace, except newlines
 		static ref RE_SPACE    : Regex = Regex::new(r#"^[^\S\n]+"#).unwrap();
 
-		// C identifier or number literal
-		static ref RE_UNQUOTED : Regex = Regex::new(r#"^[+-]?[.0-9A-Za-z_]+"#).unwrap();
+		// Identifier or number literal, names can have dashes after the first character
+		static ref RE_UNQUOTED : Regex = Regex::new(r#"^[+-]?[.0-9A-Za-z_][-.0-9A-Za-z_]*"#).unwrap();
 
 		// Quoted string, with escapes
 		static ref RE_QUOTED   : Regex = Regex::new(r#"^"(?:[^"\\]*(?:\\.)?)*""#).unwrap();
@@ -162,7 +602,7 @@ pub fn tokenize(mut text: &str) -> anyhow::Result<Vec<String>> {
 /*pub fn quote_escape(text: &str) -> Cow<'_, str> {
 	lazy_static! {
 		// As above, but anchored to end of string as well
-		static ref RE_UNQUOTED : Regex = Regex::new(r#"^[+-]?[.0-9A-Za-z_]+$"#).unwrap();
+		static ref RE_UNQUOTED : Regex = Regex::new(r#"^[+-]?[.0-9A-Za-z_][-.0-9A-Za-z_]*$"#).unwrap();
 
 		// Characters that need escaping
 		static ref RE_ESCAPE   : Regex = Regex::new(r#"[\\"]"#).unwrap();
@@ -175,3 +615,28 @@ pub fn tokenize(mut text: &str) -> anyhow::Result<Vec<String>> {
 	}
 }
 */
+
+#[cfg(test)]
+mod tests {
+	use super::tokenize;
+
+	#[test]
+	fn tokenize_dashes() {
+		assert_eq!(
+			tokenize("set log-level DEBUG; bind w +forward; set fov-aspect -1.2").unwrap(),
+			[
+				"set",
+				"log-level",
+				"DEBUG",
+				";",
+				"bind",
+				"w",
+				"+forward",
+				";",
+				"set",
+				"fov-aspect",
+				"-1.2"
+			]
+		);
+	}
+}
@@ -1,9 +1,13 @@
 use crate::assets::{Asset, DataSource};
 use specs::{Component, Entity, World, WorldExt};
-use std::{any::TypeId, collections::HashMap};
+use std::{
+	any::{Any, TypeId},
+	collections::HashMap,
+};
 
 pub trait DynComponent: Send + Sync {
 	fn add_to_entity(&self, entity: Entity, world: &World) -> Result<(), specs::error::Error>;
+	fn as_any(&self) -> &dyn Any;
 }
 
 impl<T: Component + Clone + Send + Sync> DynComponent for T {
@@ -11,6 +15,10 @@ impl<T: Component + Clone + Send + Sync> DynComponent for T {
 		world.write_component().insert(entity, self.clone())?;
 		Ok(())
 	}
+
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
 }
 
 pub struct EntityTemplate {
@@ -34,6 +42,12 @@ impl EntityTemplate {
 		self
 	}
 
+	pub fn get<T: Component>(&self) -> Option<&T> {
+		self.components
+			.get(&TypeId::of::<T>())
+			.and_then(|component| component.as_any().downcast_ref())
+	}
+
 	pub fn add_to_entity(&self, entity: Entity, world: &World) -> Result<(), specs::error::Error> {
 		for dyn_component in self.components.values() {
 			dyn_component.add_to_entity(entity, world)?;
@@ -0,0 +1,99 @@
+use crate::{
+	configvars::ConfigVariables,
+	doom::input::{Action, Axis},
+	input::Bindings,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::{
+	collections::BTreeMap,
+	fs::File,
+	io::{BufReader, BufWriter},
+	path::PathBuf,
+};
+
+const CONFIG_FILE: &str = "config.json";
+const CONFIG_BACKUP_FILE: &str = "config.json.bak";
+const AUTOEXEC_FILE: &str = "autoexec.cfg";
+
+/// Settings that are kept between runs, in the user's config directory.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct Config {
+	pub bindings: Option<Bindings<Action, Axis>>,
+	#[serde(default)]
+	pub variables: BTreeMap<String, String>,
+	pub last_iwad: Option<PathBuf>,
+}
+
+impl Config {
+	/// Loads the config file, or returns the defaults if there isn't one.
+	/// A file that can't be read is moved out of the way, so that saving doesn't overwrite it.
+	pub fn load() -> anyhow::Result<Config> {
+		let path = match base_directories()?.find_config_file(CONFIG_FILE) {
+			Some(path) => path,
+			None => return Ok(Config::default()),
+		};
+
+		log::debug!("Loading config from {}", path.display());
+		let file = File::open(&path).context(format!("Couldn't open {}", path.display()))?;
+
+		match serde_json::from_reader(BufReader::new(file)) {
+			Ok(config) => Ok(config),
+			Err(err) => {
+				let backup_path = path.with_file_name(CONFIG_BACKUP_FILE);
+				std::fs::rename(&path, &backup_path).context(format!(
+					"Couldn't read {}: {}, and couldn't move it to {}",
+					path.display(),
+					err,
+					backup_path.display()
+				))?;
+				log::warn!(
+					"Couldn't read {}: {}, moved it to {} and using the defaults",
+					path.display(),
+					err,
+					backup_path.display()
+				);
+				Ok(Config::default())
+			}
+		}
+	}
+
+	pub fn save(&self) -> anyhow::Result<()> {
+		let path = base_directories()?
+			.place_config_file(CONFIG_FILE)
+			.context("Couldn't create config directory")?;
+
+		log::debug!("Saving config to {}", path.display());
+		let file = File::create(&path).context(format!("Couldn't create {}", path.display()))?;
+		serde_json::to_writer_pretty(BufWriter::new(file), self)
+			.context(format!("Couldn't write {}", path.display()))
+	}
+
+	/// Sets the config variables to the stored values.
+	pub fn apply_variables(&self, config_variables: &mut ConfigVariables) {
+		for (name, value) in self.variables.iter() {
+			if let Err(err) = config_variables.set_string(name, value) {
+				log::warn!("{:#}", err);
+			}
+		}
+	}
+
+	/// Stores the values of the config variables that are archived.
+	pub fn store_variables(&mut self, config_variables: &ConfigVariables) {
+		self.variables = config_variables
+			.variables()
+			.into_iter()
+			.filter(|variable| variable.archive())
+			.map(|variable| (variable.name().to_owned(), variable.to_string()))
+			.collect();
+	}
+}
+
+/// Path of the script that is run at startup, if it exists.
+pub fn autoexec_path() -> anyhow::Result<Option<PathBuf>> {
+	Ok(base_directories()?.find_config_file(AUTOEXEC_FILE))
+}
+
+fn base_directories() -> anyhow::Result<xdg::BaseDirectories> {
+	xdg::BaseDirectories::with_prefix("ferret").context("Couldn't find the config directory")
+}
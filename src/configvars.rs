@@ -1,124 +1,227 @@
-use std::{
-	cell::{Cell, /*Ref, */ RefCell},
-	fmt,
-	str::FromStr,
-};
+use anyhow::{anyhow, bail};
+use shrev::{EventChannel, EventIterator, ReaderId};
+use std::{any::Any, collections::HashMap, fmt, str::FromStr};
+
+type Validator<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
 
 pub struct ConfigVariable<T> {
 	name: &'static str,
-	value: RefCell<T>,
-	validator: Option<Box<dyn Fn(&T) -> bool + Sync>>,
-	modified: Cell<bool>,
+	help: &'static str,
+	archive: bool,
+	value: T,
+	default: T,
+	validator: Option<Validator<T>>,
 }
 
-impl<T: PartialEq> ConfigVariable<T> {
-	/*	pub fn new(
-			name: &'static str,
-			default: T,
-			mut validator: Option<Box<dyn Fn(&T) -> bool + Sync>>,
-		) -> ConfigVariable<T> {
-			assert!(validator.is_none() || validator.as_mut().unwrap()(&default));
-
-			ConfigVariable {
-				name: name,
-				value: RefCell::new(default),
-				validator,
-				modified: Cell::new(false),
-			}
+impl<T: Clone + PartialEq + fmt::Display> ConfigVariable<T> {
+	pub fn new(name: &'static str, help: &'static str, default: T) -> ConfigVariable<T> {
+		ConfigVariable {
+			name,
+			help,
+			archive: false,
+			value: default.clone(),
+			default,
+			validator: None,
 		}
+	}
 
-		pub fn get(&self) -> Ref<T> {
-			self.value.borrow()
+	/// Saves the variable in the config file.
+	pub fn with_archive(mut self) -> ConfigVariable<T> {
+		self.archive = true;
+		self
+	}
+
+	pub fn with_validator(
+		mut self,
+		validator: impl Fn(&T) -> bool + Send + Sync + 'static,
+	) -> ConfigVariable<T> {
+		assert!(validator(&self.default));
+		self.validator = Some(Box::new(validator));
+		self
+	}
+
+	/// Returns whether the value changed.
+	fn set(&mut self, value: T) -> anyhow::Result<bool> {
+		if let Some(validator) = &self.validator {
+			if !validator(&value) {
+				bail!("Invalid value for {}: {}", self.name, value);
+			}
 		}
-	*/
-	fn set(&self, newvalue: T) {
-		if *self.value.borrow() != newvalue
-			&& (self.validator.is_none() || self.validator.as_ref().unwrap()(&newvalue))
-		{
-			self.value.replace(newvalue);
-			self.modified.set(true);
+
+		if self.value == value {
+			Ok(false)
+		} else {
+			self.value = value;
+			Ok(true)
 		}
 	}
 }
 
+impl<T> ConfigVariable<T> {
+	pub fn get(&self) -> &T {
+		&self.value
+	}
+}
+
 impl<T: fmt::Display> fmt::Display for ConfigVariable<T> {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-		self.value.borrow().fmt(f)
+		self.value.fmt(f)
 	}
 }
 
-pub trait ConfigVariableT: fmt::Display {
+pub trait ConfigVariableT: fmt::Display + Send + Sync {
 	fn name(&self) -> &'static str;
-	fn set_string(&self, value: &str);
+	fn help(&self) -> &'static str;
+	fn archive(&self) -> bool;
+	fn default_string(&self) -> String;
+	fn set_string(&mut self, value: &str) -> anyhow::Result<bool>;
+	fn reset(&mut self) -> bool;
+	fn as_any(&self) -> &dyn Any;
+	fn as_any_mut(&mut self) -> &mut dyn Any;
 }
 
-impl<T: PartialEq + FromStr + fmt::Display> ConfigVariableT for ConfigVariable<T>
+impl<T> ConfigVariableT for ConfigVariable<T>
 where
-	<T as FromStr>::Err: std::fmt::Debug,
+	T: Clone + PartialEq + FromStr + fmt::Display + Send + Sync + 'static,
+	<T as FromStr>::Err: fmt::Display,
 {
 	fn name(&self) -> &'static str {
 		self.name
 	}
 
-	fn set_string(&self, value: &str) {
-		self.set(value.parse().unwrap())
+	fn help(&self) -> &'static str {
+		self.help
+	}
+
+	fn archive(&self) -> bool {
+		self.archive
+	}
+
+	fn default_string(&self) -> String {
+		self.default.to_string()
+	}
+
+	fn set_string(&mut self, value: &str) -> anyhow::Result<bool> {
+		let value = value
+			.parse()
+			.map_err(|err| anyhow!("Invalid value for {}: {}", self.name, err))?;
+		self.set(value)
+	}
+
+	fn reset(&mut self) -> bool {
+		let changed = self.value != self.default;
+		self.value = self.default.clone();
+		changed
+	}
+
+	fn as_any(&self) -> &dyn Any {
+		self
+	}
+
+	fn as_any_mut(&mut self) -> &mut dyn Any {
+		self
 	}
 }
 
-/*pub struct ConfigVariables {
-	variables: HashMap<String, ConfigVariable>,
+/// All config variables by name. Every change is sent as an event with the name of the
+/// variable, so that whoever uses it can react.
+#[derive(Default)]
+pub struct ConfigVariables {
+	changes: EventChannel<&'static str>,
+	variables: HashMap<&'static str, Box<dyn ConfigVariableT>>,
 }
 
 impl ConfigVariables {
-	pub fn new<I>(iter: I) -> ConfigVariables
-	where I: IntoIterator<Item = ConfigVariable> {
-		let mut variables = HashMap::new();
-
-		for item in iter.into_iter() {
-			if let Some(item) = variables.insert(item.name.clone(), item) {
-				panic!("Duplicate variable name: {}", item.name);
-			}
+	/// Registers a variable, panicking if the name is already taken.
+	pub fn add<T>(&mut self, variable: ConfigVariable<T>)
+	where
+		T: Clone + PartialEq + FromStr + fmt::Display + Send + Sync + 'static,
+		<T as FromStr>::Err: fmt::Display,
+	{
+		if self.variables.contains_key(variable.name) {
+			panic!("Duplicate config variable name: {}", variable.name);
 		}
 
-		ConfigVariables {
-			variables,
-		}
+		self.variables.insert(variable.name, Box::new(variable));
 	}
 
-	pub fn get<T: Clone>(&self, key: &str) -> Option<&T>
-	where ConfigVariable: ValueAccess<T> {
-		self.variables.get(key).map(ValueAccess::get)
+	/// Returns the value of a variable, panicking if it doesn't exist or has another type.
+	pub fn get<T: 'static>(&self, name: &str) -> &T {
+		self.variables
+			.get(name)
+			.and_then(|variable| variable.as_any().downcast_ref::<ConfigVariable<T>>())
+			.map(ConfigVariable::get)
+			.unwrap_or_else(|| panic!("No config variable {} of the requested type", name))
 	}
 
-	fn set<T: Clone>(&mut self, key: &str, newvalue: T)
-	where ConfigVariable: ValueAccess<T> {
-		match self.variables.get_mut(key) {
-			Some(variable) => variable.set(newvalue),
-			None => (),
-		}
+	pub fn variable(&self, name: &str) -> anyhow::Result<&dyn ConfigVariableT> {
+		self.variables
+			.get(name)
+			.map(Box::as_ref)
+			.ok_or(anyhow!("Unknown config variable: {}", name))
 	}
 
-	fn set_string(&mut self, key: &str, string: &str) -> anyhow::Result<()> {
-		match self.variables.get_mut(key) {
-			Some(variable) => variable.set_string(string),
-			None => Ok(()),
+	/// All variables, sorted by name.
+	pub fn variables(&self) -> Vec<&dyn ConfigVariableT> {
+		let mut variables: Vec<_> = self.variables.values().map(Box::as_ref).collect();
+		variables.sort_by_key(|variable| variable.name());
+		variables
+	}
+
+	pub fn set_string(&mut self, name: &str, value: &str) -> anyhow::Result<()> {
+		let variable = self.variable_mut(name)?;
+		let changed = variable.set_string(value)?;
+		let name = variable.name();
+
+		if changed {
+			self.changes.single_write(name);
 		}
+
+		Ok(())
 	}
-}*/
 
-/*
-impl<T: FromStr + ToString> ConsoleVariableT for ConsoleVariable<T> {
-	fn print_value_str(&self) {
-		info!("\"{}\" = \"{}\"", self.name, self.value.borrow().to_string());
-		//if let Some(var) = self.upgrade() {
+	/// Flips a boolean variable.
+	pub fn toggle(&mut self, name: &str) -> anyhow::Result<()> {
+		let variable = match self
+			.variable_mut(name)?
+			.as_any_mut()
+			.downcast_mut::<ConfigVariable<bool>>()
+		{
+			Some(variable) => variable,
+			None => bail!("{} is not a boolean variable", name),
+		};
+
+		let value = !variable.value;
+		variable.set(value)?;
+		let name = variable.name;
+		self.changes.single_write(name);
+		Ok(())
 	}
 
-	fn set_value_str(&self, newvalue: &str) {
-		if let Ok(value) = newvalue.parse::<T>() {
-			self.set_value(value);
+	pub fn reset(&mut self, name: &str) -> anyhow::Result<()> {
+		let variable = self.variable_mut(name)?;
+		let changed = variable.reset();
+		let name = variable.name();
+
+		if changed {
+			self.changes.single_write(name);
 		}
-		//if let Some(var) = self.upgrade() {
-		// TODO: print message if parse fails
+
+		Ok(())
+	}
+
+	pub fn register_reader(&mut self) -> ReaderId<&'static str> {
+		self.changes.register_reader()
+	}
+
+	/// Names of the variables that changed since the reader last looked.
+	pub fn changes(&self, reader: &mut ReaderId<&'static str>) -> EventIterator<'_, &'static str> {
+		self.changes.read(reader)
+	}
+
+	fn variable_mut(&mut self, name: &str) -> anyhow::Result<&mut Box<dyn ConfigVariableT>> {
+		self.variables
+			.get_mut(name)
+			.ok_or(anyhow!("Unknown config variable: {}", name))
 	}
 }
-*/
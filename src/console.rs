@@ -0,0 +1,280 @@
+use crossbeam_channel::Receiver;
+use log::Level;
+use std::collections::VecDeque;
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+const MAX_LINES: usize = 1000;
+const MAX_HISTORY: usize = 100;
+const PAGE_LINES: usize = 10;
+
+/// The in-game console: scrollback of the log, and a line editor for commands.
+pub struct Console {
+	cursor: usize,
+	history: Vec<String>,
+	history_index: Option<usize>,
+	input: String,
+	lines: VecDeque<String>,
+	log_receiver: Receiver<(Level, String)>,
+	open: bool,
+	scroll: usize,
+	submitted: Vec<String>,
+}
+
+impl Console {
+	pub fn new(log_receiver: Receiver<(Level, String)>) -> Console {
+		Console {
+			cursor: 0,
+			history: Vec::new(),
+			history_index: None,
+			input: String::new(),
+			lines: VecDeque::new(),
+			log_receiver,
+			open: false,
+			scroll: 0,
+			submitted: Vec::new(),
+		}
+	}
+
+	pub fn is_open(&self) -> bool {
+		self.open
+	}
+
+	/// Lines of the scrollback, oldest first.
+	pub fn lines(&self) -> &VecDeque<String> {
+		&self.lines
+	}
+
+	pub fn input(&self) -> &str {
+		&self.input
+	}
+
+	/// Position of the cursor in the input line, in bytes.
+	pub fn cursor(&self) -> usize {
+		self.cursor
+	}
+
+	/// How many lines the scrollback is scrolled up from the bottom.
+	pub fn scroll(&self) -> usize {
+		self.scroll
+	}
+
+	/// Adds the messages that were logged since the last update to the scrollback.
+	pub fn update(&mut self) {
+		while let Ok((level, message)) = self.log_receiver.try_recv() {
+			let prefix = match level {
+				Level::Error => "ERROR: ",
+				Level::Warn => "WARNING: ",
+				Level::Info => "",
+				Level::Debug => "DEBUG: ",
+				Level::Trace => "TRACE: ",
+			};
+
+			for line in message.lines() {
+				self.print(format!("{}{}", prefix, line));
+			}
+		}
+	}
+
+	/// Lines that were entered since the last call, to be run as commands.
+	pub fn take_submitted(&mut self) -> Vec<String> {
+		std::mem::take(&mut self.submitted)
+	}
+
+	/// Handles an event, returns whether the console used it.
+	/// The completer is called on Tab with the command being typed, or `None` if the name of
+	/// the command itself is being typed, and returns the possible words.
+	pub fn process_event<F>(&mut self, event: &Event<()>, completer: F) -> bool
+	where
+		F: FnOnce(Option<&str>) -> Vec<String>,
+	{
+		let event = match event {
+			Event::WindowEvent { event, .. } => event,
+			_ => return false,
+		};
+
+		match *event {
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Grave),
+						..
+					},
+				..
+			} => {
+				self.open = !self.open;
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput {
+					state,
+					virtual_keycode,
+					..
+				},
+				..
+			} if self.open => {
+				if let (ElementState::Pressed, Some(key_code)) = (state, virtual_keycode) {
+					self.process_key(key_code, completer);
+				}
+
+				true
+			}
+			WindowEvent::ReceivedCharacter(c) if self.open => {
+				if !c.is_control() && c != '`' && c != '~' {
+					self.input.insert(self.cursor, c);
+					self.cursor += c.len_utf8();
+				}
+
+				true
+			}
+			_ => false,
+		}
+	}
+
+	fn process_key<F>(&mut self, key_code: VirtualKeyCode, completer: F)
+	where
+		F: FnOnce(Option<&str>) -> Vec<String>,
+	{
+		match key_code {
+			VirtualKeyCode::Escape => self.open = false,
+			VirtualKeyCode::Left => self.cursor = self.previous_char(),
+			VirtualKeyCode::Right => self.cursor = self.next_char(),
+			VirtualKeyCode::Home => self.cursor = 0,
+			VirtualKeyCode::End => self.cursor = self.input.len(),
+			VirtualKeyCode::Back => {
+				let start = self.previous_char();
+				self.input.replace_range(start..self.cursor, "");
+				self.cursor = start;
+			}
+			VirtualKeyCode::Delete => {
+				let end = self.next_char();
+				self.input.replace_range(self.cursor..end, "");
+			}
+			VirtualKeyCode::Up => {
+				let index = match self.history_index {
+					Some(0) => return,
+					Some(index) => index - 1,
+					None if self.history.is_empty() => return,
+					None => self.history.len() - 1,
+				};
+
+				self.history_index = Some(index);
+				self.set_input(self.history[index].clone());
+			}
+			VirtualKeyCode::Down => {
+				if let Some(index) = self.history_index {
+					if index + 1 < self.history.len() {
+						self.history_index = Some(index + 1);
+						self.set_input(self.history[index + 1].clone());
+					} else {
+						self.history_index = None;
+						self.set_input(String::new());
+					}
+				}
+			}
+			VirtualKeyCode::PageUp => {
+				self.scroll = (self.scroll + PAGE_LINES).min(self.lines.len().saturating_sub(1));
+			}
+			VirtualKeyCode::PageDown => self.scroll = self.scroll.saturating_sub(PAGE_LINES),
+			VirtualKeyCode::Tab => self.complete(completer),
+			VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+				let line = std::mem::take(&mut self.input);
+				self.cursor = 0;
+				self.history_index = None;
+				self.scroll = 0;
+				self.print(format!("] {}", line));
+
+				if line.trim().is_empty() {
+					return;
+				}
+
+				if self.history.last() != Some(&line) {
+					if self.history.len() >= MAX_HISTORY {
+						self.history.remove(0);
+					}
+
+					self.history.push(line.clone());
+				}
+
+				self.submitted.push(line);
+			}
+			_ => {}
+		}
+	}
+
+	/// Completes the word before the cursor as far as it is unambiguous,
+	/// and lists the candidates if there are several.
+	fn complete<F>(&mut self, completer: F)
+	where
+		F: FnOnce(Option<&str>) -> Vec<String>,
+	{
+		let before = &self.input[..self.cursor];
+		let command_start = before.rfind(';').map_or(0, |i| i + 1);
+		let word_start = before
+			.rfind(|c: char| c.is_whitespace() || c == ';')
+			.map_or(0, |i| i + 1);
+		let word = &before[word_start..];
+		let command = before[command_start..word_start].split_whitespace().next();
+
+		let mut matches: Vec<String> = completer(command)
+			.into_iter()
+			.filter(|candidate| candidate.starts_with(word))
+			.collect();
+		matches.sort();
+		matches.dedup();
+
+		let completion = match matches.as_slice() {
+			[] => return,
+			[single] => format!("{} ", single),
+			[first, rest @ ..] => {
+				let prefix_len = rest.iter().fold(first.len(), |len, candidate| {
+					first
+						.char_indices()
+						.zip(candidate.chars())
+						.take_while(|((i, a), b)| *i < len && a == b)
+						.map(|((i, a), _)| i + a.len_utf8())
+						.last()
+						.unwrap_or(0)
+				});
+				self.print(matches.join("  "));
+				matches[0][..prefix_len].to_owned()
+			}
+		};
+
+		self.input
+			.replace_range(word_start..self.cursor, &completion);
+		self.cursor = word_start + completion.len();
+	}
+
+	fn print(&mut self, line: String) {
+		if self.lines.len() >= MAX_LINES {
+			self.lines.pop_front();
+		}
+
+		self.lines.push_back(line);
+
+		// Keep the view where it was if scrolled up
+		if self.scroll > 0 {
+			self.scroll = (self.scroll + 1).min(self.lines.len() - 1);
+		}
+	}
+
+	fn set_input(&mut self, input: String) {
+		self.input = input;
+		self.cursor = self.input.len();
+	}
+
+	fn previous_char(&self) -> usize {
+		self.input[..self.cursor]
+			.chars()
+			.next_back()
+			.map_or(self.cursor, |c| self.cursor - c.len_utf8())
+	}
+
+	fn next_char(&self) -> usize {
+		self.input[self.cursor..]
+			.chars()
+			.next()
+			.map_or(self.cursor, |c| self.cursor + c.len_utf8())
+	}
+}
@@ -1,61 +1,134 @@
 use crate::{
-	assets::AssetStorage,
+	assets::{AssetHandle, AssetStorage},
+	audio::Sound,
+	component::EntityTemplate,
+	configvars::ConfigVariables,
 	doom::{
 		components::{Transform, Velocity},
-		data::{FORWARD_ACCEL, STRAFE_ACCEL},
+		data::{MobjTypes, FORWARD_ACCEL, SLOW_TURN_TICS, STRAFE_ACCEL, TURN_SPEED},
 		door::{DoorSwitchUse, DoorUse},
 		input::{Action, Axis, UserCommand},
 		map::{Map, MapDynamic},
-		physics::{BoxCollider, EntityTracer, SolidMask},
+		physics::{BoxCollider, EntityTracer, PhysicsSystem, SolidMask},
+		replication::SnapshotReceiver,
+		sound::{SoundChannel, SoundOrigin},
+		wad::WadLoader,
 	},
 	geometry::{Line2, AABB3},
 	input::{Bindings, InputState},
+	net::{Addr, SequencedChannel, Socket},
+	protocol::{ClientMessage, Packet, ServerMessage},
 	quadtree::Quadtree,
 };
 use nalgebra::{Vector2, Vector3};
 use shrev::EventChannel;
 use specs::{
 	Component, DenseVecStorage, Entities, Entity, Join, ReadExpect, ReadStorage, RunNow, World,
-	WriteExpect, WriteStorage,
+	WorldExt, WriteExpect, WriteStorage,
 };
 use specs_derive::Component;
-use std::time::Duration;
+use std::{
+	collections::VecDeque,
+	convert::TryFrom,
+	sync::Arc,
+	time::{Duration, Instant},
+};
 
 #[derive(Default)]
 pub struct Client {
 	pub entity: Option<Entity>,
+	pub command: UserCommand,
+	pub scores: Vec<(u8, i32)>,
+}
+
+#[derive(Clone, Component, Debug, Default)]
+pub struct User {
 	pub command: UserCommand,
 	pub previous_command: UserCommand,
 }
 
+// The original turned by mousex * 8 << 16, or sidestepped by mousex * 2 out of 40 for full
+// speed. The yaw axis is in millionths of an angle.
+const YAW_TO_STRAFE: f32 = 2.0 / 40.0 / (8 << 16) as f32 * 1e6;
+
 #[derive(Default)]
-pub struct PlayerCommandSystem;
+pub struct PlayerCommandSystem {
+	turn_held: u32,
+}
 
 impl<'a> RunNow<'a> for PlayerCommandSystem {
 	fn setup(&mut self, _world: &mut World) {}
 
 	fn run_now(&mut self, world: &'a World) {
-		let (bindings, mut client, input_state) = world.system_data::<(
-			ReadExpect<Bindings<Action, Axis>>,
-			WriteExpect<Client>,
-			ReadExpect<InputState>,
-		)>();
+		let (bindings, mut client, config_variables, input_state, transform_component) = world
+			.system_data::<(
+				ReadExpect<Bindings<Action, Axis>>,
+				WriteExpect<Client>,
+				ReadExpect<ConfigVariables>,
+				ReadExpect<InputState>,
+				ReadStorage<Transform>,
+			)>();
+
+		let running = *config_variables.get::<bool>("always-run")
+			!= bindings.action_is_down(&Action::Run, &input_state);
 
 		let mut command = UserCommand {
 			action_attack: bindings.action_is_down(&Action::Attack, &input_state),
 			action_use: bindings.action_is_down(&Action::Use, &input_state),
 			axis_forward: bindings.axis_value(&Axis::Forward, &input_state) as f32,
-			axis_pitch: bindings.axis_value(&Axis::Pitch, &input_state) as f32,
+			axis_pitch: bindings.analog_axis_value(&Axis::Pitch, &input_state) as f32,
 			axis_strafe: bindings.axis_value(&Axis::Strafe, &input_state) as f32,
-			axis_yaw: bindings.axis_value(&Axis::Yaw, &input_state) as f32,
+			axis_yaw: bindings.analog_axis_value(&Axis::Yaw, &input_state) as f32,
+		};
+
+		let mut yaw_buttons = bindings.button_axis_value(&Axis::Yaw, &input_state) as f32;
+		let pitch_buttons = bindings.button_axis_value(&Axis::Pitch, &input_state) as f32;
+		let mut analog_strafe = 0.0;
+
+		if bindings.action_is_down(&Action::Strafe, &input_state) {
+			// Sidestep instead of turning
+			command.axis_strafe -= yaw_buttons;
+			analog_strafe = -command.axis_yaw * YAW_TO_STRAFE;
+			command.axis_yaw = 0.0;
+			yaw_buttons = 0.0;
+		}
+
+		// Turning with buttons starts slow, to allow small adjustments
+		if yaw_buttons != 0.0 || pitch_buttons != 0.0 {
+			self.turn_held += 1;
+		} else {
+			self.turn_held = 0;
+		}
+
+		let turn_speed = if self.turn_held < SLOW_TURN_TICS {
+			TURN_SPEED[2]
+		} else if running {
+			TURN_SPEED[1]
+		} else {
+			TURN_SPEED[0]
 		};
 
-		if bindings.action_is_down(&Action::Walk, &input_state) {
+		// Axis values are in millionths of an angle
+		command.axis_yaw += yaw_buttons * turn_speed as f32 / 1e6;
+		command.axis_pitch += pitch_buttons * turn_speed as f32 / 1e6;
+
+		if !running {
 			command.axis_forward *= 0.5;
 			command.axis_strafe *= 0.6;
 		}
 
-		client.previous_command = client.command;
+		// Like the original, analog sidestepping doesn't depend on running
+		command.axis_strafe += analog_strafe;
+
+		if !*config_variables.get::<bool>("mouselook") {
+			// Turn the view back to level, like the original which can't look up or down
+			let pitch = client
+				.entity
+				.and_then(|entity| transform_component.get(entity))
+				.map_or(0, |transform| transform.rotation[1].0);
+			command.axis_pitch = -(pitch as f32) / 1e6;
+		}
+
 		client.command = command;
 	}
 }
@@ -69,42 +142,42 @@ impl<'a> RunNow<'a> for PlayerMoveSystem {
 	fn run_now(&mut self, world: &'a World) {
 		let (
 			entities,
-			client,
 			delta,
 			map_storage,
 			quadtree,
 			box_collider_component,
 			map_dynamic_component,
+			user_component,
 			mut transform_component,
 			mut velocity_component,
 		) = world.system_data::<(
 			Entities,
-			ReadExpect<Client>,
 			ReadExpect<Duration>,
 			ReadExpect<AssetStorage<Map>>,
 			ReadExpect<Quadtree>,
 			ReadStorage<BoxCollider>,
 			ReadStorage<MapDynamic>,
+			ReadStorage<User>,
 			WriteStorage<Transform>,
 			WriteStorage<Velocity>,
 		)>();
 
-		if let Some(entity) = client.entity {
+		for (entity, user) in (&entities, &user_component).join() {
 			// Apply rotation
 			{
 				let transform = transform_component.get_mut(entity).unwrap();
 
-				transform.rotation[1] += (client.command.axis_pitch * 1e6) as i32;
+				transform.rotation[1] += (user.command.axis_pitch * 1e6) as i32;
 				transform.rotation[1].0 =
 					num_traits::clamp(transform.rotation[1].0, -0x4000_0000, 0x4000_0000);
 
-				transform.rotation[2] -= (client.command.axis_yaw * 1e6) as i32;
+				transform.rotation[2] -= (user.command.axis_yaw * 1e6) as i32;
 			}
 
 			// Apply acceleration
 			{
-				if client.command.axis_forward == 0.0 && client.command.axis_strafe == 0.0 {
-					return;
+				if user.command.axis_forward == 0.0 && user.command.axis_strafe == 0.0 {
+					continue;
 				}
 
 				let velocity = velocity_component.get_mut(entity).unwrap();
@@ -133,12 +206,12 @@ impl<'a> RunNow<'a> for PlayerMoveSystem {
 
 				if trace.collision.is_none() {
 					// Player is not on ground
-					return;
+					continue;
 				}
 
 				let move_dir = Vector2::new(
-					client.command.axis_forward.max(-1.0).min(1.0) * FORWARD_ACCEL,
-					client.command.axis_strafe.max(-1.0).min(1.0) * STRAFE_ACCEL,
+					user.command.axis_forward.max(-1.0).min(1.0) * FORWARD_ACCEL,
+					user.command.axis_strafe.max(-1.0).min(1.0) * STRAFE_ACCEL,
 				);
 
 				let angles = Vector3::new(0.into(), 0.into(), transform.rotation[2]);
@@ -158,24 +231,26 @@ impl<'a> RunNow<'a> for PlayerUseSystem {
 
 	fn run_now(&mut self, world: &'a World) {
 		let (
-			client,
+			entities,
 			map_asset_storage,
 			mut use_event_channel,
 			map_dynamic_component,
+			transform_component,
 			use_action_component,
-			mut transform_component,
+			user_component,
 		) = world.system_data::<(
-			ReadExpect<Client>,
+			Entities,
 			ReadExpect<AssetStorage<Map>>,
 			WriteExpect<EventChannel<UseEvent>>,
 			ReadStorage<MapDynamic>,
+			ReadStorage<Transform>,
 			ReadStorage<UseAction>,
-			WriteStorage<Transform>,
+			ReadStorage<User>,
 		)>();
 
-		if let Some(entity) = client.entity {
-			if client.command.action_use && !client.previous_command.action_use {
-				let transform = transform_component.get_mut(entity).unwrap();
+		for (entity, user) in (&entities, &user_component).join() {
+			if user.command.action_use && !user.previous_command.action_use {
+				let transform = transform_component.get(entity).unwrap();
 				let map_dynamic = map_dynamic_component.join().next().unwrap();
 				let map = map_asset_storage.get(&map_dynamic.map).unwrap();
 
@@ -218,7 +293,7 @@ impl<'a> RunNow<'a> for PlayerUseSystem {
 
 					// Used from the back, ignore
 					if (use_line.point - linedef.line.point).dot(&linedef.normal) <= 0.0 {
-						return;
+						continue;
 					}
 
 					let linedef_entity = map_dynamic.linedefs[linedef_index].entity;
@@ -242,3 +317,421 @@ pub enum UseAction {
 pub struct UseEvent {
 	pub linedef_entity: Entity,
 }
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const CONNECT_ATTEMPTS: u32 = 10;
+const CONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct ServerConnection {
+	channel: SequencedChannel<ClientMessage, ServerMessage>,
+	last_received: Instant,
+	socket: Arc<Socket>,
+	state: ConnectionState,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ConnectionState {
+	Connecting {
+		attempts: u32,
+		last_attempt: Option<Instant>,
+	},
+	Connected,
+	Disconnected,
+}
+
+impl ServerConnection {
+	pub fn new(socket: Socket, addr: Addr) -> ServerConnection {
+		let socket = Arc::new(socket);
+
+		ServerConnection {
+			channel: SequencedChannel::new(socket.clone(), addr),
+			last_received: Instant::now(),
+			socket,
+			state: ConnectionState::Connecting {
+				attempts: 0,
+				last_attempt: None,
+			},
+		}
+	}
+
+	pub fn is_disconnected(&self) -> bool {
+		if let ConnectionState::Disconnected = self.state {
+			true
+		} else {
+			false
+		}
+	}
+
+	pub fn receive(&mut self) -> Vec<ServerMessage> {
+		let now = Instant::now();
+		let mut messages = Vec::new();
+
+		while let Some((data, addr)) = self.socket.next() {
+			if addr != self.channel.addr() {
+				continue;
+			}
+
+			let received = match Packet::<ServerMessage>::try_from(data) {
+				Ok(Packet::Unsequenced(received)) => received,
+				Ok(Packet::Sequenced(packet)) => self.channel.process(packet),
+				Err(err) => {
+					log::warn!("Received a malformed packet from {}: {}", addr, err);
+					continue;
+				}
+			};
+
+			self.last_received = now;
+
+			for message in received {
+				match message {
+					ServerMessage::ConnectResponse => {
+						if let ConnectionState::Connecting { .. } = self.state {
+							log::info!("Connected to {}", addr);
+							self.state = ConnectionState::Connected;
+						}
+					}
+					ServerMessage::Disconnect => {
+						log::info!("Disconnected by the server");
+						self.state = ConnectionState::Disconnected;
+						return messages;
+					}
+					_ => messages.push(message),
+				}
+			}
+		}
+
+		match self.state {
+			ConnectionState::Connecting {
+				attempts,
+				last_attempt,
+			} => {
+				if last_attempt.map_or(true, |last| now - last >= CONNECT_INTERVAL) {
+					if attempts == CONNECT_ATTEMPTS {
+						log::error!("Could not connect to {}", self.channel.addr());
+						self.state = ConnectionState::Disconnected;
+					} else {
+						log::info!("Connecting to {}...", self.channel.addr());
+						self.socket.send_to(
+							Packet::Unsequenced(vec![ClientMessage::Connect]).into(),
+							self.channel.addr(),
+						);
+						self.state = ConnectionState::Connecting {
+							attempts: attempts + 1,
+							last_attempt: Some(now),
+						};
+					}
+				}
+			}
+			ConnectionState::Connected => {
+				if now - self.last_received >= TIMEOUT {
+					log::error!("Connection to {} timed out", self.channel.addr());
+					self.state = ConnectionState::Disconnected;
+				}
+			}
+			ConnectionState::Disconnected => {}
+		}
+
+		messages
+	}
+
+	pub fn send_reliable(&mut self, message: ClientMessage) {
+		if let ConnectionState::Connected = self.state {
+			self.channel.send_reliable(message);
+		}
+	}
+
+	pub fn send_unreliable(&mut self, message: ClientMessage) {
+		if let ConnectionState::Connected = self.state {
+			self.channel.send_unreliable(message);
+		}
+	}
+
+	pub fn flush(&mut self) {
+		if let ConnectionState::Connected = self.state {
+			self.channel.flush();
+		}
+	}
+
+	pub fn disconnect(&mut self) {
+		// The server times out the client if this gets lost
+		self.send_reliable(ClientMessage::Disconnect);
+		self.flush();
+		self.state = ConnectionState::Disconnected;
+
+		let stats = self.channel.stats();
+		log::debug!(
+			"Sent {} packets, received {} packets, RTT {} ms, packet loss {:.1}%",
+			stats.packets_sent,
+			stats.packets_received,
+			stats.rtt.as_millis(),
+			stats.packet_loss * 100.0,
+		);
+	}
+}
+
+/*
+	Prediction
+*/
+
+// Number of commands kept for replaying while they wait to be acknowledged
+const COMMAND_BUFFER_SIZE: usize = 64;
+
+// Number of the most recent commands that are sent each tic, in case some get lost
+const COMMAND_REDUNDANCY: usize = 4;
+
+/// Commands that were run locally but not yet by the server.
+#[derive(Default)]
+pub struct Prediction {
+	commands: VecDeque<(u32, UserCommand)>,
+	last_number: u32,
+	reconcile: bool,
+}
+
+impl Prediction {
+	fn push(&mut self, command: UserCommand) {
+		self.last_number += 1;
+		self.commands.push_back((self.last_number, command));
+
+		if self.commands.len() > COMMAND_BUFFER_SIZE {
+			self.commands.pop_front();
+		}
+	}
+
+	/// Drops the commands the server has run. The remaining ones are replayed on top of
+	/// the server's state on the next tic.
+	pub fn ack(&mut self, number: u32) {
+		while self.commands.front().map_or(false, |(n, _)| *n <= number) {
+			self.commands.pop_front();
+		}
+
+		self.reconcile = true;
+	}
+}
+
+/// Runs the player's commands locally so that movement responds immediately.
+#[derive(Default)]
+pub struct ClientPredictSystem {
+	physics: PhysicsSystem,
+	player_move: PlayerMoveSystem,
+}
+
+impl<'a> RunNow<'a> for ClientPredictSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let entity = match world.fetch::<Client>().entity {
+			Some(entity) => entity,
+			None => return,
+		};
+
+		{
+			let (
+				entity_types,
+				template_storage,
+				mut box_collider_component,
+				mut interpolation_component,
+				transform_component,
+				mut user_component,
+				velocity_component,
+			) = world.system_data::<(
+				ReadExpect<MobjTypes>,
+				ReadExpect<AssetStorage<EntityTemplate>>,
+				WriteStorage<BoxCollider>,
+				WriteStorage<Interpolation>,
+				ReadStorage<Transform>,
+				WriteStorage<User>,
+				ReadStorage<Velocity>,
+			)>();
+
+			if !transform_component.contains(entity) || !velocity_component.contains(entity) {
+				return;
+			}
+
+			interpolation_component.remove(entity);
+
+			// The server doesn't send colliders, so take it from the player template
+			if !box_collider_component.contains(entity) {
+				let box_collider = entity_types
+					.names
+					.get("PLAYER")
+					.and_then(|handle| template_storage.get(handle))
+					.and_then(|template| template.get::<BoxCollider>())
+					.copied();
+
+				match box_collider {
+					Some(box_collider) => {
+						box_collider_component.insert(entity, box_collider).unwrap()
+					}
+					None => return,
+				};
+			}
+
+			if !user_component.contains(entity) {
+				user_component.insert(entity, User::default()).unwrap();
+			}
+		}
+
+		let commands: Vec<UserCommand> = {
+			let mut prediction = world.fetch_mut::<Prediction>();
+
+			if std::mem::take(&mut prediction.reconcile) {
+				// Go back to the server's state and replay the commands it hasn't run yet
+				if let Err(err) = world.fetch::<SnapshotReceiver>().restore(world, entity) {
+					log::warn!("Could not restore the player state: {}", err);
+				}
+
+				prediction.commands.iter().map(|(_, c)| *c).collect()
+			} else {
+				prediction
+					.commands
+					.back()
+					.map(|(_, c)| *c)
+					.into_iter()
+					.collect()
+			}
+		};
+
+		for command in commands {
+			if let Some(user) = world.write_component::<User>().get_mut(entity) {
+				user.previous_command = user.command;
+				user.command = command;
+			}
+
+			self.player_move.run_now(world);
+			self.physics.run_now(world);
+		}
+	}
+}
+
+/*
+	Interpolation
+*/
+
+/// Smooths out the movement of entities between snapshots.
+#[derive(Clone, Component, Debug)]
+pub struct Interpolation {
+	pub from: Transform,
+	pub to: Transform,
+	pub start: Instant,
+}
+
+#[derive(Default)]
+pub struct InterpolationSystem;
+
+impl<'a> RunNow<'a> for InterpolationSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let (frame_time, interpolation_component, mut transform_component) = world.system_data::<(
+			ReadExpect<Duration>,
+			ReadStorage<Interpolation>,
+			WriteStorage<Transform>,
+		)>();
+		let now = Instant::now();
+
+		for (interpolation, transform) in
+			(&interpolation_component, &mut transform_component).join()
+		{
+			let Interpolation { from, to, start } = interpolation;
+			let t = ((now - *start).as_secs_f32() / frame_time.as_secs_f32()).min(1.0);
+
+			transform.position = from.position + (to.position - from.position) * t;
+
+			for i in 0..3 {
+				transform.rotation[i] =
+					from.rotation[i] + ((to.rotation[i] - from.rotation[i]).0 as f32 * t) as i32;
+			}
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct ClientSendSystem;
+
+impl<'a> RunNow<'a> for ClientSendSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let (client, mut connection, mut prediction, receiver) = world.system_data::<(
+			ReadExpect<Client>,
+			WriteExpect<ServerConnection>,
+			WriteExpect<Prediction>,
+			ReadExpect<SnapshotReceiver>,
+		)>();
+
+		prediction.push(client.command);
+		let skip = prediction.commands.len().saturating_sub(COMMAND_REDUNDANCY);
+
+		for &(number, command) in prediction.commands.iter().skip(skip) {
+			connection.send_unreliable(ClientMessage::UserCommand(number, command));
+		}
+
+		if let Some(number) = receiver.latest() {
+			connection.send_unreliable(ClientMessage::SnapshotAck(number));
+		}
+
+		connection.flush();
+	}
+}
+
+pub fn handle_message(message: ServerMessage, world: &World) -> anyhow::Result<()> {
+	match message {
+		ServerMessage::CommandAck(number) => {
+			world.fetch_mut::<Prediction>().ack(number);
+		}
+		ServerMessage::PlayerEntity(entity_id) => {
+			// Not known yet if the snapshot with the entity didn't arrive
+			let entity = world.fetch::<SnapshotReceiver>().entity(entity_id).ok();
+			let mut client = world.fetch_mut::<Client>();
+
+			if client.entity != entity {
+				// Stop predicting the old entity
+				if let Some(old_entity) = client.entity {
+					world.write_component::<BoxCollider>().remove(old_entity);
+					world.write_component::<User>().remove(old_entity);
+					world.fetch_mut::<Quadtree>().remove(old_entity);
+				}
+
+				client.entity = entity;
+			}
+		}
+		ServerMessage::RConOutput(text) => {
+			log::info!("{}", text);
+		}
+		ServerMessage::Scoreboard(scores) => {
+			for (player_num, frags) in scores.iter() {
+				log::info!("Player {}: {} frag(s)", player_num, frags);
+			}
+
+			world.fetch_mut::<Client>().scores = scores;
+		}
+		ServerMessage::Snapshot(snapshot) => {
+			world
+				.fetch_mut::<SnapshotReceiver>()
+				.apply(world, snapshot)?;
+		}
+		ServerMessage::SoundPlay(origin, name) => {
+			let (receiver, mut loader, mut sound_storage, mut sound_queue) = world.system_data::<(
+				ReadExpect<SnapshotReceiver>,
+				WriteExpect<WadLoader>,
+				WriteExpect<AssetStorage<Sound>>,
+				WriteExpect<Vec<(AssetHandle<Sound>, SoundOrigin)>>,
+			)>();
+			let origin = match origin {
+				Some((entity_id, channel)) => SoundOrigin::Entity(
+					receiver.entity(entity_id)?,
+					SoundChannel::try_from(channel)?,
+				),
+				None => SoundOrigin::Global,
+			};
+			let handle = sound_storage.load(&name, &mut *loader);
+			sound_storage.build_waiting(crate::doom::sound::build_sound);
+			sound_queue.push((handle, origin));
+		}
+		ServerMessage::ConnectResponse
+		| ServerMessage::Disconnect
+		| ServerMessage::MapChange(_) => unreachable!(),
+	}
+
+	Ok(())
+}
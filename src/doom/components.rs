@@ -3,6 +3,9 @@ use nalgebra::Vector3;
 use specs::{Component, DenseVecStorage};
 use specs_derive::Component;
 
+#[derive(Clone, Component, Copy, Debug, Default)]
+pub struct DeathmatchStart;
+
 #[derive(Clone, Component, Copy, Debug)]
 pub struct SpawnOnCeiling {
 	pub offset: f32,
@@ -3,9 +3,11 @@ use crate::{
 	assets::{AssetHandle, AssetStorage},
 	component::EntityTemplate,
 	doom::{
-		components::{SpawnOnCeiling, SpawnPoint, Velocity},
+		client::User,
+		components::{DeathmatchStart, SpawnOnCeiling, SpawnPoint, Velocity},
+		effects::PlayerEffects,
 		physics::{BoxCollider, SolidMask},
-		render::sprite::SpriteRender,
+		render::sprite::{RenderStyle, SpriteRender},
 		sprite::Sprite,
 		wad::WadLoader,
 	},
@@ -56,6 +58,7 @@ impl MobjTypes {
 
 		let handle = template_storage.insert({
 			EntityTemplate::new()
+				.with_component(DeathmatchStart)
 		});
 		doomednums.insert(11, handle);
 
@@ -70,7 +73,11 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PLAY", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
+				.with_component(PlayerEffects::default())
+				.with_component(User::default())
 				.with_component(Velocity::default())
 		});
 		names.insert("PLAYER", handle);
@@ -86,6 +93,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POSS", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -103,6 +112,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SPOS", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -120,6 +131,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("VILE", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -132,6 +145,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("FIRE", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("FIRE", handle);
@@ -147,6 +162,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SKEL", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -159,6 +176,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("FATB", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -170,6 +189,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PUFF", &mut *loader),
 					frame: 1,
 					full_bright: false,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("SMOKE", handle);
@@ -185,6 +206,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("FATT", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -197,6 +220,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("MANF", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -213,6 +238,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CPOS", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -230,6 +257,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TROO", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -247,6 +276,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SARG", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -264,6 +295,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SARG", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Fuzz,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -281,6 +314,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HEAD", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -298,6 +333,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BOSS", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -310,6 +347,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BAL7", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -326,6 +365,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BOS2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -343,6 +384,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SKUL", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -360,6 +403,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SPID", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -377,6 +422,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BSPI", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -394,6 +441,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CYBR", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -411,6 +460,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PAIN", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -428,6 +479,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SSWV", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -448,6 +501,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("KEEN", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -465,6 +520,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BBRN", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -477,6 +534,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SSWV", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("BOSSSPIT", handle.clone());
@@ -494,6 +553,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BOSF", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -505,6 +566,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("FIRE", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("SPAWNFIRE", handle);
@@ -520,6 +583,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BAR1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -532,6 +597,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BAL1", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -543,6 +610,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BAL2", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -554,6 +623,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("MISL", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -565,6 +636,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PLSS", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -576,6 +649,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BFS1", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -587,6 +662,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("APLS", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 				.with_component(Velocity::default())
 		});
@@ -598,6 +675,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PUFF", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("PUFF", handle);
@@ -608,6 +687,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BLUD", &mut *loader),
 					frame: 2,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("BLOOD", handle);
@@ -618,6 +699,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TFOG", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("TFOG", handle);
@@ -628,6 +711,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("IFOG", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("IFOG", handle);
@@ -644,6 +729,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BFE2", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("EXTRABFG", handle);
@@ -659,6 +746,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("ARM1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC0", handle.clone());
@@ -675,6 +764,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("ARM2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC1", handle.clone());
@@ -691,6 +782,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BON1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC2", handle.clone());
@@ -707,6 +800,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BON2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC3", handle.clone());
@@ -723,6 +818,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BKEY", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC4", handle.clone());
@@ -739,6 +836,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("RKEY", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC5", handle.clone());
@@ -755,6 +854,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("YKEY", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC6", handle.clone());
@@ -771,6 +872,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("YSKU", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC7", handle.clone());
@@ -787,6 +890,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("RSKU", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC8", handle.clone());
@@ -803,6 +908,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BSKU", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC9", handle.clone());
@@ -819,6 +926,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("STIM", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC10", handle.clone());
@@ -835,6 +944,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("MEDI", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC11", handle.clone());
@@ -851,6 +962,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SOUL", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("MISC12", handle.clone());
@@ -867,6 +980,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PINV", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("INV", handle.clone());
@@ -883,6 +998,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PSTR", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC13", handle.clone());
@@ -899,6 +1016,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PINS", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("INS", handle.clone());
@@ -915,6 +1034,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SUIT", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC14", handle.clone());
@@ -931,6 +1052,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PMAP", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC15", handle.clone());
@@ -947,6 +1070,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PVIS", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC16", handle.clone());
@@ -963,6 +1088,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("MEGA", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Translucent,
+					translation: 0,
 				})
 		});
 		names.insert("MEGA", handle.clone());
@@ -979,6 +1106,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CLIP", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("CLIP", handle.clone());
@@ -995,6 +1124,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("AMMO", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC17", handle.clone());
@@ -1011,6 +1142,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("ROCK", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC18", handle.clone());
@@ -1027,6 +1160,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BROK", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC19", handle.clone());
@@ -1043,6 +1178,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CELL", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC20", handle.clone());
@@ -1059,6 +1196,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CELP", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC21", handle.clone());
@@ -1075,6 +1214,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SHEL", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC22", handle.clone());
@@ -1091,6 +1232,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SBOX", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC23", handle.clone());
@@ -1107,6 +1250,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BPAK", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC24", handle.clone());
@@ -1123,6 +1268,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BFUG", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC25", handle.clone());
@@ -1139,6 +1286,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("MGUN", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("CHAINGUN", handle.clone());
@@ -1155,6 +1304,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CSAW", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC26", handle.clone());
@@ -1171,6 +1322,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("LAUN", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC27", handle.clone());
@@ -1187,6 +1340,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PLAS", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC28", handle.clone());
@@ -1203,6 +1358,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SHOT", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("SHOTGUN", handle.clone());
@@ -1219,6 +1376,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SGN2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("SUPERSHOTGUN", handle.clone());
@@ -1235,6 +1394,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TLMP", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC29", handle.clone());
@@ -1251,6 +1412,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TLP2", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC30", handle.clone());
@@ -1267,6 +1430,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COLU", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC31", handle.clone());
@@ -1283,6 +1448,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COL1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC32", handle.clone());
@@ -1299,6 +1466,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COL2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC33", handle.clone());
@@ -1315,6 +1484,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COL3", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC34", handle.clone());
@@ -1331,6 +1502,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COL4", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC35", handle.clone());
@@ -1347,6 +1520,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COL6", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC36", handle.clone());
@@ -1363,6 +1538,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("COL5", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC37", handle.clone());
@@ -1379,6 +1556,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CEYE", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC38", handle.clone());
@@ -1395,6 +1574,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("FSKU", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC39", handle.clone());
@@ -1411,6 +1592,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TRE1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC40", handle.clone());
@@ -1427,6 +1610,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TBLU", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC41", handle.clone());
@@ -1443,6 +1628,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TGRN", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC42", handle.clone());
@@ -1459,6 +1646,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TRED", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC43", handle.clone());
@@ -1475,6 +1664,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SMBT", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC44", handle.clone());
@@ -1491,6 +1682,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SMGT", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC45", handle.clone());
@@ -1507,6 +1700,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SMRT", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC46", handle.clone());
@@ -1523,6 +1718,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SMIT", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC47", handle.clone());
@@ -1539,6 +1736,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("ELEC", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC48", handle.clone());
@@ -1555,6 +1754,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CAND", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC49", handle.clone());
@@ -1571,6 +1772,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("CBRA", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC50", handle.clone());
@@ -1590,6 +1793,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC51", handle.clone());
@@ -1609,6 +1814,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC52", handle.clone());
@@ -1628,6 +1835,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR3", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC53", handle.clone());
@@ -1647,6 +1856,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR4", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC54", handle.clone());
@@ -1666,6 +1877,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR5", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC55", handle.clone());
@@ -1685,6 +1898,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC56", handle.clone());
@@ -1704,6 +1919,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR4", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC57", handle.clone());
@@ -1723,6 +1940,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR3", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC58", handle.clone());
@@ -1742,6 +1961,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR5", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC59", handle.clone());
@@ -1761,6 +1982,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("GOR1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC60", handle.clone());
@@ -1777,6 +2000,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HEAD", &mut *loader),
 					frame: 11,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC61", handle.clone());
@@ -1793,6 +2018,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PLAY", &mut *loader),
 					frame: 13,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC62", handle.clone());
@@ -1809,6 +2036,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POSS", &mut *loader),
 					frame: 11,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC63", handle.clone());
@@ -1825,6 +2054,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SARG", &mut *loader),
 					frame: 13,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC64", handle.clone());
@@ -1841,6 +2072,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SKUL", &mut *loader),
 					frame: 10,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC65", handle.clone());
@@ -1857,6 +2090,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TROO", &mut *loader),
 					frame: 12,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC66", handle.clone());
@@ -1873,6 +2108,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("SPOS", &mut *loader),
 					frame: 11,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC67", handle.clone());
@@ -1889,6 +2126,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PLAY", &mut *loader),
 					frame: 22,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC68", handle.clone());
@@ -1905,6 +2144,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("PLAY", &mut *loader),
 					frame: 22,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC69", handle.clone());
@@ -1921,6 +2162,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POL2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC70", handle.clone());
@@ -1937,6 +2180,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POL5", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC71", handle.clone());
@@ -1953,6 +2198,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POL4", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC72", handle.clone());
@@ -1969,6 +2216,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POL3", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC73", handle.clone());
@@ -1985,6 +2234,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POL1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC74", handle.clone());
@@ -2001,6 +2252,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POL6", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC75", handle.clone());
@@ -2017,6 +2270,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("TRE2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC76", handle.clone());
@@ -2033,6 +2288,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("FCAN", &mut *loader),
 					frame: 0,
 					full_bright: true,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC77", handle.clone());
@@ -2052,6 +2309,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HDB1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC78", handle.clone());
@@ -2071,6 +2330,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HDB2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC79", handle.clone());
@@ -2090,6 +2351,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HDB3", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC80", handle.clone());
@@ -2109,6 +2372,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HDB4", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC81", handle.clone());
@@ -2128,6 +2393,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HDB5", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC82", handle.clone());
@@ -2147,6 +2414,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("HDB6", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC83", handle.clone());
@@ -2158,6 +2427,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POB1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC84", handle.clone());
@@ -2169,6 +2440,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("POB2", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC85", handle.clone());
@@ -2180,6 +2453,8 @@ impl MobjTypes {
 					sprite: sprite_storage.load("BRS1", &mut *loader),
 					frame: 0,
 					full_bright: false,
+					render_style: RenderStyle::Normal,
+					translation: 0,
 				})
 		});
 		names.insert("MISC86", handle.clone());
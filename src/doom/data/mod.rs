@@ -2,10 +2,12 @@ pub mod anims;
 mod linedefs;
 mod mobjs;
 mod sectors;
+mod sounds;
 
 pub use linedefs::LinedefTypes;
 pub use mobjs::MobjTypes;
 pub use sectors::SectorTypes;
+pub use sounds::sound_info;
 
 use lazy_static::lazy_static;
 use std::time::Duration;
@@ -16,6 +18,10 @@ pub const FRAME_TIME: Duration = Duration::from_nanos(28_571_429); // 1/35 sec
 pub const FORWARD_ACCEL: f32 = (50.0 * 2048.0 / 65536.0) * FRAME_RATE * FRAME_RATE;
 pub const STRAFE_ACCEL: f32 = (40.0 * 2048.0 / 65536.0) * FRAME_RATE * FRAME_RATE;
 
+// Turning with buttons, per tic: walking, running, and slowly when just starting to turn
+pub const TURN_SPEED: [u32; 3] = [640 << 16, 1280 << 16, 320 << 16];
+pub const SLOW_TURN_TICS: u32 = 6;
+
 pub const GRAVITY: f32 = 1.0 * FRAME_RATE * FRAME_RATE;
 
 lazy_static! {
@@ -0,0 +1,138 @@
+#[derive(Clone, Copy, Debug)]
+pub struct SoundInfo {
+	/// Lower numbers are more important, and can replace other sounds when channels run out.
+	pub priority: i32,
+	/// Only one instance can play at a time.
+	pub singular: bool,
+}
+
+const DEFAULT_SOUND_INFO: SoundInfo = SoundInfo {
+	priority: 64,
+	singular: false,
+};
+
+/// The priority and singularity of a sound lump, from the original's sound table.
+pub fn sound_info(name: &str) -> SoundInfo {
+	let name = name.to_ascii_uppercase();
+
+	SOUNDS
+		.iter()
+		.find(|(sound_name, _, _)| *sound_name == name)
+		.map_or(DEFAULT_SOUND_INFO, |&(_, priority, singular)| SoundInfo {
+			priority,
+			singular,
+		})
+}
+
+#[rustfmt::skip]
+const SOUNDS: [(&str, i32, bool); 108] = [
+	("DSPISTOL", 64, false),
+	("DSSHOTGN", 64, false),
+	("DSSGCOCK", 64, false),
+	("DSDSHTGN", 64, false),
+	("DSDBOPN", 64, false),
+	("DSDBCLS", 64, false),
+	("DSDBLOAD", 64, false),
+	("DSPLASMA", 64, false),
+	("DSBFG", 64, false),
+	// Only one chainsaw at a time
+	("DSSAWUP", 64, true),
+	("DSSAWIDL", 118, true),
+	("DSSAWFUL", 64, true),
+	("DSSAWHIT", 64, true),
+	("DSRLAUNC", 64, false),
+	("DSRXPLOD", 70, false),
+	("DSFIRSHT", 70, false),
+	("DSFIRXPL", 70, false),
+	("DSPSTART", 100, false),
+	("DSPSTOP", 100, false),
+	("DSDOROPN", 100, false),
+	("DSDORCLS", 100, false),
+	("DSSTNMOV", 119, false),
+	("DSSWTCHN", 78, false),
+	("DSSWTCHX", 78, false),
+	("DSPLPAIN", 96, false),
+	("DSDMPAIN", 96, false),
+	("DSPOPAIN", 96, false),
+	("DSVIPAIN", 96, false),
+	("DSMNPAIN", 96, false),
+	("DSPEPAIN", 96, false),
+	("DSSLOP", 78, false),
+	("DSITEMUP", 78, true),
+	("DSWPNUP", 78, true),
+	("DSOOF", 96, false),
+	("DSTELEPT", 32, false),
+	("DSPOSIT1", 98, true),
+	("DSPOSIT2", 98, true),
+	("DSPOSIT3", 98, true),
+	("DSBGSIT1", 98, true),
+	("DSBGSIT2", 98, true),
+	("DSSGTSIT", 98, true),
+	("DSCACSIT", 98, true),
+	("DSBRSSIT", 94, true),
+	("DSCYBSIT", 92, true),
+	("DSSPISIT", 90, true),
+	("DSBSPSIT", 90, true),
+	("DSKNTSIT", 90, true),
+	("DSVILSIT", 90, true),
+	("DSMANSIT", 90, true),
+	("DSPESIT", 90, true),
+	("DSSKLATK", 70, false),
+	("DSSGTATK", 70, false),
+	("DSSKEPCH", 70, false),
+	("DSVILATK", 70, false),
+	("DSCLAW", 70, false),
+	("DSSKESWG", 70, false),
+	("DSPLDETH", 32, false),
+	("DSPDIEHI", 32, false),
+	("DSPODTH1", 70, false),
+	("DSPODTH2", 70, false),
+	("DSPODTH3", 70, false),
+	("DSBGDTH1", 70, false),
+	("DSBGDTH2", 70, false),
+	("DSSGTDTH", 70, false),
+	("DSCACDTH", 70, false),
+	("DSSKLDTH", 70, false),
+	("DSBRSDTH", 32, false),
+	("DSCYBDTH", 32, false),
+	("DSSPIDTH", 32, false),
+	("DSBSPDTH", 32, false),
+	("DSVILDTH", 32, false),
+	("DSKNTDTH", 32, false),
+	("DSPEDTH", 32, false),
+	("DSSKEDTH", 32, false),
+	("DSPOSACT", 120, true),
+	("DSBGACT", 120, true),
+	("DSDMACT", 120, true),
+	("DSBSPACT", 100, true),
+	("DSBSPWLK", 100, true),
+	("DSVILACT", 100, true),
+	("DSNOWAY", 78, false),
+	("DSBAREXP", 60, false),
+	("DSPUNCH", 64, false),
+	("DSHOOF", 70, false),
+	("DSMETAL", 70, false),
+	("DSCHGUN", 64, false),
+	("DSTINK", 60, false),
+	("DSBDOPN", 100, false),
+	("DSBDCLS", 100, false),
+	("DSITMBK", 100, false),
+	("DSFLAME", 32, false),
+	("DSFLAMST", 32, false),
+	("DSGETPOW", 60, false),
+	("DSBOSPIT", 70, false),
+	("DSBOSCUB", 70, false),
+	("DSBOSSIT", 70, false),
+	("DSBOSPN", 70, false),
+	("DSBOSDTH", 70, false),
+	("DSMANATK", 70, false),
+	("DSMANDTH", 70, false),
+	("DSSSSIT", 70, false),
+	("DSSSDTH", 70, false),
+	("DSKEENPN", 70, false),
+	("DSKEENDT", 70, false),
+	("DSSKEACT", 70, false),
+	("DSSKESIT", 70, false),
+	("DSSKEATK", 70, false),
+	("DSRADIO", 60, false),
+];
@@ -9,12 +9,13 @@ use crate::{
 			LinedefRef, Map, MapDynamic, SectorRef, SidedefSlot,
 		},
 		physics::{BoxCollider, SectorTracer},
+		sound::{SoundChannel, SoundOrigin},
 	},
 	geometry::Side,
 };
 use shrev::{EventChannel, ReaderId};
 use specs::{
-	Component, DenseVecStorage, Entities, Entity, Join, ReadExpect, ReadStorage, RunNow, World,
+	Component, DenseVecStorage, Entities, Join, ReadExpect, ReadStorage, RunNow, World,
 	WriteExpect, WriteStorage,
 };
 use specs_derive::Component;
@@ -53,7 +54,7 @@ impl<'a> RunNow<'a> for DoorUpdateSystem {
 			ReadExpect<Duration>,
 			ReadExpect<EventChannel<UseEvent>>,
 			ReadExpect<AssetStorage<Map>>,
-			WriteExpect<Vec<(AssetHandle<Sound>, Entity)>>,
+			WriteExpect<Vec<(AssetHandle<Sound>, SoundOrigin)>>,
 			ReadStorage<BoxCollider>,
 			ReadStorage<LinedefRef>,
 			ReadStorage<SectorRef>,
@@ -216,7 +217,10 @@ impl<'a> RunNow<'a> for DoorUpdateSystem {
 								// Play sound
 								let sector_entity =
 									map_dynamic.sectors[sidedef.sector_index].entity;
-								sound_queue.push((door_use.switch_sound.clone(), sector_entity));
+								sound_queue.push((
+									door_use.switch_sound.clone(),
+									SoundOrigin::Entity(sector_entity, SoundChannel::Body),
+								));
 
 								// Add SwitchActive component
 								switch_active_component
@@ -256,7 +260,10 @@ impl<'a> RunNow<'a> for DoorUpdateSystem {
 					door_active.state = DoorState::Opening;
 
 					// Play sound
-					sound_queue.push((door_active.open_sound.clone(), entity));
+					sound_queue.push((
+						door_active.open_sound.clone(),
+						SoundOrigin::Entity(entity, SoundChannel::Body),
+					));
 				}
 				DoorState::Opening => {
 					let move_step = door_active.speed * delta.as_secs_f32();
@@ -275,7 +282,10 @@ impl<'a> RunNow<'a> for DoorUpdateSystem {
 						door_active.state = DoorState::Closing;
 
 						// Play sound
-						sound_queue.push((door_active.close_sound.clone(), entity));
+						sound_queue.push((
+							door_active.close_sound.clone(),
+							SoundOrigin::Entity(entity, SoundChannel::Body),
+						));
 					}
 				}
 				DoorState::Closing => {
@@ -330,7 +340,10 @@ impl<'a> RunNow<'a> for DoorUpdateSystem {
 
 				sidedef_dynamic.textures[switch_active.texture_slot as usize] =
 					TextureType::Normal(switch_active.texture.clone());
-				sound_queue.push((switch_active.sound.clone(), sector_entity));
+				sound_queue.push((
+					switch_active.sound.clone(),
+					SoundOrigin::Entity(sector_entity, SoundChannel::Body),
+				));
 				done.push(entity);
 			}
 		}
@@ -0,0 +1,85 @@
+use crate::doom::data::FRAME_TIME;
+use specs::{Component, DenseVecStorage, Join, ReadExpect, RunNow, World, WriteStorage};
+use specs_derive::Component;
+use std::time::Duration;
+
+// Palettes in PLAYPAL
+const NUM_DAMAGE_PALETTES: u32 = 8;
+const START_DAMAGE_PALETTES: u32 = 1;
+const NUM_BONUS_PALETTES: u32 = 4;
+const START_BONUS_PALETTES: u32 = 9;
+const RADIATION_PALETTE: u32 = 13;
+
+// Colormaps in COLORMAP
+const INVERSE_COLORMAP: i32 = 32;
+const FULL_BRIGHT_COLORMAP: i32 = 1;
+
+/// Effects on a player's view. The damage and bonus counts go down by one every frame, as in
+/// the original, while powers last for a time.
+#[derive(Clone, Component, Copy, Debug, Default)]
+pub struct PlayerEffects {
+	pub damage_count: u32,
+	pub bonus_count: u32,
+	pub invulnerability: Duration,
+	pub light_amp: Duration,
+	pub radiation_suit: Duration,
+}
+
+impl PlayerEffects {
+	/// The PLAYPAL palette to show the world with.
+	pub fn palette(&self) -> u32 {
+		if self.damage_count > 0 {
+			let palette = ((self.damage_count + 7) >> 3).min(NUM_DAMAGE_PALETTES - 1);
+			START_DAMAGE_PALETTES + palette
+		} else if self.bonus_count > 0 {
+			let palette = ((self.bonus_count + 7) >> 3).min(NUM_BONUS_PALETTES - 1);
+			START_BONUS_PALETTES + palette
+		} else if power_showing(self.radiation_suit) {
+			RADIATION_PALETTE
+		} else {
+			0
+		}
+	}
+
+	/// The colormap that replaces sector lighting, if any.
+	pub fn fixed_colormap(&self) -> Option<i32> {
+		if power_showing(self.invulnerability) {
+			Some(INVERSE_COLORMAP)
+		} else if power_showing(self.light_amp) {
+			Some(FULL_BRIGHT_COLORMAP)
+		} else {
+			None
+		}
+	}
+}
+
+/// Powers blink when they are about to run out.
+fn power_showing(time_left: Duration) -> bool {
+	let frames = time_left.as_nanos() / FRAME_TIME.as_nanos();
+	frames > 4 * 32 || frames & 8 != 0
+}
+
+#[derive(Default)]
+pub struct PlayerEffectsSystem;
+
+impl<'a> RunNow<'a> for PlayerEffectsSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let (delta, mut player_effects_component) =
+			world.system_data::<(ReadExpect<Duration>, WriteStorage<PlayerEffects>)>();
+
+		for player_effects in (&mut player_effects_component).join() {
+			player_effects.damage_count = player_effects.damage_count.saturating_sub(1);
+			player_effects.bonus_count = player_effects.bonus_count.saturating_sub(1);
+
+			for power in [
+				&mut player_effects.invulnerability,
+				&mut player_effects.light_amp,
+				&mut player_effects.radiation_suit,
+			] {
+				*power = power.checked_sub(*delta).unwrap_or_default();
+			}
+		}
+	}
+}
@@ -0,0 +1,204 @@
+use crate::{
+	doom::{
+		client::User,
+		components::{DeathmatchStart, Transform},
+		data::FRAME_TIME,
+		server::Server,
+	},
+	quadtree::Quadtree,
+};
+use anyhow::bail;
+use rand::seq::SliceRandom;
+use rand_pcg::Pcg64Mcg;
+use specs::{Join, ReadExpect, ReadStorage, RunNow, World, WorldExt, WriteExpect};
+use std::{fmt, time::Duration};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum GameMode {
+	/// Players respawn at their own start.
+	/// TODO: share keys and map progress between players, once items and exits exist.
+	#[default]
+	Coop,
+	/// Players respawn at random deathmatch starts and score frags.
+	/// TODO: leave weapons behind when picked up, once items can be picked up.
+	Deathmatch,
+	/// Deathmatch with different item rules.
+	/// TODO: respawn picked up items, once items can be picked up.
+	AltDeath,
+}
+
+impl GameMode {
+	pub fn is_deathmatch(self) -> bool {
+		self != GameMode::Coop
+	}
+}
+
+impl fmt::Display for GameMode {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			GameMode::Coop => write!(f, "cooperative"),
+			GameMode::Deathmatch => write!(f, "deathmatch"),
+			GameMode::AltDeath => write!(f, "altdeath"),
+		}
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GameRules {
+	pub mode: GameMode,
+	pub frag_limit: Option<i32>,
+	pub time_limit: Option<Duration>,
+	pub map_time: Duration,
+	pub map_ended: bool,
+}
+
+impl GameRules {
+	pub fn log_status(&self) {
+		let mut rules = vec![self.mode.to_string()];
+
+		if let Some(frag_limit) = self.frag_limit {
+			rules.push(format!("frag limit {}", frag_limit));
+		}
+
+		if let Some(time_limit) = self.time_limit {
+			rules.push(format!("time limit {} min", time_limit.as_secs() / 60));
+		}
+
+		log::info!("Rules: {}", rules.join(", "));
+		log::info!(
+			"Map time: {}:{:02}",
+			self.map_time.as_secs() / 60,
+			self.map_time.as_secs() % 60
+		);
+	}
+}
+
+/// Ends the map when the frag or time limit is reached.
+#[derive(Default)]
+pub struct GameRulesSystem;
+
+impl<'a> RunNow<'a> for GameRulesSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let (mut rules, server) =
+			world.system_data::<(WriteExpect<GameRules>, ReadExpect<Server>)>();
+
+		if rules.map_ended || !server.has_map() {
+			return;
+		}
+
+		rules.map_time += FRAME_TIME;
+
+		if let Some(time_limit) = rules.time_limit {
+			if rules.map_time >= time_limit {
+				log::info!("Time limit reached");
+				rules.map_ended = true;
+			}
+		}
+
+		if let Some(frag_limit) = rules.frag_limit {
+			if rules.mode.is_deathmatch() {
+				if let Some((player_num, frags)) = server.scores().first() {
+					if *frags >= frag_limit {
+						log::info!("Frag limit reached by player {}", player_num);
+						rules.map_ended = true;
+					}
+				}
+			}
+		}
+
+		if rules.map_ended {
+			server.log_scores();
+		}
+	}
+}
+
+/// Kills a player, who then respawns on the next tic.
+/// Frags only count in deathmatch, where killing yourself loses one.
+pub fn kill_player(world: &World, victim: usize, killer: Option<usize>) -> anyhow::Result<()> {
+	let entity = {
+		let (rules, mut server) =
+			world.system_data::<(ReadExpect<GameRules>, WriteExpect<Server>)>();
+		let entity = match server.kill_player(victim) {
+			Some(entity) => entity,
+			None => bail!("Player {} is not in the game", victim),
+		};
+
+		if rules.mode.is_deathmatch() {
+			match killer {
+				Some(killer) if killer != victim => {
+					log::info!("Player {} fragged player {}", killer, victim);
+					server.add_frags(killer, 1);
+				}
+				_ => {
+					log::info!("Player {} died", victim);
+					server.add_frags(victim, -1);
+				}
+			}
+		}
+
+		entity
+	};
+
+	world.fetch_mut::<Quadtree>().remove(entity);
+	world.entities().delete(entity)?;
+	Ok(())
+}
+
+// Players closer than this to a deathmatch start block it
+const SPAWN_BLOCK_DISTANCE: f32 = 64.0;
+
+/// Picks a random deathmatch start that no player is standing on.
+pub fn deathmatch_start(world: &World) -> Option<Transform> {
+	let (mut rng, deathmatch_start_component, transform_component, user_component) = world
+		.system_data::<(
+			WriteExpect<Pcg64Mcg>,
+			ReadStorage<DeathmatchStart>,
+			ReadStorage<Transform>,
+			ReadStorage<User>,
+		)>();
+
+	let mut starts: Vec<Transform> = (&deathmatch_start_component, &transform_component)
+		.join()
+		.map(|(_, transform)| *transform)
+		.collect();
+	starts.shuffle(&mut *rng);
+
+	let is_free = |start: &Transform| {
+		(&user_component, &transform_component)
+			.join()
+			.all(|(_, transform)| {
+				(transform.position.xy() - start.position.xy()).norm() >= SPAWN_BLOCK_DISTANCE
+			})
+	};
+
+	starts
+		.iter()
+		.find(|start| is_free(start))
+		.or_else(|| starts.first())
+		.copied()
+}
+
+/// The map that follows in the usual order, if the loaded WADs have it.
+pub fn next_map<'a>(name: &str, mut map_names: impl Iterator<Item = &'a str>) -> Option<String> {
+	let name = name.to_ascii_uppercase();
+
+	let next = if let Some(number) = name.strip_prefix("MAP") {
+		let number: u32 = number.parse().ok()?;
+		format!("MAP{:02}", number % 32 + 1)
+	} else {
+		match name.as_bytes() {
+			&[b'E', episode @ b'0'..=b'9', b'M', map @ b'0'..=b'9'] => {
+				format!("E{}M{}", episode as char, (map - b'0') % 8 + 1)
+			}
+			_ => return None,
+		}
+	};
+
+	if map_names.any(|lump| lump.eq_ignore_ascii_case(&next)) {
+		Some(next)
+	} else {
+		None
+	}
+}
@@ -1,4 +1,5 @@
 use crate::assets::{Asset, AssetFormat, DataSource};
+use anyhow::ensure;
 use byteorder::{ReadBytesExt, LE};
 use std::{
 	io::{Cursor, Read, Seek, SeekFrom},
@@ -56,6 +57,108 @@ impl Asset for Palette {
 	}
 }
 
+/// All palettes in PLAYPAL. The first is the normal one, the others tint the screen red for
+/// damage, yellow for pickups and green for the radiation suit.
+pub struct Palettes(Vec<Palette>);
+
+impl Deref for Palettes {
+	type Target = [Palette];
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl Asset for Palettes {
+	type Data = Self;
+	type Intermediate = Self;
+	const NAME: &'static str = "Palettes";
+
+	fn import(name: &str, source: &impl DataSource) -> anyhow::Result<Self::Intermediate> {
+		let data = source.load(name)?;
+		let palettes: Vec<Palette> = data
+			.chunks_exact(256 * 3)
+			.map(|chunk| {
+				let mut palette = [RGBAColor::default(); 256];
+
+				for (color, rgb) in palette.iter_mut().zip(chunk.chunks_exact(3)) {
+					*color = RGBAColor {
+						r: rgb[0],
+						g: rgb[1],
+						b: rgb[2],
+						a: 0xFF,
+					};
+				}
+
+				Palette(palette)
+			})
+			.collect();
+
+		ensure!(palettes.len() >= 14, "Not enough palettes in {}", name);
+
+		Ok(Palettes(palettes))
+	}
+}
+
+/// The lighting tables from COLORMAP. Each maps palette indices to the nearest colour at one
+/// light level, from full bright (0) to darkest (31), followed by the invulnerability map.
+pub struct Colormaps(Vec<[u8; 256]>);
+
+impl Deref for Colormaps {
+	type Target = [[u8; 256]];
+
+	fn deref(&self) -> &Self::Target {
+		&self.0
+	}
+}
+
+impl Asset for Colormaps {
+	type Data = Self;
+	type Intermediate = Self;
+	const NAME: &'static str = "Colormaps";
+
+	fn import(name: &str, source: &impl DataSource) -> anyhow::Result<Self::Intermediate> {
+		let data = source.load(name)?;
+		let colormaps: Vec<[u8; 256]> = data
+			.chunks_exact(256)
+			.map(|chunk| {
+				let mut colormap = [0u8; 256];
+				colormap.copy_from_slice(chunk);
+				colormap
+			})
+			.collect();
+
+		ensure!(colormaps.len() >= 34, "Not enough colormaps in {}", name);
+
+		Ok(Colormaps(colormaps))
+	}
+}
+
+pub const NUM_PLAYER_TRANSLATIONS: usize = 4;
+
+/// Palette remappings that turn the green ramp at 0x70-0x7F into the other player colours:
+/// none (green), indigo, brown and red.
+pub fn player_translations() -> Vec<[u8; 256]> {
+	let ramp_starts: [u8; NUM_PLAYER_TRANSLATIONS] = [0x70, 0x60, 0x40, 0x20];
+
+	ramp_starts
+		.iter()
+		.map(|&ramp_start| {
+			let mut translation = [0u8; 256];
+
+			for (i, index) in translation.iter_mut().enumerate() {
+				*index = if (0x70..=0x7F).contains(&i) {
+					ramp_start + (i & 0xF) as u8
+				} else {
+					i as u8
+				};
+			}
+
+			translation
+		})
+		.collect()
+}
+
 pub struct Image {
 	pub data: Vec<IAColor>,
 	pub size: [usize; 2],
@@ -1,11 +1,17 @@
+use crate::input::{self, AnalogAxis, Bindings, Button};
+use anyhow::{anyhow, bail};
 use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Action {
 	Attack,
+	NextWeapon,
+	PreviousWeapon,
+	Run,
+	Strafe,
 	SwitchWeapon(u8),
 	Use,
-	Walk,
 }
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
@@ -16,6 +22,191 @@ pub enum Axis {
 	Yaw,
 }
 
+impl FromStr for Action {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<Action> {
+		Ok(match name.to_ascii_lowercase().as_str() {
+			"attack" => Action::Attack,
+			"nextweapon" => Action::NextWeapon,
+			"prevweapon" => Action::PreviousWeapon,
+			"run" => Action::Run,
+			"strafe" => Action::Strafe,
+			"use" => Action::Use,
+			other => match other.strip_prefix("weapon") {
+				Some(number) => Action::SwitchWeapon(
+					number
+						.parse()
+						.map_err(|_| anyhow!("Invalid weapon number: {}", number))?,
+				),
+				None => bail!("Unknown action: {}", name),
+			},
+		})
+	}
+}
+
+impl fmt::Display for Action {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Action::Attack => write!(f, "attack"),
+			Action::NextWeapon => write!(f, "nextweapon"),
+			Action::PreviousWeapon => write!(f, "prevweapon"),
+			Action::Run => write!(f, "run"),
+			Action::Strafe => write!(f, "strafe"),
+			Action::SwitchWeapon(number) => write!(f, "weapon{}", number),
+			Action::Use => write!(f, "use"),
+		}
+	}
+}
+
+impl FromStr for Axis {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<Axis> {
+		Ok(match name.to_ascii_lowercase().as_str() {
+			"forward" => Axis::Forward,
+			"pitch" => Axis::Pitch,
+			"strafe" => Axis::Strafe,
+			"yaw" => Axis::Yaw,
+			_ => bail!("Unknown axis: {}", name),
+		})
+	}
+}
+
+impl fmt::Display for Axis {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Axis::Forward => write!(f, "forward"),
+			Axis::Pitch => write!(f, "pitch"),
+			Axis::Strafe => write!(f, "strafe"),
+			Axis::Yaw => write!(f, "yaw"),
+		}
+	}
+}
+
+/// What a button can be bound to: an action, or one direction of an axis.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum BindTarget {
+	Action(Action),
+	Axis(Axis, bool),
+}
+
+/// Actions are written by name, axes as `+axis` or `-axis`.
+impl FromStr for BindTarget {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<BindTarget> {
+		Ok(if let Some(axis) = name.strip_prefix('+') {
+			BindTarget::Axis(axis.parse()?, true)
+		} else if let Some(axis) = name.strip_prefix('-') {
+			BindTarget::Axis(axis.parse()?, false)
+		} else {
+			BindTarget::Action(name.parse()?)
+		})
+	}
+}
+
+impl fmt::Display for BindTarget {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			BindTarget::Action(action) => fmt::Display::fmt(action, f),
+			BindTarget::Axis(axis, true) => write!(f, "+{}", axis),
+			BindTarget::Axis(axis, false) => write!(f, "-{}", axis),
+		}
+	}
+}
+
+/// Binds a button, after unbinding it from anything else.
+pub fn bind(bindings: &mut Bindings<Action, Axis>, button: Button, target: BindTarget) {
+	bindings.unbind(button);
+
+	match target {
+		BindTarget::Action(action) => bindings.bind_action(action, button),
+		BindTarget::Axis(axis, positive) => bindings.bind_axis_button(axis, button, positive),
+	}
+}
+
+/// The buttons that are bound to a target.
+pub fn bound_buttons(bindings: &Bindings<Action, Axis>, target: BindTarget) -> Vec<Button> {
+	match target {
+		BindTarget::Action(action) => bindings
+			.actions()
+			.find(|(a, _)| **a == action)
+			.map_or_else(Vec::new, |(_, buttons)| buttons.to_vec()),
+		BindTarget::Axis(axis, positive) => bindings
+			.axes()
+			.find_map(|(a, binding)| match binding {
+				input::Axis::Emulated { pos, neg } if *a == axis => {
+					Some(if positive { pos } else { neg })
+				}
+				_ => None,
+			})
+			.map_or_else(Vec::new, |buttons| buttons.clone()),
+	}
+}
+
+/// The name of each bound button or analog axis with what it's bound to, sorted.
+pub fn binding_list(bindings: &Bindings<Action, Axis>) -> Vec<(String, String)> {
+	let mut list = Vec::new();
+
+	for (action, buttons) in bindings.actions() {
+		for button in buttons {
+			list.push((button.to_string(), action.to_string()));
+		}
+	}
+
+	for (axis, binding) in bindings.axes() {
+		match binding {
+			input::Axis::Emulated { pos, neg } => {
+				for button in pos {
+					list.push((
+						button.to_string(),
+						BindTarget::Axis(*axis, true).to_string(),
+					));
+				}
+
+				for button in neg {
+					list.push((
+						button.to_string(),
+						BindTarget::Axis(*axis, false).to_string(),
+					));
+				}
+			}
+			input::Axis::Mouse {
+				axis: mouse_axis,
+				scale,
+			} => {
+				list.push((
+					mouse_axis.to_string(),
+					format!("{} (scale {})", axis, scale),
+				));
+			}
+			input::Axis::Gamepad {
+				axis: gamepad_axis,
+				scale,
+				deadzone,
+				curve,
+				invert,
+			} => {
+				list.push((
+					format!(
+						"{}{}",
+						if *invert { "-" } else { "" },
+						AnalogAxis::Gamepad(*gamepad_axis)
+					),
+					format!(
+						"{} (scale {}, deadzone {}, curve {})",
+						axis, scale, deadzone, curve
+					),
+				));
+			}
+		}
+	}
+
+	list.sort();
+	list
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct UserCommand {
 	pub action_attack: bool,
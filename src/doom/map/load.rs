@@ -67,6 +67,27 @@ impl Asset for Map {
 	}
 }
 
+/// The sky texture the original used for a map: one per episode in Doom, and in Doom 2 a
+/// different one from MAP12 and from MAP21.
+pub fn sky_for_map(map_name: &str) -> &'static str {
+	let name = map_name.to_ascii_uppercase();
+
+	if let Some(number) = name.strip_prefix("MAP").and_then(|n| n.parse::<u32>().ok()) {
+		match number {
+			0..=11 => "SKY1",
+			12..=20 => "SKY2",
+			_ => "SKY3",
+		}
+	} else {
+		match name.as_bytes() {
+			[b'E', b'2', b'M', ..] => "SKY2",
+			[b'E', b'3', b'M', ..] => "SKY3",
+			[b'E', b'4', b'M', ..] => "SKY4",
+			_ => "SKY1",
+		}
+	}
+}
+
 pub fn build_map(
 	map_data: MapData,
 	sky_name: &str,
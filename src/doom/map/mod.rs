@@ -8,11 +8,14 @@ use crate::{
 	doom::{
 		components::{SpawnOnCeiling, SpawnPoint, Transform},
 		data::{LinedefTypes, MobjTypes, SectorTypes},
+		game::{self, GameRules},
+		image::NUM_PLAYER_TRANSLATIONS,
 		map::{
 			load::LinedefFlags,
 			textures::{Flat, TextureType, Wall},
 		},
 		physics::{BoxCollider, SolidMask},
+		render::sprite::SpriteRender,
 	},
 	geometry::{Angle, Interval, Line2, Plane2, Plane3, Side, AABB2, AABB3},
 	quadtree::Quadtree,
@@ -201,6 +204,84 @@ impl Map {
 		}
 	}
 
+	/// Finds how far a sound made in a sector carries, the same way monsters are alerted.
+	/// Sound spreads through two-sided linedefs that are open between floor and ceiling, so
+	/// closed doors stop it, and it can cross at most one linedef with the BLOCKSOUND flag.
+	/// For each sector, returns how many sound-blocking linedefs the sound crossed to get there,
+	/// or `None` if it doesn't get there at all.
+	pub fn sound_propagation(
+		&self,
+		map_dynamic: &MapDynamic,
+		sector_index: usize,
+	) -> Vec<Option<usize>> {
+		let mut blocks_crossed = vec![None; self.sectors.len()];
+		let mut stack = vec![(sector_index, 0)];
+
+		while let Some((index, blocks)) = stack.pop() {
+			match blocks_crossed[index] {
+				Some(old_blocks) if old_blocks <= blocks => continue,
+				_ => blocks_crossed[index] = Some(blocks),
+			}
+
+			for &linedef_index in self.sectors[index].linedefs.iter() {
+				let linedef = &self.linedefs[linedef_index];
+				let other_index = match &linedef.sidedefs {
+					[Some(front), Some(back)] if front.sector_index == index => back.sector_index,
+					[Some(front), Some(_)] => front.sector_index,
+					_ => continue,
+				};
+
+				let opening = map_dynamic.sectors[index]
+					.interval
+					.intersection(map_dynamic.sectors[other_index].interval);
+
+				if opening.is_empty_or_point() {
+					continue;
+				}
+
+				let blocks = blocks + linedef.flags.intersects(LinedefFlags::BLOCKSOUND) as usize;
+
+				if blocks < 2 {
+					stack.push((other_index, blocks));
+				}
+			}
+		}
+
+		blocks_crossed
+	}
+
+	/// How many sound-blocking linedefs a sound made in a sector crosses, given the
+	/// propagation from the listener's sector. A closed sector, like a door that is about to
+	/// open, still makes sound into the sectors next to it, whatever its own height is.
+	pub fn sound_blocks(
+		&self,
+		propagation: &[Option<usize>],
+		sector_index: usize,
+	) -> Option<usize> {
+		if let Some(blocks) = propagation[sector_index] {
+			return Some(blocks);
+		}
+
+		self.sectors[sector_index]
+			.linedefs
+			.iter()
+			.filter_map(|&linedef_index| {
+				let linedef = &self.linedefs[linedef_index];
+				let other_index = match &linedef.sidedefs {
+					[Some(front), Some(back)] if front.sector_index == sector_index => {
+						back.sector_index
+					}
+					[Some(front), Some(_)] => front.sector_index,
+					_ => return None,
+				};
+
+				let blocks = propagation[other_index]?
+					+ linedef.flags.intersects(LinedefFlags::BLOCKSOUND) as usize;
+				Some(blocks).filter(|&blocks| blocks < 2)
+			})
+			.min()
+	}
+
 	pub fn traverse_nodes<F: FnMut(NodeChild)>(&self, node: NodeChild, bbox: &AABB2, func: &mut F) {
 		func(node);
 
@@ -286,16 +367,32 @@ pub fn spawn_things(
 	Ok(())
 }
 
-pub fn spawn_player(world: &World) -> anyhow::Result<Entity> {
+pub fn spawn_player(world: &World, player_num: usize) -> anyhow::Result<Entity> {
+	// Deathmatch players spawn at a random deathmatch start, if the map has any
+	let deathmatch = world.fetch::<GameRules>().mode.is_deathmatch();
+	let deathmatch_start = if deathmatch {
+		game::deathmatch_start(world)
+	} else {
+		None
+	};
+
 	// Get spawn point transform
-	let transform = {
+	let transform = if let Some(transform) = deathmatch_start {
+		transform
+	} else {
 		let (transform, spawn_point) =
 			world.system_data::<(ReadStorage<Transform>, ReadStorage<SpawnPoint>)>();
 
 		(&transform, &spawn_point)
 			.join()
-			.find_map(|(t, s)| if s.player_num == 1 { Some(*t) } else { None })
-			.unwrap()
+			.find_map(|(t, s)| {
+				if s.player_num == player_num {
+					Some(*t)
+				} else {
+					None
+				}
+			})
+			.ok_or(anyhow!("No spawn point for player {}", player_num))?
 	};
 
 	// Fetch entity template
@@ -315,10 +412,19 @@ pub fn spawn_player(world: &World) -> anyhow::Result<Entity> {
 	template.add_to_entity(entity, world)?;
 
 	// Set entity transform
-	let (box_collider_component, mut transform_component) =
-		world.system_data::<(ReadStorage<BoxCollider>, WriteStorage<Transform>)>();
+	let (box_collider_component, mut sprite_render_component, mut transform_component) = world
+		.system_data::<(
+			ReadStorage<BoxCollider>,
+			WriteStorage<SpriteRender>,
+			WriteStorage<Transform>,
+		)>();
 	transform_component.insert(entity, transform)?;
 
+	// Each player gets their own colour
+	if let Some(sprite_render) = sprite_render_component.get_mut(entity) {
+		sprite_render.translation = (player_num - 1) % NUM_PLAYER_TRANSLATIONS;
+	}
+
 	// Add to quadtree
 	if let Some(box_collider) = box_collider_component.get(entity) {
 		let transform = transform_component.get(entity).unwrap();
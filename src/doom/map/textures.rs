@@ -240,7 +240,7 @@ impl AssetFormat for RawTexturesFormat {
 }
 
 #[derive(Derivative)]
-#[derivative(Clone(bound = ""), Debug(bound = ""))]
+#[derivative(Clone(bound = ""), Debug(bound = ""), PartialEq(bound = ""))]
 pub enum TextureType<T> {
 	Normal(AssetHandle<T>),
 	Sky,
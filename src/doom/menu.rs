@@ -0,0 +1,102 @@
+use crate::{
+	doom::input::{Action, Axis, BindTarget},
+	input::ButtonCapture,
+};
+use winit::event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent};
+
+/// The controls that can be rebound from the menu, in the order they are shown.
+pub const MENU_TARGETS: [BindTarget; 19] = [
+	BindTarget::Axis(Axis::Forward, true),
+	BindTarget::Axis(Axis::Forward, false),
+	BindTarget::Axis(Axis::Strafe, false),
+	BindTarget::Axis(Axis::Strafe, true),
+	BindTarget::Axis(Axis::Yaw, true),
+	BindTarget::Axis(Axis::Yaw, false),
+	BindTarget::Action(Action::Attack),
+	BindTarget::Action(Action::Use),
+	BindTarget::Action(Action::Run),
+	BindTarget::Action(Action::Strafe),
+	BindTarget::Action(Action::NextWeapon),
+	BindTarget::Action(Action::PreviousWeapon),
+	BindTarget::Action(Action::SwitchWeapon(1)),
+	BindTarget::Action(Action::SwitchWeapon(2)),
+	BindTarget::Action(Action::SwitchWeapon(3)),
+	BindTarget::Action(Action::SwitchWeapon(4)),
+	BindTarget::Action(Action::SwitchWeapon(5)),
+	BindTarget::Action(Action::SwitchWeapon(6)),
+	BindTarget::Action(Action::SwitchWeapon(7)),
+];
+
+/// The options menu for rebinding controls. Escape opens and closes it, Enter waits for the
+/// next button press and binds it to the selected control.
+#[derive(Debug, Default)]
+pub struct ControlsMenu {
+	open: bool,
+	selected: usize,
+}
+
+impl ControlsMenu {
+	pub fn is_open(&self) -> bool {
+		self.open
+	}
+
+	/// Index of the selected control in `MENU_TARGETS`.
+	pub fn selected(&self) -> usize {
+		self.selected
+	}
+
+	/// Handles an event, returns whether the menu used it.
+	pub fn process_event(
+		&mut self,
+		event: &Event<()>,
+		button_capture: &mut ButtonCapture<BindTarget>,
+	) -> bool {
+		let event = match event {
+			Event::WindowEvent { event, .. } => event,
+			_ => return false,
+		};
+
+		match *event {
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(VirtualKeyCode::Escape),
+						..
+					},
+				..
+			} => {
+				self.open = !self.open;
+				true
+			}
+			WindowEvent::KeyboardInput {
+				input: KeyboardInput {
+					state,
+					virtual_keycode,
+					..
+				},
+				..
+			} if self.open => {
+				if let (ElementState::Pressed, Some(key_code)) = (state, virtual_keycode) {
+					match key_code {
+						VirtualKeyCode::Up => {
+							self.selected =
+								(self.selected + MENU_TARGETS.len() - 1) % MENU_TARGETS.len();
+						}
+						VirtualKeyCode::Down => {
+							self.selected = (self.selected + 1) % MENU_TARGETS.len();
+						}
+						VirtualKeyCode::Return | VirtualKeyCode::NumpadEnter => {
+							button_capture.start(MENU_TARGETS[self.selected]);
+						}
+						_ => {}
+					}
+				}
+
+				true
+			}
+			WindowEvent::MouseInput { .. } | WindowEvent::MouseWheel { .. } if self.open => true,
+			_ => false,
+		}
+	}
+}
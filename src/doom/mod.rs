@@ -2,12 +2,18 @@ pub mod client;
 pub mod components;
 pub mod data;
 pub mod door;
+pub mod effects;
+pub mod game;
 pub mod image;
 pub mod input;
 pub mod light;
 pub mod map;
+pub mod menu;
+pub mod music;
 pub mod physics;
 pub mod render;
+pub mod replication;
+pub mod server;
 pub mod sound;
 pub mod sprite;
 pub mod update;
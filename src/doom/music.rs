@@ -0,0 +1,929 @@
+use crate::{
+	assets::{Asset, DataSource},
+	audio::{compressed_format, DecoderSource, Mixer, OUTPUT_RATE},
+	doom::wad::WadLoader,
+	opl::{Opl, CHANNEL_OPERATORS},
+};
+use anyhow::{bail, ensure, Context};
+use byteorder::{ReadBytesExt, BE, LE};
+use rodio::Source;
+use specs::{ReadExpect, World, WriteExpect};
+use std::{
+	io::{Cursor, Read},
+	sync::Arc,
+	time::Duration,
+};
+
+// MUS ticks per second
+const MUS_RATE: f64 = 140.0;
+
+// Doom 2 music, by map number
+const DOOM2_MUSIC: [&str; 32] = [
+	"RUNNIN", "STALKS", "COUNTD", "BETWEE", "DOOM", "THE_DA", "SHAWN", "DDTBLU", "IN_CIT", "DEAD",
+	"STLKS2", "THEDA2", "DOOM2", "DDTBL2", "RUNNI2", "DEAD2", "STLKS3", "ROMERO", "SHAWN2",
+	"MESSAG", "COUNT2", "DDTBL3", "AMPIE", "THEDA3", "ADRIAN", "MESSG2", "ROMER2", "TENSE",
+	"SHAWN3", "OPENIN", "EVIL", "ULTIMA",
+];
+
+// Episode 4 of Ultimate Doom reuses music from the other episodes
+const EPISODE4_MUSIC: [&str; 9] = [
+	"E3M4", "E3M2", "E3M3", "E1M5", "E2M7", "E2M4", "E2M6", "E2M5", "E1M9",
+];
+
+/// The music lump that plays on a map, if the map name has one.
+pub fn map_music(map_name: &str) -> Option<String> {
+	let map_name = map_name.to_ascii_uppercase();
+
+	if let Some(number) = map_name.strip_prefix("MAP") {
+		let number: usize = number.parse().ok()?;
+		let name = DOOM2_MUSIC.get(number.checked_sub(1)?)?;
+		return Some(format!("D_{}", name));
+	}
+
+	let bytes = map_name.as_bytes();
+
+	match bytes {
+		[b'E', episode, b'M', map]
+			if episode.is_ascii_digit() && map.is_ascii_digit() && *map != b'0' =>
+		{
+			if *episode == b'4' {
+				Some(format!("D_{}", EPISODE4_MUSIC[(map - b'1') as usize]))
+			} else {
+				Some(format!("D_{}", map_name))
+			}
+		}
+		_ => None,
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MidiEvent {
+	NoteOff {
+		channel: u8,
+		note: u8,
+	},
+	NoteOn {
+		channel: u8,
+		note: u8,
+		velocity: u8,
+	},
+	Controller {
+		channel: u8,
+		controller: u8,
+		value: u8,
+	},
+	ProgramChange {
+		channel: u8,
+		program: u8,
+	},
+	/// Between -8192 and 8191, the full range is 2 semitones.
+	PitchBend {
+		channel: u8,
+		value: i16,
+	},
+}
+
+/// A piece of music, read from either a MUS or a MIDI lump.
+#[derive(Clone, Debug)]
+pub struct Song {
+	/// Each event with the time in seconds since the previous one.
+	events: Vec<(f64, MidiEvent)>,
+	/// Time from the last event until the end.
+	end_delay: f64,
+}
+
+impl Asset for Song {
+	type Data = Self;
+	type Intermediate = Self;
+	const NAME: &'static str = "Song";
+
+	fn import(name: &str, source: &impl DataSource) -> anyhow::Result<Self::Intermediate> {
+		read_song(&source.load(name)?)
+	}
+}
+
+fn read_song(data: &[u8]) -> anyhow::Result<Song> {
+	let song = if data.starts_with(b"MUS\x1A") {
+		read_mus(data)?
+	} else if data.starts_with(b"MThd") {
+		read_midi(data)?
+	} else {
+		bail!("Unknown music format")
+	};
+
+	// Looping a song that takes no time would never finish a buffer
+	let length = song.events.iter().map(|(delay, _)| delay).sum::<f64>() + song.end_delay;
+	ensure!(
+		song.events.is_empty() || length > 0.0,
+		"Song has events but no length"
+	);
+
+	Ok(song)
+}
+
+fn read_variable_length(reader: &mut impl Read) -> anyhow::Result<u32> {
+	let mut value = 0u32;
+
+	for _ in 0..4 {
+		let byte = reader.read_u8()?;
+		value = (value << 7) | (byte & 0x7F) as u32;
+
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+
+	bail!("Variable-length number is too long")
+}
+
+fn read_mus(data: &[u8]) -> anyhow::Result<Song> {
+	let mut reader = Cursor::new(data);
+	reader.set_position(6);
+	let score_start = reader.read_u16::<LE>()?;
+	reader.set_position(score_start as u64);
+
+	// Notes without a volume reuse the previous one on the channel
+	let mut volumes = [127u8; 16];
+	let mut events = Vec::new();
+	let mut delay = 0.0;
+
+	loop {
+		let descriptor = reader.read_u8()?;
+
+		// MUS has percussion on channel 15, MIDI on channel 9
+		let channel = match descriptor & 0x0F {
+			15 => 9,
+			c if c >= 9 => c + 1,
+			c => c,
+		};
+
+		let event = match (descriptor >> 4) & 0x07 {
+			0 => Some(MidiEvent::NoteOff {
+				channel,
+				note: reader.read_u8()? & 0x7F,
+			}),
+			1 => {
+				let note = reader.read_u8()?;
+
+				if note & 0x80 != 0 {
+					volumes[channel as usize] = reader.read_u8()? & 0x7F;
+				}
+
+				Some(MidiEvent::NoteOn {
+					channel,
+					note: note & 0x7F,
+					velocity: volumes[channel as usize],
+				})
+			}
+			2 => Some(MidiEvent::PitchBend {
+				channel,
+				value: (reader.read_u8()? as i16 - 128) * 64,
+			}),
+			3 => {
+				let controller = match reader.read_u8()? {
+					10 => 120, // All sounds off
+					11 => 123, // All notes off
+					12 => 126, // Mono
+					13 => 127, // Poly
+					14 => 121, // Reset all controllers
+					_ => 0xFF,
+				};
+
+				if controller == 0xFF {
+					None
+				} else {
+					Some(MidiEvent::Controller {
+						channel,
+						controller,
+						value: 0,
+					})
+				}
+			}
+			4 => {
+				let controller = reader.read_u8()?;
+				let value = reader.read_u8()? & 0x7F;
+
+				match controller {
+					0 => Some(MidiEvent::ProgramChange {
+						channel,
+						program: value,
+					}),
+					1..=9 => Some(MidiEvent::Controller {
+						channel,
+						controller: [0, 1, 7, 10, 11, 91, 93, 64, 67][controller as usize - 1],
+						value,
+					}),
+					_ => None,
+				}
+			}
+			5 => None, // End of measure
+			6 => break,
+			_ => bail!("Unknown MUS event type"),
+		};
+
+		if let Some(event) = event {
+			events.push((delay, event));
+			delay = 0.0;
+		}
+
+		if descriptor & 0x80 != 0 {
+			delay += read_variable_length(&mut reader)? as f64 / MUS_RATE;
+		}
+	}
+
+	Ok(Song {
+		events,
+		end_delay: delay,
+	})
+}
+
+enum TrackEvent {
+	Midi(MidiEvent),
+	Tempo(u32),
+	End,
+}
+
+fn read_midi(data: &[u8]) -> anyhow::Result<Song> {
+	let mut reader = Cursor::new(data);
+	let read_chunk = |reader: &mut Cursor<&[u8]>| -> anyhow::Result<([u8; 4], Vec<u8>)> {
+		let mut id = [0u8; 4];
+		reader.read_exact(&mut id)?;
+		let length = reader.read_u32::<BE>()?;
+		let mut data = vec![0u8; length as usize];
+		reader.read_exact(&mut data)?;
+		Ok((id, data))
+	};
+
+	let (_, header) = read_chunk(&mut reader)?;
+	let mut header = Cursor::new(header);
+	let _format = header.read_u16::<BE>()?;
+	let track_count = header.read_u16::<BE>()?;
+	let division = header.read_u16::<BE>()?;
+	ensure!(division & 0x8000 == 0, "SMPTE timing is not supported");
+	ensure!(division != 0, "Invalid time division");
+
+	// Gather the events of all tracks, in order of time
+	let mut track_events = Vec::new();
+
+	for _ in 0..track_count {
+		let (id, track) = read_chunk(&mut reader)?;
+
+		if &id != b"MTrk" {
+			continue;
+		}
+
+		let mut reader = Cursor::new(track.as_slice());
+		let mut time = 0u64;
+		let mut running_status = 0u8;
+
+		while (reader.position() as usize) < track.len() {
+			time += read_variable_length(&mut reader)? as u64;
+			let mut status = reader.read_u8()?;
+
+			if status < 0x80 {
+				ensure!(running_status != 0, "MIDI data without a status byte");
+				reader.set_position(reader.position() - 1);
+				status = running_status;
+			}
+
+			let channel = status & 0x0F;
+			let event = match status {
+				0xFF => {
+					let meta_type = reader.read_u8()?;
+					let length = read_variable_length(&mut reader)?;
+					let mut data = vec![0u8; length as usize];
+					reader.read_exact(&mut data)?;
+
+					match meta_type {
+						0x2F => {
+							track_events.push((time, TrackEvent::End));
+							break;
+						}
+						0x51 if data.len() == 3 => TrackEvent::Tempo(
+							(data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32,
+						),
+						_ => continue,
+					}
+				}
+				0xF0 | 0xF7 => {
+					let length = read_variable_length(&mut reader)?;
+					reader.set_position(reader.position() + length as u64);
+					continue;
+				}
+				0x80..=0xEF => {
+					running_status = status;
+
+					match status >> 4 {
+						0x8 => {
+							let note = reader.read_u8()? & 0x7F;
+							reader.read_u8()?;
+							TrackEvent::Midi(MidiEvent::NoteOff { channel, note })
+						}
+						0x9 => TrackEvent::Midi(MidiEvent::NoteOn {
+							channel,
+							note: reader.read_u8()? & 0x7F,
+							velocity: reader.read_u8()? & 0x7F,
+						}),
+						0xB => TrackEvent::Midi(MidiEvent::Controller {
+							channel,
+							controller: reader.read_u8()? & 0x7F,
+							value: reader.read_u8()? & 0x7F,
+						}),
+						0xC => TrackEvent::Midi(MidiEvent::ProgramChange {
+							channel,
+							program: reader.read_u8()? & 0x7F,
+						}),
+						0xE => {
+							let low = (reader.read_u8()? & 0x7F) as i16;
+							let high = (reader.read_u8()? & 0x7F) as i16;
+							TrackEvent::Midi(MidiEvent::PitchBend {
+								channel,
+								value: ((high << 7) | low) - 8192,
+							})
+						}
+						// Aftertouch and channel pressure aren't used
+						0xA => {
+							reader.read_u16::<BE>()?;
+							continue;
+						}
+						_ => {
+							reader.read_u8()?;
+							continue;
+						}
+					}
+				}
+				_ => bail!("Unknown MIDI status byte {:#x}", status),
+			};
+
+			track_events.push((time, event));
+		}
+	}
+
+	// Sorting is stable, so events at the same time stay in track order
+	track_events.sort_by_key(|(time, _)| *time);
+
+	let mut events = Vec::new();
+	let mut tempo = 500_000; // Microseconds per quarter note
+	let mut previous_time = 0;
+	let mut delay = 0.0;
+
+	for (time, event) in track_events {
+		delay += (time - previous_time) as f64 * tempo as f64 / 1e6 / division as f64;
+		previous_time = time;
+
+		match event {
+			TrackEvent::Midi(event) => {
+				events.push((delay, event));
+				delay = 0.0;
+			}
+			TrackEvent::Tempo(new_tempo) => tempo = new_tempo,
+			TrackEvent::End => {}
+		}
+	}
+
+	Ok(Song {
+		events,
+		end_delay: delay,
+	})
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct InstrumentOperator {
+	characteristic: u8,
+	attack: u8,
+	sustain: u8,
+	waveform: u8,
+	key_scale: u8,
+	level: u8,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct InstrumentVoice {
+	modulator: InstrumentOperator,
+	feedback: u8,
+	carrier: InstrumentOperator,
+	note_offset: i16,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Instrument {
+	fixed_note: Option<u8>,
+	fine_tune: u8,
+	voices: [InstrumentVoice; 2],
+	double_voice: bool,
+}
+
+/// The OPL instrument bank. The first 128 are the General MIDI instruments,
+/// the rest are the percussion notes 35 to 81.
+pub struct GenMidi {
+	instruments: Vec<Instrument>,
+}
+
+impl Asset for GenMidi {
+	type Data = Self;
+	type Intermediate = Self;
+	const NAME: &'static str = "GenMidi";
+
+	fn import(name: &str, source: &impl DataSource) -> anyhow::Result<Self::Intermediate> {
+		let mut reader = Cursor::new(source.load(name)?);
+		let mut signature = [0u8; 8];
+		reader.read_exact(&mut signature)?;
+		ensure!(&signature == b"#OPL_II#", "No GENMIDI signature found");
+
+		let read_operator = |reader: &mut Cursor<Vec<u8>>| -> anyhow::Result<InstrumentOperator> {
+			Ok(InstrumentOperator {
+				characteristic: reader.read_u8()?,
+				attack: reader.read_u8()?,
+				sustain: reader.read_u8()?,
+				waveform: reader.read_u8()?,
+				key_scale: reader.read_u8()?,
+				level: reader.read_u8()?,
+			})
+		};
+		let read_voice = |reader: &mut Cursor<Vec<u8>>| -> anyhow::Result<InstrumentVoice> {
+			let modulator = read_operator(reader)?;
+			let feedback = reader.read_u8()?;
+			let carrier = read_operator(reader)?;
+			reader.read_u8()?;
+			let note_offset = reader.read_i16::<LE>()?;
+
+			Ok(InstrumentVoice {
+				modulator,
+				feedback,
+				carrier,
+				note_offset,
+			})
+		};
+
+		let instruments = (0..175)
+			.map(|_| {
+				let flags = reader.read_u16::<LE>()?;
+				let fine_tune = reader.read_u8()?;
+				let fixed_note = reader.read_u8()?;
+
+				Ok(Instrument {
+					fixed_note: if flags & 0x01 != 0 {
+						Some(fixed_note)
+					} else {
+						None
+					},
+					fine_tune,
+					voices: [read_voice(&mut reader)?, read_voice(&mut reader)?],
+					double_voice: flags & 0x04 != 0,
+				})
+			})
+			.collect::<anyhow::Result<_>>()?;
+
+		Ok(GenMidi { instruments })
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+struct MidiChannel {
+	program: u8,
+	volume: u8,
+	expression: u8,
+	pan: u8,
+	/// In semitones.
+	bend: f64,
+}
+
+impl Default for MidiChannel {
+	fn default() -> MidiChannel {
+		MidiChannel {
+			program: 0,
+			volume: 100,
+			expression: 127,
+			pan: 64,
+			bend: 0.0,
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Voice {
+	/// The MIDI channel and note that are playing, `None` if the voice is free.
+	playing: Option<(u8, u8)>,
+	instrument: usize,
+	/// Which of the instrument's two voices this is.
+	secondary: bool,
+	velocity: u8,
+	/// When the voice started playing, the oldest one is replaced when all are in use.
+	started: u64,
+	/// Last value written to the 0xB0 register, to key off without changing the pitch.
+	key_block: u8,
+}
+
+const VOICE_COUNT: usize = 18;
+
+/// Plays MIDI events on an OPL3 chip, with GENMIDI instruments.
+pub struct OplSynth {
+	channels: [MidiChannel; 16],
+	counter: u64,
+	genmidi: Arc<GenMidi>,
+	opl: Opl,
+	voices: [Voice; VOICE_COUNT],
+}
+
+impl OplSynth {
+	pub fn new(genmidi: Arc<GenMidi>, sample_rate: u32) -> OplSynth {
+		let mut opl = Opl::new(sample_rate);
+		opl.write(0x105, 0x01); // OPL3 mode
+		opl.write(0x01, 0x20); // Enable waveforms
+
+		OplSynth {
+			channels: [MidiChannel::default(); 16],
+			counter: 0,
+			genmidi,
+			opl,
+			voices: [Voice::default(); VOICE_COUNT],
+		}
+	}
+
+	pub fn generate(&mut self, buffer: &mut [f32]) {
+		self.opl.generate(buffer);
+	}
+
+	pub fn play_event(&mut self, event: MidiEvent) {
+		match event {
+			MidiEvent::NoteOn {
+				channel,
+				note,
+				velocity: 0,
+			}
+			| MidiEvent::NoteOff { channel, note } => self.note_off(channel, note),
+			MidiEvent::NoteOn {
+				channel,
+				note,
+				velocity,
+			} => self.note_on(channel, note, velocity),
+			MidiEvent::Controller {
+				channel,
+				controller,
+				value,
+			} => {
+				let midi_channel = &mut self.channels[channel as usize];
+
+				match controller {
+					7 => midi_channel.volume = value,
+					10 => midi_channel.pan = value,
+					11 => midi_channel.expression = value,
+					120 | 123 => {
+						for index in 0..VOICE_COUNT {
+							if matches!(self.voices[index].playing, Some((c, _)) if c == channel) {
+								self.release_voice(index);
+							}
+						}
+						return;
+					}
+					121 => {
+						*midi_channel = MidiChannel {
+							program: midi_channel.program,
+							..MidiChannel::default()
+						};
+					}
+					_ => return,
+				}
+
+				self.update_channel(channel);
+			}
+			MidiEvent::ProgramChange { channel, program } => {
+				self.channels[channel as usize].program = program;
+			}
+			MidiEvent::PitchBend { channel, value } => {
+				self.channels[channel as usize].bend = value as f64 / 8192.0 * 2.0;
+				self.update_channel(channel);
+			}
+		}
+	}
+
+	/// Releases all notes, used when the song loops.
+	pub fn notes_off(&mut self) {
+		for index in 0..VOICE_COUNT {
+			if self.voices[index].playing.is_some() {
+				self.release_voice(index);
+			}
+		}
+	}
+
+	fn note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+		let instrument = if channel == 9 {
+			match note {
+				35..=81 => 128 + (note - 35) as usize,
+				_ => return,
+			}
+		} else {
+			self.channels[channel as usize].program as usize
+		};
+
+		let voice_count = if self.genmidi.instruments[instrument].double_voice {
+			2
+		} else {
+			1
+		};
+
+		for secondary in [false, true].iter().take(voice_count) {
+			let index = self.allocate_voice();
+			self.counter += 1;
+			self.voices[index] = Voice {
+				playing: Some((channel, note)),
+				instrument,
+				secondary: *secondary,
+				velocity,
+				started: self.counter,
+				key_block: 0,
+			};
+			self.program_voice(index);
+		}
+	}
+
+	fn note_off(&mut self, channel: u8, note: u8) {
+		for index in 0..VOICE_COUNT {
+			if self.voices[index].playing == Some((channel, note)) {
+				self.release_voice(index);
+			}
+		}
+	}
+
+	/// Finds a free voice, or takes the oldest one.
+	fn allocate_voice(&mut self) -> usize {
+		if let Some(index) = self.voices.iter().position(|v| v.playing.is_none()) {
+			return index;
+		}
+
+		let index = (0..VOICE_COUNT)
+			.min_by_key(|&index| self.voices[index].started)
+			.unwrap();
+		self.release_voice(index);
+		index
+	}
+
+	fn release_voice(&mut self, index: usize) {
+		let voice = &mut self.voices[index];
+		voice.playing = None;
+		voice.key_block &= !0x20;
+		let key_block = voice.key_block;
+		let (bank, channel) = voice_registers(index);
+		self.opl.write(bank | (0xB0 + channel), key_block);
+	}
+
+	fn update_channel(&mut self, channel: u8) {
+		for index in 0..VOICE_COUNT {
+			if matches!(self.voices[index].playing, Some((c, _)) if c == channel) {
+				self.set_volume(index);
+				self.set_frequency(index);
+			}
+		}
+	}
+
+	fn program_voice(&mut self, index: usize) {
+		let voice = self.voices[index];
+		let instrument = self.genmidi.instruments[voice.instrument];
+		let data = instrument.voices[voice.secondary as usize];
+		let (bank, channel) = voice_registers(index);
+		let modulator = bank | CHANNEL_OPERATORS[channel as usize];
+		let carrier = modulator + 3;
+
+		// Silence it first, in case it was still sounding
+		self.opl.write(bank | (0xB0 + channel), 0);
+
+		for (operator, op_data) in [(modulator, data.modulator), (carrier, data.carrier)].iter() {
+			self.opl.write(0x20 + operator, op_data.characteristic);
+			self.opl.write(0x60 + operator, op_data.attack);
+			self.opl.write(0x80 + operator, op_data.sustain);
+			self.opl.write(0xE0 + operator, op_data.waveform);
+		}
+
+		self.set_volume(index);
+		self.set_frequency(index);
+	}
+
+	fn set_volume(&mut self, index: usize) {
+		let voice = self.voices[index];
+		let midi_channel = match voice.playing {
+			Some((channel, _)) => self.channels[channel as usize],
+			None => return,
+		};
+		let data = self.genmidi.instruments[voice.instrument].voices[voice.secondary as usize];
+		let (bank, channel) = voice_registers(index);
+		let modulator = bank | CHANNEL_OPERATORS[channel as usize];
+		let carrier = modulator + 3;
+
+		// Attenuation in 0.75 dB steps, following the General MIDI volume curve
+		let volume =
+			voice.velocity as f64 * midi_channel.volume as f64 * midi_channel.expression as f64
+				/ (127.0 * 127.0 * 127.0);
+		let attenuation = if volume > 0.0 {
+			(-40.0 * volume.log10() / 0.75).round().min(63.0) as u8
+		} else {
+			63
+		};
+		let level = |op_data: &InstrumentOperator| {
+			(op_data.key_scale & 0xC0) | ((op_data.level & 0x3F) + attenuation).min(0x3F)
+		};
+
+		self.opl.write(0x40 + carrier, level(&data.carrier));
+
+		// With additive synthesis, the modulator is heard too
+		if data.feedback & 0x01 != 0 {
+			self.opl.write(0x40 + modulator, level(&data.modulator));
+		} else {
+			self.opl.write(
+				0x40 + modulator,
+				(data.modulator.key_scale & 0xC0) | (data.modulator.level & 0x3F),
+			);
+		}
+
+		let pan = match midi_channel.pan {
+			0..=47 => 0x10,
+			48..=80 => 0x30,
+			_ => 0x20,
+		};
+		self.opl.write(bank | (0xC0 + channel), data.feedback | pan);
+	}
+
+	fn set_frequency(&mut self, index: usize) {
+		let voice = self.voices[index];
+		let (midi_channel, note) = match voice.playing {
+			Some((channel, note)) => (self.channels[channel as usize], note),
+			None => return,
+		};
+		let instrument = self.genmidi.instruments[voice.instrument];
+		let data = instrument.voices[voice.secondary as usize];
+		let (bank, channel) = voice_registers(index);
+
+		let mut note = instrument.fixed_note.unwrap_or(note) as f64
+			+ data.note_offset as f64
+			+ midi_channel.bend;
+
+		// The second voice is detuned, in 1/64 semitones
+		if voice.secondary {
+			note += (instrument.fine_tune as f64 - 128.0) / 64.0;
+		}
+
+		let frequency = 440.0 * ((note - 69.0) / 12.0).exp2();
+		let (frequency_number, block) = frequency_registers(frequency);
+
+		let key_block = 0x20 | (block << 2) | (frequency_number >> 8) as u8;
+		self.voices[index].key_block = key_block;
+		self.opl
+			.write(bank | (0xA0 + channel), frequency_number as u8);
+		self.opl.write(bank | (0xB0 + channel), key_block);
+	}
+}
+
+/// The register bank and channel number of a voice.
+fn voice_registers(index: usize) -> (u16, u16) {
+	(((index / 9) as u16) << 8, (index % 9) as u16)
+}
+
+/// The OPL frequency number and block for a frequency in Hz.
+fn frequency_registers(frequency: f64) -> (u16, u8) {
+	for block in 0..8 {
+		let frequency_number = (frequency * (20.0 - block as f64).exp2() / 49716.0).round();
+
+		if frequency_number < 1024.0 {
+			return (frequency_number as u16, block);
+		}
+	}
+
+	(1023, 7)
+}
+
+// Samples to generate at a time, at most
+const MAX_FRAMES: usize = 256;
+
+/// Plays a song through the synthesizer, as a stereo audio source.
+pub struct MusicSource {
+	buffer: Vec<f32>,
+	buffer_position: usize,
+	event_index: usize,
+	looping: bool,
+	song: Song,
+	synth: OplSynth,
+	/// Time in seconds until the next event.
+	wait: f64,
+}
+
+impl MusicSource {
+	pub fn new(song: Song, genmidi: Arc<GenMidi>, looping: bool) -> MusicSource {
+		MusicSource {
+			buffer: Vec::new(),
+			buffer_position: 0,
+			event_index: 0,
+			looping,
+			wait: song
+				.events
+				.first()
+				.map_or(song.end_delay, |(delay, _)| *delay),
+			song,
+			synth: OplSynth::new(genmidi, OUTPUT_RATE),
+		}
+	}
+
+	/// Plays the events that are due and generates samples up to the next one.
+	/// Returns false when the song has ended.
+	fn fill_buffer(&mut self) -> bool {
+		while self.wait <= 0.0 {
+			match self.song.events.get(self.event_index) {
+				Some(&(_, event)) => {
+					self.synth.play_event(event);
+					self.event_index += 1;
+					self.wait += self
+						.song
+						.events
+						.get(self.event_index)
+						.map_or(self.song.end_delay, |(delay, _)| *delay);
+				}
+				None if self.looping && !self.song.events.is_empty() => {
+					self.synth.notes_off();
+					self.event_index = 0;
+					self.wait += self.song.events[0].0;
+				}
+				None => return false,
+			}
+		}
+
+		let frames = ((self.wait * OUTPUT_RATE as f64).ceil() as usize).clamp(1, MAX_FRAMES);
+		self.wait -= frames as f64 / OUTPUT_RATE as f64;
+		self.buffer.resize(frames * 2, 0.0);
+		self.synth.generate(&mut self.buffer);
+		self.buffer_position = 0;
+		true
+	}
+}
+
+impl Source for MusicSource {
+	#[inline]
+	fn current_frame_len(&self) -> Option<usize> {
+		None
+	}
+
+	#[inline]
+	fn channels(&self) -> u16 {
+		2
+	}
+
+	#[inline]
+	fn sample_rate(&self) -> u32 {
+		OUTPUT_RATE
+	}
+
+	#[inline]
+	fn total_duration(&self) -> Option<Duration> {
+		None
+	}
+}
+
+impl Iterator for MusicSource {
+	type Item = f32;
+
+	#[inline]
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.buffer_position == self.buffer.len() && !self.fill_buffer() {
+			return None;
+		}
+
+		let sample = self.buffer[self.buffer_position];
+		self.buffer_position += 1;
+		Some(sample)
+	}
+}
+
+/// The music that is currently playing.
+#[derive(Default)]
+pub struct Music {
+	current: Option<String>,
+}
+
+/// Starts playing a music lump, unless it's already playing.
+pub fn play_music(world: &World, name: &str) -> anyhow::Result<()> {
+	let (loader, mixer, mut music) =
+		world.system_data::<(ReadExpect<WadLoader>, ReadExpect<Mixer>, WriteExpect<Music>)>();
+	let name = name.to_ascii_uppercase();
+
+	if music.current.as_ref() == Some(&name) {
+		return Ok(());
+	}
+
+	let data = loader
+		.load(&name)
+		.context(format!("Couldn't load music {}", name))?;
+
+	// Compressed music is streamed, MUS and MIDI go through the synthesizer
+	let source: Box<dyn Source<Item = f32> + Send> = if compressed_format(&data).is_some() {
+		Box::new(
+			DecoderSource::new(data.into(), true)
+				.context(format!("Couldn't load music {}", name))?,
+		)
+	} else {
+		let song = read_song(&data).context(format!("Couldn't load music {}", name))?;
+		let genmidi =
+			Arc::new(GenMidi::import("GENMIDI", &*loader).context("Couldn't load GENMIDI")?);
+		Box::new(MusicSource::new(song, genmidi, true))
+	};
+
+	mixer.set_music(Some(source));
+	music.current = Some(name);
+	Ok(())
+}
@@ -0,0 +1,445 @@
+use crate::{
+	assets::{Asset, AssetFormat},
+	console::Console,
+	doom::{
+		image::{ImageFormat, Palette, RGBAColor},
+		input::{self, Action, Axis, BindTarget},
+		menu::{ControlsMenu, MENU_TARGETS},
+		wad::WadLoader,
+	},
+	input::{Bindings, ButtonCapture},
+	renderer::{AsBytes, RenderContext},
+};
+use anyhow::Context;
+use std::{
+	collections::{hash_map::Entry, HashMap},
+	sync::Arc,
+};
+use vulkano::{
+	buffer::{BufferUsage, CpuBufferPool, ImmutableBuffer},
+	command_buffer::{AutoCommandBufferBuilder, DynamicState},
+	descriptor::{descriptor_set::FixedSizeDescriptorSetsPool, PipelineLayoutAbstract},
+	device::DeviceOwned,
+	format::Format,
+	framebuffer::{RenderPassAbstract, Subpass},
+	image::{Dimensions, ImageViewAccess, ImmutableImage},
+	impl_vertex,
+	pipeline::{
+		vertex::OneVertexOneInstanceDefinition, GraphicsPipeline, GraphicsPipelineAbstract,
+	},
+	sampler::Sampler,
+};
+
+// The console font, STCFN033 to STCFN126
+const FONT_START: u8 = b'!';
+const FONT_END: u8 = b'~';
+
+// Sizes in pixels of the original 320x200 screen, scaled up to fit the window
+const LINE_HEIGHT: f32 = 9.0;
+const SPACE_WIDTH: f32 = 4.0;
+const MARGIN: f32 = 4.0;
+const MENU_COLUMN: f32 = 100.0;
+
+const BACKGROUND_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.8];
+const TEXT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+struct Glyph {
+	image: Arc<dyn ImageViewAccess + Send + Sync>,
+	size: [f32; 2],
+	offset: [f32; 2],
+}
+
+pub struct ConsoleRenderSystem {
+	font: HashMap<char, Glyph>,
+	instance_buffer_pool: CpuBufferPool<InstanceData>,
+	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	sampler: Arc<Sampler>,
+	texture_set_pool: FixedSizeDescriptorSetsPool,
+	vertex_buffer: Arc<ImmutableBuffer<[u8]>>,
+	white: Arc<dyn ImageViewAccess + Send + Sync>,
+}
+
+impl ConsoleRenderSystem {
+	pub fn new(
+		render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+		render_context: &RenderContext,
+		loader: &WadLoader,
+	) -> anyhow::Result<ConsoleRenderSystem> {
+		let device = render_pass.device();
+		let queue = render_context.queues().graphics.clone();
+
+		// Create pipeline
+		let vert = console_vert::Shader::load(device.clone()).context("Couldn't load shader")?;
+		let frag = console_frag::Shader::load(device.clone()).context("Couldn't load shader")?;
+
+		let pipeline = Arc::new(
+			GraphicsPipeline::start()
+				.render_pass(
+					Subpass::from(render_pass.clone(), 0).context("Subpass index out of range")?,
+				)
+				.vertex_input(OneVertexOneInstanceDefinition::<VertexData, InstanceData>::new())
+				.vertex_shader(vert.main_entry_point(), ())
+				.fragment_shader(frag.main_entry_point(), ())
+				.triangle_fan()
+				.viewports_dynamic_scissors_irrelevant(1)
+				.cull_mode_disabled()
+				.blend_alpha_blending()
+				.build(device.clone())
+				.context("Couldn't create pipeline")?,
+		) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+
+		// Create mesh
+		let (vertex_buffer, _future) = ImmutableBuffer::from_iter(
+			vec![
+				VertexData {
+					in_position: [0.0, 0.0],
+				},
+				VertexData {
+					in_position: [1.0, 0.0],
+				},
+				VertexData {
+					in_position: [1.0, 1.0],
+				},
+				VertexData {
+					in_position: [0.0, 1.0],
+				},
+			]
+			.as_bytes()
+			.iter()
+			.copied(),
+			BufferUsage::vertex_buffer(),
+			queue.clone(),
+		)?;
+
+		// Plain texture for the background
+		let (white, _future) = ImmutableImage::from_iter(
+			vec![RGBAColor {
+				r: 0xFF,
+				g: 0xFF,
+				b: 0xFF,
+				a: 0xFF,
+			}]
+			.as_bytes()
+			.iter()
+			.copied(),
+			Dimensions::Dim2d {
+				width: 1,
+				height: 1,
+			},
+			Format::R8G8B8A8Unorm,
+			queue.clone(),
+		)?;
+
+		// Load font
+		let palette = Palette::import("PLAYPAL", loader)?;
+		let mut font = HashMap::new();
+
+		for c in FONT_START..=FONT_END {
+			// Not every WAD has the full range
+			let image = match ImageFormat.import(&format!("STCFN{:03}", c), loader) {
+				Ok(image) => image,
+				Err(_) => continue,
+			};
+
+			let data: Vec<_> = image
+				.data
+				.into_iter()
+				.map(|pixel| {
+					if pixel.a == 0xFF {
+						palette[pixel.i as usize]
+					} else {
+						RGBAColor::default()
+					}
+				})
+				.collect();
+
+			let (texture, _future) = ImmutableImage::from_iter(
+				data.as_bytes().iter().copied(),
+				Dimensions::Dim2d {
+					width: image.size[0] as u32,
+					height: image.size[1] as u32,
+				},
+				Format::R8G8B8A8Unorm,
+				queue.clone(),
+			)?;
+
+			font.insert(
+				c as char,
+				Glyph {
+					image: texture,
+					size: [image.size[0] as f32, image.size[1] as f32],
+					offset: [image.offset[0] as f32, image.offset[1] as f32],
+				},
+			);
+		}
+
+		Ok(ConsoleRenderSystem {
+			font,
+			instance_buffer_pool: CpuBufferPool::new(device.clone(), BufferUsage::vertex_buffer()),
+			texture_set_pool: FixedSizeDescriptorSetsPool::new(
+				pipeline.descriptor_set_layout(0).unwrap().clone(),
+			),
+			pipeline,
+			sampler: super::create_sampler(device, false)?,
+			vertex_buffer,
+			white,
+		})
+	}
+
+	pub fn draw(
+		&mut self,
+		console: &Console,
+		command_buffer_builder: AutoCommandBufferBuilder,
+		dynamic_state: &DynamicState,
+		dimensions: [f32; 2],
+	) -> anyhow::Result<AutoCommandBufferBuilder> {
+		if !console.is_open() {
+			return Ok(command_buffer_builder);
+		}
+
+		let scale = (dimensions[1] / 200.0).floor().max(1.0);
+		let height = (dimensions[1] * 0.5).floor();
+		let mut texts = Vec::new();
+
+		// Input line at the bottom, scrollback above it
+		let line_height = LINE_HEIGHT * scale;
+		let x = MARGIN * scale;
+		let mut y = height - line_height - MARGIN * scale;
+		let input = format!("] {}", console.input());
+		let cursor_x = x + self.text_width(&input[..console.cursor() + 2]) * scale;
+		texts.push(("_".to_owned(), [cursor_x, y]));
+		texts.push((input, [x, y]));
+
+		for line in console.lines().iter().rev().skip(console.scroll()) {
+			y -= line_height;
+
+			if y + line_height < 0.0 {
+				break;
+			}
+
+			texts.push((line.clone(), [x, y]));
+		}
+
+		self.draw_text(
+			command_buffer_builder,
+			dynamic_state,
+			dimensions,
+			scale,
+			height,
+			texts,
+		)
+	}
+
+	pub fn draw_menu(
+		&mut self,
+		menu: &ControlsMenu,
+		button_capture: &ButtonCapture<BindTarget>,
+		bindings: &Bindings<Action, Axis>,
+		command_buffer_builder: AutoCommandBufferBuilder,
+		dynamic_state: &DynamicState,
+		dimensions: [f32; 2],
+	) -> anyhow::Result<AutoCommandBufferBuilder> {
+		if !menu.is_open() {
+			return Ok(command_buffer_builder);
+		}
+
+		let scale = (dimensions[1] / 200.0).floor().max(1.0);
+		let line_height = LINE_HEIGHT * scale;
+		let x = MARGIN * scale;
+		let column = x + MENU_COLUMN * scale;
+		let mut y = MARGIN * scale;
+		let mut texts = Vec::new();
+
+		texts.push(("CONTROLS".to_owned(), [x, y]));
+		y += line_height * 2.0;
+
+		for (i, &target) in MENU_TARGETS.iter().enumerate() {
+			let buttons: Vec<String> = input::bound_buttons(bindings, target)
+				.iter()
+				.map(ToString::to_string)
+				.collect();
+			let marker = if i == menu.selected() { "> " } else { "  " };
+
+			texts.push((format!("{}{}", marker, target), [x, y]));
+			texts.push((buttons.join(", "), [column, y]));
+			y += line_height;
+		}
+
+		y += line_height;
+		let help = match button_capture.target() {
+			Some(target) => format!("Press a button for {}, Escape cancels", target),
+			None => "Enter to rebind, Escape to close".to_owned(),
+		};
+		texts.push((help, [x, y]));
+
+		self.draw_text(
+			command_buffer_builder,
+			dynamic_state,
+			dimensions,
+			scale,
+			dimensions[1],
+			texts,
+		)
+	}
+
+	/// Draws lines of text at positions in pixels, over a background of the given height.
+	fn draw_text(
+		&mut self,
+		mut command_buffer_builder: AutoCommandBufferBuilder,
+		dynamic_state: &DynamicState,
+		dimensions: [f32; 2],
+		scale: f32,
+		height: f32,
+		texts: Vec<(String, [f32; 2])>,
+	) -> anyhow::Result<AutoCommandBufferBuilder> {
+		let to_instance = |position: [f32; 2], size: [f32; 2], color: [f32; 4]| InstanceData {
+			in_offset: [
+				position[0] / dimensions[0] * 2.0 - 1.0,
+				position[1] / dimensions[1] * 2.0 - 1.0,
+			],
+			in_size: [size[0] / dimensions[0] * 2.0, size[1] / dimensions[1] * 2.0],
+			in_color: color,
+		};
+
+		// Background first, so that the text goes over it
+		command_buffer_builder = self.draw_batch(
+			command_buffer_builder,
+			dynamic_state,
+			self.white.clone(),
+			vec![to_instance(
+				[0.0, 0.0],
+				[dimensions[0], height],
+				BACKGROUND_COLOR,
+			)],
+		)?;
+
+		// Group glyphs into batches by texture
+		let mut batches: HashMap<Arc<dyn ImageViewAccess + Send + Sync>, Vec<InstanceData>> =
+			HashMap::new();
+		let mut add_glyph = |glyph: &Glyph, position: [f32; 2]| {
+			let instance_data = to_instance(
+				[
+					position[0] - glyph.offset[0] * scale,
+					position[1] - glyph.offset[1] * scale,
+				],
+				[glyph.size[0] * scale, glyph.size[1] * scale],
+				TEXT_COLOR,
+			);
+
+			match batches.entry(glyph.image.clone()) {
+				Entry::Occupied(mut entry) => {
+					entry.get_mut().push(instance_data);
+				}
+				Entry::Vacant(entry) => {
+					entry.insert(vec![instance_data]);
+				}
+			}
+		};
+
+		for (text, position) in &texts {
+			self.layout_text(text, *position, scale, &mut add_glyph);
+		}
+
+		// Draw the batches
+		for (texture, instance_data) in batches {
+			command_buffer_builder = self.draw_batch(
+				command_buffer_builder,
+				dynamic_state,
+				texture,
+				instance_data,
+			)?;
+		}
+
+		Ok(command_buffer_builder)
+	}
+
+	fn glyph(&self, c: char) -> Option<&Glyph> {
+		// The font usually only has uppercase letters
+		self.font
+			.get(&c)
+			.or_else(|| self.font.get(&c.to_ascii_uppercase()))
+	}
+
+	/// Width of the text in unscaled pixels.
+	fn text_width(&self, text: &str) -> f32 {
+		text.chars()
+			.map(|c| self.glyph(c).map_or(SPACE_WIDTH, |glyph| glyph.size[0]))
+			.sum()
+	}
+
+	/// Calls the function with each glyph of the text and its position.
+	fn layout_text(
+		&self,
+		text: &str,
+		position: [f32; 2],
+		scale: f32,
+		func: &mut impl FnMut(&Glyph, [f32; 2]),
+	) {
+		let mut x = position[0];
+
+		for c in text.chars() {
+			match self.glyph(c) {
+				Some(glyph) => {
+					func(glyph, [x, position[1]]);
+					x += glyph.size[0] * scale;
+				}
+				None => x += SPACE_WIDTH * scale,
+			}
+		}
+	}
+
+	fn draw_batch(
+		&mut self,
+		command_buffer_builder: AutoCommandBufferBuilder,
+		dynamic_state: &DynamicState,
+		texture: Arc<dyn ImageViewAccess + Send + Sync>,
+		instance_data: Vec<InstanceData>,
+	) -> anyhow::Result<AutoCommandBufferBuilder> {
+		let texture_set = Arc::new(
+			self.texture_set_pool
+				.next()
+				.add_sampled_image(texture, self.sampler.clone())?
+				.build()?,
+		);
+
+		let instance_buffer = self.instance_buffer_pool.chunk(instance_data)?;
+
+		command_buffer_builder
+			.draw(
+				self.pipeline.clone(),
+				dynamic_state,
+				vec![self.vertex_buffer.clone(), Arc::new(instance_buffer)],
+				texture_set,
+				(),
+			)
+			.context("Draw error")
+	}
+}
+
+mod console_vert {
+	vulkano_shaders::shader! {
+		ty: "vertex",
+		path: "shaders/console.vert",
+	}
+}
+
+mod console_frag {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "shaders/console.frag",
+	}
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VertexData {
+	pub in_position: [f32; 2],
+}
+impl_vertex!(VertexData, in_position);
+
+#[derive(Clone, Debug, Default)]
+pub struct InstanceData {
+	pub in_offset: [f32; 2],
+	pub in_size: [f32; 2],
+	pub in_color: [f32; 4],
+}
+impl_vertex!(InstanceData, in_offset, in_size, in_color);
@@ -8,11 +8,9 @@ use crate::{
 		},
 		render::normal_frag,
 	},
-	geometry::Angle,
 	renderer::AsBytes,
 };
 use anyhow::{anyhow, Context};
-use nalgebra::Vector3;
 use specs::{Join, ReadExpect, ReadStorage, World};
 use std::sync::Arc;
 use vulkano::{
@@ -59,7 +57,6 @@ pub struct MapRenderSystem {
 	normal_texture_set_pool: FixedSizeDescriptorSetsPool,
 	sky_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
 	sky_texture_set_pool: FixedSizeDescriptorSetsPool,
-	sky_uniform_pool: CpuBufferPool<sky_frag::ty::FragParams>,
 	vertex_buffer_pool: CpuBufferPool<u8>,
 }
 
@@ -123,7 +120,6 @@ impl MapRenderSystem {
 			),
 			normal_pipeline,
 
-			sky_uniform_pool: CpuBufferPool::new(device.clone(), BufferUsage::uniform_buffer()),
 			sky_texture_set_pool: FixedSizeDescriptorSetsPool::new(
 				sky_pipeline.descriptor_set_layout(1).unwrap().clone(),
 			),
@@ -138,7 +134,7 @@ impl MapRenderSystem {
 		dynamic_state: DynamicState,
 		sampler: Arc<Sampler>,
 		matrix_set: Arc<dyn DescriptorSet + Send + Sync>,
-		rotation: Vector3<Angle>,
+		palette_set: Arc<dyn DescriptorSet + Send + Sync>,
 	) -> anyhow::Result<AutoCommandBufferBuilder> {
 		let (flat_storage, map_storage, wall_storage, map_component) = world.system_data::<(
 			ReadExpect<AssetStorage<Flat>>,
@@ -181,7 +177,7 @@ impl MapRenderSystem {
 					&dynamic_state,
 					vec![Arc::new(vertex_buffer)],
 					index_buffer,
-					(matrix_set.clone(), texture_set.clone()),
+					(matrix_set.clone(), texture_set.clone(), palette_set.clone()),
 					(),
 				)?;
 			}
@@ -215,7 +211,7 @@ impl MapRenderSystem {
 						&dynamic_state,
 						vec![Arc::new(vertex_buffer)],
 						index_buffer,
-						(matrix_set.clone(), texture_set.clone()),
+						(matrix_set.clone(), texture_set.clone(), palette_set.clone()),
 						(),
 					)
 					.context("Draw error")?;
@@ -227,17 +223,10 @@ impl MapRenderSystem {
 				.chunk(sky_mesh.0.as_bytes().iter().copied())?;
 			let index_buffer = self.index_buffer_pool.chunk(sky_mesh.1)?;
 			let image = wall_storage.get(&map.sky).unwrap();
-			let sky_buffer = self.sky_uniform_pool.next(sky_frag::ty::FragParams {
-				screenSize: [800.0, 600.0],
-				pitch: rotation[1].to_degrees() as f32,
-				yaw: rotation[2].to_degrees() as f32,
-			})?;
-
-			let texture_params_set = Arc::new(
+			let texture_set = Arc::new(
 				self.sky_texture_set_pool
 					.next()
 					.add_sampled_image(image.clone(), sampler.clone())?
-					.add_buffer(sky_buffer)?
 					.build()?,
 			);
 
@@ -247,7 +236,7 @@ impl MapRenderSystem {
 					&dynamic_state,
 					vec![Arc::new(vertex_buffer)],
 					index_buffer,
-					(matrix_set.clone(), texture_params_set.clone()),
+					(matrix_set.clone(), texture_set, palette_set.clone()),
 					(),
 				)
 				.context("Draw error")?;
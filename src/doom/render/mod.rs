@@ -1,29 +1,47 @@
+pub mod console;
 pub mod map;
 pub mod sprite;
 
 use crate::{
+	assets::Asset,
+	configvars::ConfigVariables,
+	console::Console,
 	doom::{
 		client::Client,
 		components::Transform,
+		effects::PlayerEffects,
+		image::{self, Colormaps, Palettes},
+		input::{Action, Axis, BindTarget},
+		menu::ControlsMenu,
 		render::{
+			console::ConsoleRenderSystem,
 			map::{MapRenderSystem, UniformBufferObject},
 			sprite::SpriteRenderSystem,
 		},
+		wad::WadLoader,
 	},
-	renderer::{RenderContext, RenderTarget},
+	input::{Bindings, ButtonCapture},
+	renderer::{AsBytes, RenderContext, RenderTarget},
 };
 use anyhow::Context;
 use nalgebra::{Matrix4, Vector3};
+use shrev::ReaderId;
 use specs::{ReadExpect, ReadStorage, RunNow, World};
 use std::sync::Arc;
 use vulkano::{
 	buffer::{BufferUsage, CpuBufferPool},
 	command_buffer::{AutoCommandBufferBuilder, DynamicState},
 	descriptor::{
-		descriptor::{DescriptorBufferDesc, DescriptorDesc, DescriptorDescTy, ShaderStages},
+		descriptor::{
+			DescriptorBufferDesc, DescriptorDesc, DescriptorDescTy, DescriptorImageDesc,
+			DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages,
+		},
 		descriptor_set::{FixedSizeDescriptorSetsPool, UnsafeDescriptorSetLayout},
 	},
+	device::Device,
+	format::{ClearValue, Format},
 	framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract},
+	image::{AttachmentImage, Dimensions, ImageUsage, ImageViewAccess, ImmutableImage},
 	pipeline::viewport::Viewport,
 	sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
 	single_pass_renderpass,
@@ -32,35 +50,35 @@ use vulkano::{
 };
 
 pub struct RenderSystem {
+	colormaps: Arc<dyn ImageViewAccess + Send + Sync>,
+	config_reader: ReaderId<&'static str>,
+	console: ConsoleRenderSystem,
 	framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+	load_framebuffers: Vec<Arc<dyn FramebufferAbstract + Send + Sync>>,
+	load_render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
 	map: MapRenderSystem,
 	matrix_uniform_pool: CpuBufferPool<UniformBufferObject>,
 	matrix_set_pool: FixedSizeDescriptorSetsPool,
+	palette: Arc<dyn ImageViewAccess + Send + Sync>,
+	palette_set_pool: FixedSizeDescriptorSetsPool,
+	palette_uniform_pool: CpuBufferPool<normal_frag::ty::PaletteParams>,
 	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
 	sampler: Arc<Sampler>,
+	scene_copy: Arc<AttachmentImage>,
 	sprites: SpriteRenderSystem,
 	target: RenderTarget,
+	translations: Arc<dyn ImageViewAccess + Send + Sync>,
 }
 
 impl RenderSystem {
 	pub fn new(world: &World) -> anyhow::Result<RenderSystem> {
 		let render_context = world.fetch::<RenderContext>();
+		let mut config_variables = world.fetch_mut::<ConfigVariables>();
+		let config_reader = config_variables.register_reader();
 
 		// Create texture sampler
-		let sampler = Sampler::new(
-			render_context.device().clone(),
-			Filter::Nearest,
-			Filter::Nearest,
-			MipmapMode::Nearest,
-			SamplerAddressMode::Repeat,
-			SamplerAddressMode::Repeat,
-			SamplerAddressMode::Repeat,
-			0.0,
-			1.0,
-			0.0,
-			0.0,
-		)
-		.context("Couldn't create sampler")?;
+		// Textures are palette indices, so filtering is done in the shader instead
+		let sampler = create_sampler(render_context.device(), false)?;
 
 		// Create render target
 		let size = render_context.surface().window().inner_size().into();
@@ -69,6 +87,7 @@ impl RenderSystem {
 			render_context.device().clone(),
 			size,
 			true,
+			*config_variables.get::<bool>("vsync"),
 		)
 		.context("Couldn't create render target")?;
 
@@ -84,6 +103,31 @@ impl RenderSystem {
 					},
 					depth: {
 						load: Clear,
+						store: Store,
+						format: target.depth_format().unwrap(),
+						samples: 1,
+					}
+				},
+				pass: {
+					color: [color],
+					depth_stencil: {depth}
+				}
+			)
+			.context("Couldn't create render pass")?,
+		);
+
+		// Same as above, to carry on drawing after the frame has been copied for fuzz
+		let load_render_pass = Arc::new(
+			single_pass_renderpass!(render_context.device().clone(),
+				attachments: {
+					color: {
+						load: Load,
+						store: Store,
+						format: target.image_format(),
+						samples: 1,
+					},
+					depth: {
+						load: Load,
 						store: DontCare,
 						format: target.depth_format().unwrap(),
 						samples: 1,
@@ -98,18 +142,11 @@ impl RenderSystem {
 		);
 
 		// Create framebuffers
-		let images = target.images();
-		let mut framebuffers = Vec::with_capacity(images.len());
-
-		for image in images.iter() {
-			framebuffers.push(Arc::new(
-				Framebuffer::start(render_pass.clone())
-					.add(image.clone())?
-					.add(target.depth_buffer().unwrap().clone())?
-					.build()
-					.context("Couldn't create framebuffers")?,
-			) as Arc<dyn FramebufferAbstract + Send + Sync>);
-		}
+		let framebuffers = create_framebuffers(render_pass.clone(), &target)
+			.context("Couldn't create framebuffers")?;
+		let load_framebuffers = create_framebuffers(load_render_pass.clone(), &target)
+			.context("Couldn't create framebuffers")?;
+		let scene_copy = create_scene_copy(&target).context("Couldn't create scene copy")?;
 
 		// Create descriptor sets pool for matrices
 		let descriptors = [Some(DescriptorDesc {
@@ -134,8 +171,100 @@ impl RenderSystem {
 		);
 		let matrix_set_pool = FixedSizeDescriptorSetsPool::new(layout);
 
+		// Upload the palette and colormaps, for shaders to look up colours in
+		let loader = world.fetch::<WadLoader>();
+		let queue = render_context.queues().graphics.clone();
+
+		let palettes = Palettes::import("PLAYPAL", &*loader)?;
+		let (palette, _future) = ImmutableImage::from_iter(
+			palettes
+				.iter()
+				.flat_map(|palette| palette.iter().copied())
+				.collect::<Vec<_>>()
+				.as_bytes()
+				.iter()
+				.copied(),
+			Dimensions::Dim2d {
+				width: 256,
+				height: palettes.len() as u32,
+			},
+			Format::R8G8B8A8Unorm,
+			queue.clone(),
+		)?;
+
+		let colormaps = Colormaps::import("COLORMAP", &*loader)?;
+		let (colormaps, _future) = ImmutableImage::from_iter(
+			colormaps.concat().into_iter(),
+			Dimensions::Dim2d {
+				width: 256,
+				height: colormaps.len() as u32,
+			},
+			Format::R8Unorm,
+			queue.clone(),
+		)?;
+
+		let translations = image::player_translations();
+		let (translations, _future) = ImmutableImage::from_iter(
+			translations.concat().into_iter(),
+			Dimensions::Dim2d {
+				width: 256,
+				height: translations.len() as u32,
+			},
+			Format::R8Unorm,
+			queue,
+		)?;
+
+		// Create descriptor sets pool for palette, colormaps and translations
+		let image_descriptor = DescriptorDesc {
+			ty: DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+				sampled: true,
+				dimensions: DescriptorImageDescDimensions::TwoDimensional,
+				format: None,
+				multisampled: false,
+				array_layers: DescriptorImageDescArray::NonArrayed,
+			}),
+			array_count: 1,
+			stages: ShaderStages {
+				fragment: true,
+				..ShaderStages::none()
+			},
+			readonly: true,
+		};
+		let descriptors = [
+			Some(image_descriptor.clone()),
+			Some(image_descriptor.clone()),
+			Some(DescriptorDesc {
+				ty: DescriptorDescTy::Buffer(DescriptorBufferDesc {
+					dynamic: Some(false),
+					storage: false,
+				}),
+				array_count: 1,
+				stages: ShaderStages {
+					fragment: true,
+					..ShaderStages::none()
+				},
+				readonly: true,
+			}),
+			Some(image_descriptor),
+		];
+
+		let layout = Arc::new(
+			UnsafeDescriptorSetLayout::new(
+				render_context.device().clone(),
+				descriptors.iter().cloned(),
+			)
+			.context("Couldn't create descriptor set layout")?,
+		);
+		let palette_set_pool = FixedSizeDescriptorSetsPool::new(layout);
+
 		Ok(RenderSystem {
+			colormaps,
+			config_reader,
+			console: ConsoleRenderSystem::new(render_pass.clone(), &render_context, &loader)
+				.context("Couldn't create ConsoleRenderSystem")?,
 			framebuffers,
+			load_framebuffers,
+			load_render_pass,
 			map: MapRenderSystem::new(render_pass.clone())
 				.context("Couldn't create MapRenderSystem")?,
 			matrix_uniform_pool: CpuBufferPool::new(
@@ -143,11 +272,19 @@ impl RenderSystem {
 				BufferUsage::uniform_buffer(),
 			),
 			matrix_set_pool,
+			palette,
+			palette_set_pool,
+			palette_uniform_pool: CpuBufferPool::new(
+				render_context.device().clone(),
+				BufferUsage::uniform_buffer(),
+			),
 			render_pass: render_pass.clone(),
 			sampler,
+			scene_copy,
 			sprites: SpriteRenderSystem::new(render_pass, &*render_context)
 				.context("Couldn't create SpriteRenderSystem")?,
 			target,
+			translations,
 		})
 	}
 
@@ -164,21 +301,12 @@ impl RenderSystem {
 			.recreate(size)
 			.context("Couldn't recreate render target")?;
 
-		let images = self.target.images();
-		let depth_buffer = self.target.depth_buffer().unwrap();
-		let mut framebuffers = Vec::with_capacity(images.len());
-
-		for image in images.iter() {
-			framebuffers.push(Arc::new(
-				Framebuffer::start(self.render_pass.clone())
-					.add(image.clone())?
-					.add(depth_buffer.clone())?
-					.build()
-					.context("Couldn't recreate framebuffers")?,
-			) as Arc<dyn FramebufferAbstract + Send + Sync>);
-		}
-
-		self.framebuffers = framebuffers;
+		self.framebuffers = create_framebuffers(self.render_pass.clone(), &self.target)
+			.context("Couldn't recreate framebuffers")?;
+		self.load_framebuffers = create_framebuffers(self.load_render_pass.clone(), &self.target)
+			.context("Couldn't recreate framebuffers")?;
+		self.scene_copy =
+			create_scene_copy(&self.target).context("Couldn't recreate scene copy")?;
 
 		Ok(())
 	}
@@ -186,6 +314,22 @@ impl RenderSystem {
 	pub fn draw(&mut self, world: &World) -> anyhow::Result<()> {
 		let render_context = world.fetch::<RenderContext>();
 		let queues = render_context.queues();
+		let config_variables = world.fetch::<ConfigVariables>();
+
+		// Apply changed settings
+		let mut vsync_changed = false;
+
+		for name in config_variables.changes(&mut self.config_reader) {
+			if *name == "vsync" {
+				vsync_changed = true;
+			}
+		}
+
+		if vsync_changed {
+			self.target
+				.set_vsync(*config_variables.get::<bool>("vsync"));
+			return self.recreate();
+		}
 
 		// Prepare for drawing
 		let (image_num, future) = match self.target.acquire_next_image() {
@@ -221,13 +365,22 @@ impl RenderSystem {
 		// Doom had non-square pixels, with a resolution of 320x200 (16:10) running on a 4:3
 		// screen. This caused everything to be stretched vertically by some degree, and the game
 		// art was made with that in mind.
-		// The 1.2 factor here applies the same stretching as in the original.
-		let aspect_ratio = (dimensions[0] / dimensions[1]) * 1.2;
-		let proj = projection_matrix(90.0, aspect_ratio, 1.0, 20000.0);
+		// The default 1.2 factor here applies the same stretching as in the original.
+		let aspect_ratio =
+			(dimensions[0] / dimensions[1]) * *config_variables.get::<f32>("fov-aspect");
+		let proj = projection_matrix(
+			*config_variables.get::<f32>("fov"),
+			aspect_ratio,
+			1.0,
+			20000.0,
+		);
 
 		// View matrix
-		let (client, transform_storage) =
-			world.system_data::<(ReadExpect<Client>, ReadStorage<Transform>)>();
+		let (client, player_effects_storage, transform_storage) = world.system_data::<(
+			ReadExpect<Client>,
+			ReadStorage<PlayerEffects>,
+			ReadStorage<Transform>,
+		)>();
 
 		if let Some(entity) = client.entity {
 			let Transform {
@@ -262,6 +415,30 @@ impl RenderSystem {
 					.build()?,
 			);
 
+			// Palette, colormaps and translations
+			let player_effects = player_effects_storage
+				.get(entity)
+				.copied()
+				.unwrap_or_default();
+			let palette_buffer =
+				self.palette_uniform_pool
+					.next(normal_frag::ty::PaletteParams {
+						colormap_lighting: *config_variables.get::<bool>("colormap-lighting")
+							as i32,
+						filtering: *config_variables.get::<bool>("texture-filtering") as i32,
+						palette: player_effects.palette() as i32,
+						fixed_colormap: player_effects.fixed_colormap().unwrap_or(-1),
+					})?;
+			let palette_set = Arc::new(
+				self.palette_set_pool
+					.next()
+					.add_sampled_image(self.colormaps.clone(), self.sampler.clone())?
+					.add_sampled_image(self.palette.clone(), self.sampler.clone())?
+					.add_buffer(palette_buffer)?
+					.add_sampled_image(self.translations.clone(), self.sampler.clone())?
+					.build()?,
+			);
+
 			// Draw the map
 			command_buffer_builder = self
 				.map
@@ -271,7 +448,7 @@ impl RenderSystem {
 					dynamic_state.clone(),
 					self.sampler.clone(),
 					matrix_set.clone(),
-					rotation,
+					palette_set.clone(),
 				)
 				.context("Draw error")?;
 
@@ -281,15 +458,79 @@ impl RenderSystem {
 				.draw(
 					world,
 					command_buffer_builder,
-					dynamic_state,
+					dynamic_state.clone(),
 					self.sampler.clone(),
-					matrix_set,
+					matrix_set.clone(),
+					palette_set.clone(),
 					rotation[2],
 					position,
 				)
 				.context("Draw error")?;
+
+			// Fuzz reads what is behind it, which can't be done while drawing to it, so the
+			// frame so far is copied and the rest is drawn in a new render pass
+			if self.sprites.has_fuzz() {
+				let image = self.target.images()[image_num].clone();
+				let extent = [dimensions[0] as u32, dimensions[1] as u32, 1];
+
+				command_buffer_builder = command_buffer_builder
+					.end_render_pass()?
+					.copy_image(
+						image,
+						[0; 3],
+						0,
+						0,
+						self.scene_copy.clone(),
+						[0; 3],
+						0,
+						0,
+						extent,
+						1,
+					)
+					.context("Couldn't copy frame")?
+					.begin_render_pass(
+						self.load_framebuffers[image_num].clone(),
+						false,
+						vec![ClearValue::None, ClearValue::None],
+					)
+					.context("Couldn't begin render pass")?;
+			}
+
+			command_buffer_builder = self
+				.sprites
+				.draw_blended(
+					command_buffer_builder,
+					dynamic_state.clone(),
+					self.sampler.clone(),
+					matrix_set,
+					palette_set,
+					self.scene_copy.clone(),
+				)
+				.context("Draw error")?;
 		}
 
+		// Draw the menu and the console over everything else
+		command_buffer_builder = self
+			.console
+			.draw_menu(
+				&world.fetch::<ControlsMenu>(),
+				&world.fetch::<ButtonCapture<BindTarget>>(),
+				&world.fetch::<Bindings<Action, Axis>>(),
+				command_buffer_builder,
+				&dynamic_state,
+				dimensions,
+			)
+			.context("Draw error")?;
+		command_buffer_builder = self
+			.console
+			.draw(
+				&world.fetch::<Console>(),
+				command_buffer_builder,
+				&dynamic_state,
+				dimensions,
+			)
+			.context("Draw error")?;
+
 		// Finalise
 		let command_buffer = Arc::new(command_buffer_builder.end_render_pass()?.build()?);
 
@@ -320,6 +561,65 @@ impl<'a> RunNow<'a> for RenderSystem {
 	}
 }
 
+fn create_framebuffers(
+	render_pass: Arc<dyn RenderPassAbstract + Send + Sync>,
+	target: &RenderTarget,
+) -> anyhow::Result<Vec<Arc<dyn FramebufferAbstract + Send + Sync>>> {
+	let depth_buffer = target.depth_buffer().unwrap();
+
+	target
+		.images()
+		.iter()
+		.map(|image| {
+			Ok(Arc::new(
+				Framebuffer::start(render_pass.clone())
+					.add(image.clone())?
+					.add(depth_buffer.clone())?
+					.build()?,
+			) as Arc<dyn FramebufferAbstract + Send + Sync>)
+		})
+		.collect()
+}
+
+/// An image the size of the render target, that the frame can be copied to for sampling.
+fn create_scene_copy(target: &RenderTarget) -> anyhow::Result<Arc<AttachmentImage>> {
+	let dimensions = target.images()[0].dimensions();
+
+	Ok(AttachmentImage::with_usage(
+		target.device().clone(),
+		[dimensions.width(), dimensions.height()],
+		target.image_format(),
+		ImageUsage {
+			sampled: true,
+			transfer_destination: true,
+			..ImageUsage::none()
+		},
+	)?)
+}
+
+fn create_sampler(device: &Arc<Device>, filtering: bool) -> anyhow::Result<Arc<Sampler>> {
+	let (filter, mipmap_mode) = if filtering {
+		(Filter::Linear, MipmapMode::Linear)
+	} else {
+		(Filter::Nearest, MipmapMode::Nearest)
+	};
+
+	Sampler::new(
+		device.clone(),
+		filter,
+		filter,
+		mipmap_mode,
+		SamplerAddressMode::Repeat,
+		SamplerAddressMode::Repeat,
+		SamplerAddressMode::Repeat,
+		0.0,
+		1.0,
+		0.0,
+		0.0,
+	)
+	.context("Couldn't create sampler")
+}
+
 mod normal_frag {
 	vulkano_shaders::shader! {
 		ty: "fragment",
@@ -1,5 +1,6 @@
 use crate::{
 	assets::{AssetHandle, AssetStorage},
+	configvars::ConfigVariables,
 	doom::{
 		client::Client,
 		components::Transform,
@@ -12,14 +13,16 @@ use crate::{
 };
 use anyhow::Context;
 use nalgebra::{Matrix4, Vector2, Vector3};
+use rand::Rng;
 use specs::{Component, DenseVecStorage, Entities, Join, ReadExpect, ReadStorage, World};
 use specs_derive::Component;
 use std::{
+	cmp::Ordering,
 	collections::{hash_map::Entry, HashMap},
 	sync::Arc,
 };
 use vulkano::{
-	buffer::{BufferUsage, CpuBufferPool, ImmutableBuffer},
+	buffer::{BufferAccess, BufferUsage, CpuBufferPool, ImmutableBuffer},
 	command_buffer::{
 		pool::standard::StandardCommandPoolBuilder, AutoCommandBufferBuilder, DynamicState,
 	},
@@ -32,15 +35,32 @@ use vulkano::{
 	image::ImageViewAccess,
 	impl_vertex,
 	pipeline::{
-		vertex::OneVertexOneInstanceDefinition, GraphicsPipeline, GraphicsPipelineAbstract,
+		blend::{AttachmentBlend, BlendFactor},
+		vertex::OneVertexOneInstanceDefinition,
+		GraphicsPipeline, GraphicsPipelineAbstract,
 	},
 	sampler::Sampler,
 };
 
+// Boom's default translucency
+const TRANSLUCENCY: f32 = 0.66;
+
+// Entries in the fuzz offset table in fuzz.frag
+const FUZZ_TABLE_SIZE: i32 = 50;
+
 pub struct SpriteRenderSystem {
+	/// Blended sprites of the current frame, back to front.
+	blended: Vec<(
+		RenderStyle,
+		Arc<dyn ImageViewAccess + Send + Sync>,
+		InstanceData,
+	)>,
 	instance_buffer_pool: CpuBufferPool<InstanceData>,
 	vertex_buffer: Arc<ImmutableBuffer<[u8]>>,
-	pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	fuzz_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	normal_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	translucent_pipeline: Arc<dyn GraphicsPipelineAbstract + Send + Sync>,
+	scene_set_pool: FixedSizeDescriptorSetsPool,
 	texture_set_pool: FixedSizeDescriptorSetsPool,
 }
 
@@ -51,11 +71,30 @@ impl SpriteRenderSystem {
 	) -> anyhow::Result<SpriteRenderSystem> {
 		let device = render_pass.device();
 
-		// Create pipeline
+		// Create pipelines
 		let vert = sprite_vert::Shader::load(device.clone()).context("Couldn't load shader")?;
 		let frag = normal_frag::Shader::load(device.clone()).context("Couldn't load shader")?;
+		let fuzz_frag = fuzz_frag::Shader::load(device.clone()).context("Couldn't load shader")?;
+
+		let normal_pipeline = Arc::new(
+			GraphicsPipeline::start()
+				.render_pass(
+					Subpass::from(render_pass.clone(), 0).context("Subpass index out of range")?,
+				)
+				.vertex_input(OneVertexOneInstanceDefinition::<VertexData, InstanceData>::new())
+				.vertex_shader(vert.main_entry_point(), ())
+				.fragment_shader(frag.main_entry_point(), ())
+				.triangle_fan()
+				.primitive_restart(true)
+				.viewports_dynamic_scissors_irrelevant(1)
+				.cull_mode_disabled()
+				.depth_stencil_simple_depth()
+				.build(device.clone())
+				.context("Couldn't create pipeline")?,
+		) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
 
-		let pipeline = Arc::new(
+		// Blended sprites are drawn last, so they don't need to write depth
+		let translucent_pipeline = Arc::new(
 			GraphicsPipeline::start()
 				.render_pass(
 					Subpass::from(render_pass.clone(), 0).context("Subpass index out of range")?,
@@ -68,6 +107,32 @@ impl SpriteRenderSystem {
 				.viewports_dynamic_scissors_irrelevant(1)
 				.cull_mode_disabled()
 				.depth_stencil_simple_depth()
+				.depth_write(false)
+				.blend_collective(AttachmentBlend {
+					enabled: true,
+					color_source: BlendFactor::ConstantAlpha,
+					color_destination: BlendFactor::OneMinusConstantAlpha,
+					..AttachmentBlend::pass_through()
+				})
+				.blend_constants([0.0, 0.0, 0.0, TRANSLUCENCY])
+				.build(device.clone())
+				.context("Couldn't create pipeline")?,
+		) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
+
+		let fuzz_pipeline = Arc::new(
+			GraphicsPipeline::start()
+				.render_pass(
+					Subpass::from(render_pass.clone(), 0).context("Subpass index out of range")?,
+				)
+				.vertex_input(OneVertexOneInstanceDefinition::<VertexData, InstanceData>::new())
+				.vertex_shader(vert.main_entry_point(), ())
+				.fragment_shader(fuzz_frag.main_entry_point(), ())
+				.triangle_fan()
+				.primitive_restart(true)
+				.viewports_dynamic_scissors_irrelevant(1)
+				.cull_mode_disabled()
+				.depth_stencil_simple_depth()
+				.depth_write(false)
 				.build(device.clone())
 				.context("Couldn't create pipeline")?,
 		) as Arc<dyn GraphicsPipelineAbstract + Send + Sync>;
@@ -100,16 +165,23 @@ impl SpriteRenderSystem {
 		)?;
 
 		Ok(SpriteRenderSystem {
+			blended: Vec::new(),
 			vertex_buffer,
 
 			instance_buffer_pool: CpuBufferPool::new(device.clone(), BufferUsage::vertex_buffer()),
 			texture_set_pool: FixedSizeDescriptorSetsPool::new(
-				pipeline.descriptor_set_layout(1).unwrap().clone(),
+				normal_pipeline.descriptor_set_layout(1).unwrap().clone(),
 			),
-			pipeline,
+			scene_set_pool: FixedSizeDescriptorSetsPool::new(
+				fuzz_pipeline.descriptor_set_layout(2).unwrap().clone(),
+			),
+			fuzz_pipeline,
+			normal_pipeline,
+			translucent_pipeline,
 		})
 	}
 
+	/// Draws the opaque sprites, and keeps the blended ones for `draw_blended`.
 	pub fn draw(
 		&mut self,
 		world: &World,
@@ -117,12 +189,14 @@ impl SpriteRenderSystem {
 		dynamic_state: DynamicState,
 		sampler: Arc<Sampler>,
 		matrix_set: Arc<dyn DescriptorSet + Send + Sync>,
+		palette_set: Arc<dyn DescriptorSet + Send + Sync>,
 		yaw: Angle,
 		view_pos: Vector3<f32>,
 	) -> anyhow::Result<AutoCommandBufferBuilder> {
 		let (
 			entities,
 			client,
+			config_variables,
 			map_storage,
 			sprite_storage,
 			sprite_image_storage,
@@ -132,6 +206,7 @@ impl SpriteRenderSystem {
 		) = world.system_data::<(
 			Entities,
 			ReadExpect<Client>,
+			ReadExpect<ConfigVariables>,
 			ReadExpect<AssetStorage<Map>>,
 			ReadExpect<AssetStorage<Sprite>>,
 			ReadExpect<AssetStorage<SpriteImage>>,
@@ -143,10 +218,20 @@ impl SpriteRenderSystem {
 		let map_dynamic = map_dynamic_component.join().next().unwrap();
 		let map = map_storage.get(&map_dynamic.map).unwrap();
 
-		// Group draws into batches by texture
+		let translucency = *config_variables.get::<bool>("translucency");
+
+		// Group opaque draws into batches by texture
 		let mut batches: HashMap<Arc<dyn ImageViewAccess + Send + Sync>, Vec<InstanceData>> =
 			HashMap::new();
 
+		// Blended draws have to be sorted by distance instead
+		let mut blended: Vec<(
+			f32,
+			RenderStyle,
+			Arc<dyn ImageViewAccess + Send + Sync>,
+			InstanceData,
+		)> = Vec::new();
+
 		for (entity, sprite_render, transform) in
 			(&entities, &sprite_component, &transform_component).join()
 		{
@@ -198,16 +283,23 @@ impl SpriteRenderSystem {
 				in_flip: image_info.flip,
 				in_light_level: light_level,
 				in_matrix: instance_matrix.into(),
+				in_translation: sprite_render.translation as i32,
 			};
 
 			// Add to batches
-			match batches.entry(
-				sprite_image_storage
-					.get(&image_info.handle)
-					.unwrap()
-					.image
-					.clone(),
-			) {
+			let texture = sprite_image.image.clone();
+
+			match sprite_render.render_style {
+				RenderStyle::Translucent if !translucency => (),
+				RenderStyle::Fuzz | RenderStyle::Translucent => {
+					let distance = (transform.position - view_pos).norm_squared();
+					blended.push((distance, sprite_render.render_style, texture, instance_data));
+					continue;
+				}
+				RenderStyle::Normal => (),
+			}
+
+			match batches.entry(texture) {
 				Entry::Occupied(mut entry) => {
 					entry.get_mut().push(instance_data);
 				}
@@ -230,19 +322,103 @@ impl SpriteRenderSystem {
 
 			command_buffer_builder = command_buffer_builder
 				.draw(
-					self.pipeline.clone(),
+					self.normal_pipeline.clone(),
 					&dynamic_state,
 					vec![self.vertex_buffer.clone(), Arc::new(instance_buffer)],
-					(matrix_set.clone(), texture_set.clone()),
+					(matrix_set.clone(), texture_set.clone(), palette_set.clone()),
 					(),
 				)
 				.context("Draw error")?;
 		}
 
+		// Blended sprites are drawn back to front, over everything else
+		blended.sort_unstable_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+		self.blended = blended
+			.into_iter()
+			.map(|(_, render_style, texture, instance_data)| (render_style, texture, instance_data))
+			.collect();
+
+		Ok(command_buffer_builder)
+	}
+
+	/// Whether any of the blended sprites need a copy of the frame to draw.
+	pub fn has_fuzz(&self) -> bool {
+		self.blended
+			.iter()
+			.any(|(render_style, _, _)| *render_style == RenderStyle::Fuzz)
+	}
+
+	/// Draws the blended sprites that `draw` kept. `scene` is a copy of the frame drawn so far,
+	/// for fuzz to read from.
+	pub fn draw_blended(
+		&mut self,
+		mut command_buffer_builder: AutoCommandBufferBuilder<StandardCommandPoolBuilder>,
+		dynamic_state: DynamicState,
+		sampler: Arc<Sampler>,
+		matrix_set: Arc<dyn DescriptorSet + Send + Sync>,
+		palette_set: Arc<dyn DescriptorSet + Send + Sync>,
+		scene: Arc<dyn ImageViewAccess + Send + Sync>,
+	) -> anyhow::Result<AutoCommandBufferBuilder> {
+		if self.blended.is_empty() {
+			return Ok(command_buffer_builder);
+		}
+
+		let scene_set = Arc::new(
+			self.scene_set_pool
+				.next()
+				.add_sampled_image(scene, sampler.clone())?
+				.build()?,
+		);
+
+		// The original carried the fuzz position over from the last frame, so the pattern changes
+		// every frame
+		let fuzz_params = fuzz_frag::ty::FuzzParams {
+			fuzz_position: rand::thread_rng().gen_range(0, FUZZ_TABLE_SIZE),
+			fuzz_scale: 200.0 / dynamic_state.viewports.as_ref().unwrap()[0].dimensions[1],
+		};
+
+		for (render_style, texture, instance_data) in std::mem::take(&mut self.blended) {
+			let texture_set = Arc::new(
+				self.texture_set_pool
+					.next()
+					.add_sampled_image(texture, sampler.clone())?
+					.build()?,
+			);
+
+			let instance_buffer = self.instance_buffer_pool.chunk(vec![instance_data])?;
+			let vertex_buffers: Vec<Arc<dyn BufferAccess + Send + Sync>> =
+				vec![self.vertex_buffer.clone(), Arc::new(instance_buffer)];
+
+			command_buffer_builder = match render_style {
+				RenderStyle::Fuzz => command_buffer_builder.draw(
+					self.fuzz_pipeline.clone(),
+					&dynamic_state,
+					vertex_buffers,
+					(matrix_set.clone(), texture_set, scene_set.clone()),
+					fuzz_params,
+				),
+				_ => command_buffer_builder.draw(
+					self.translucent_pipeline.clone(),
+					&dynamic_state,
+					vertex_buffers,
+					(matrix_set.clone(), texture_set, palette_set.clone()),
+					(),
+				),
+			}
+			.context("Draw error")?;
+		}
+
 		Ok(command_buffer_builder)
 	}
 }
 
+mod fuzz_frag {
+	vulkano_shaders::shader! {
+		ty: "fragment",
+		path: "shaders/fuzz.frag",
+	}
+}
+
 mod sprite_vert {
 	vulkano_shaders::shader! {
 		ty: "vertex",
@@ -262,12 +438,31 @@ pub struct InstanceData {
 	pub in_flip: f32,
 	pub in_light_level: f32,
 	pub in_matrix: [[f32; 4]; 4],
+	pub in_translation: i32,
 }
-impl_vertex!(InstanceData, in_flip, in_light_level, in_matrix);
+impl_vertex!(
+	InstanceData,
+	in_flip,
+	in_light_level,
+	in_matrix,
+	in_translation
+);
 
 #[derive(Clone, Component, Debug)]
 pub struct SpriteRender {
 	pub sprite: AssetHandle<Sprite>,
 	pub frame: usize,
 	pub full_bright: bool,
+	pub render_style: RenderStyle,
+	/// Index into the player translations, 0 leaves the colours unchanged.
+	pub translation: usize,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RenderStyle {
+	Normal = 0,
+	/// Darkens what's behind it in a shimmering pattern, like spectres.
+	Fuzz = 1,
+	/// Blends with what's behind it, like Boom's translucent projectiles and effects.
+	Translucent = 2,
 }
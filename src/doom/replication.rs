@@ -0,0 +1,988 @@
+use crate::{
+	assets::AssetStorage,
+	doom::{
+		client::{Client, Interpolation},
+		components::{Transform, Velocity},
+		data::FRAME_TIME,
+		effects::PlayerEffects,
+		image::NUM_PLAYER_TRANSLATIONS,
+		map::{
+			textures::{TextureType, Wall},
+			LinedefRef, Map, MapDynamic, SectorRef,
+		},
+		render::sprite::{RenderStyle, SpriteRender},
+		sprite::Sprite,
+	},
+	protocol::{Snapshot, SnapshotDelta},
+};
+use anyhow::{anyhow, bail, ensure};
+use byteorder::{NetworkEndian as NE, ReadBytesExt};
+use nalgebra::Vector3;
+use specs::{Entities, Entity, Join, ReadExpect, ReadStorage, World, WorldExt, WriteStorage};
+use std::{
+	cmp::Ordering,
+	collections::{HashMap, HashSet, VecDeque},
+	io::Cursor,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+/*
+	Registry
+*/
+
+/// The replicated state of a component, split into fields that are sent only when they change.
+pub type Fields = Vec<Vec<u8>>;
+
+/// A component type whose state is sent from the server to the clients.
+pub trait Replicate {
+	/// Identifies the type on the network, so it must be unique and never change.
+	const ID: u8;
+	const NAME: &'static str;
+
+	/// Returns the fields of every entity that has something to replicate.
+	fn write(world: &World) -> Vec<(Entity, Fields)>;
+
+	/// Inserts or updates the component on a client entity.
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()>;
+
+	/// Removes the component from a client entity.
+	fn remove(world: &World, entity: Entity);
+}
+
+struct ReplicatedType {
+	name: &'static str,
+	write: fn(&World) -> Vec<(Entity, Fields)>,
+	apply: fn(&World, Entity, &[Vec<u8>]) -> anyhow::Result<()>,
+	remove: fn(&World, Entity),
+}
+
+#[derive(Default)]
+pub struct ReplicationRegistry {
+	types: HashMap<u8, ReplicatedType>,
+}
+
+impl ReplicationRegistry {
+	pub fn register<T: Replicate>(&mut self) {
+		if let Some(other) = self.types.get(&T::ID) {
+			panic!(
+				"Replicated types {} and {} both have id {}",
+				other.name,
+				T::NAME,
+				T::ID
+			);
+		}
+
+		self.types.insert(
+			T::ID,
+			ReplicatedType {
+				name: T::NAME,
+				write: T::write,
+				apply: T::apply,
+				remove: T::remove,
+			},
+		);
+	}
+
+	fn get(&self, id: u8) -> anyhow::Result<&ReplicatedType> {
+		self.types
+			.get(&id)
+			.ok_or_else(|| anyhow!("unknown component type {}", id))
+	}
+}
+
+/*
+	Field encoding
+*/
+
+fn read_f32(field: &[u8]) -> anyhow::Result<f32> {
+	Ok(Cursor::new(field).read_f32::<NE>()?)
+}
+
+fn read_i32(field: &[u8]) -> anyhow::Result<i32> {
+	Ok(Cursor::new(field).read_i32::<NE>()?)
+}
+
+fn read_u32(field: &[u8]) -> anyhow::Result<u32> {
+	Ok(Cursor::new(field).read_u32::<NE>()?)
+}
+
+fn check_field_count(fields: &[Vec<u8>], count: usize) -> anyhow::Result<()> {
+	ensure!(
+		fields.len() == count,
+		"expected {} fields, got {}",
+		count,
+		fields.len()
+	);
+	Ok(())
+}
+
+/// Encodes the fields that differ from the baseline, as a bitmask followed by the
+/// length-prefixed fields. Returns `None` if nothing changed.
+fn encode_delta(baseline: Option<&Fields>, fields: &Fields) -> Option<Vec<u8>> {
+	let mut mask = 0u16;
+	let mut data = vec![0, 0];
+
+	for (i, field) in fields.iter().enumerate() {
+		if baseline.and_then(|b| b.get(i)) != Some(field) {
+			mask |= 1 << i;
+			data.push(field.len() as u8);
+			data.extend(field);
+		}
+	}
+
+	if mask == 0 {
+		None
+	} else {
+		data[0..2].copy_from_slice(&mask.to_be_bytes());
+		Some(data)
+	}
+}
+
+fn decode_delta(baseline: Option<&Fields>, data: &[u8]) -> anyhow::Result<Fields> {
+	let mut reader = Cursor::new(data);
+	let mask = reader.read_u16::<NE>()?;
+	let mut fields = baseline.cloned().unwrap_or_default();
+
+	for i in (0..16).filter(|i| mask & (1 << i) != 0) {
+		let length = reader.read_u8()? as usize;
+		let start = reader.position() as usize;
+		ensure!(
+			start + length <= data.len(),
+			"field length {} exceeds the end of the data",
+			length
+		);
+
+		if fields.len() <= i {
+			fields.resize(i + 1, Vec::new());
+		}
+
+		fields[i] = data[start..start + length].to_owned();
+		reader.set_position((start + length) as u64);
+	}
+
+	Ok(fields)
+}
+
+/*
+	Replicated components
+*/
+
+// Movements further than this are not interpolated
+const TELEPORT_DISTANCE: f32 = 128.0;
+
+impl Replicate for Transform {
+	const ID: u8 = 1;
+	const NAME: &'static str = "Transform";
+
+	fn write(world: &World) -> Vec<(Entity, Fields)> {
+		let (entities, transform_component) =
+			world.system_data::<(Entities, ReadStorage<Transform>)>();
+
+		(&entities, &transform_component)
+			.join()
+			.map(|(entity, transform)| {
+				let mut fields = Vec::with_capacity(6);
+
+				for i in 0..3 {
+					fields.push(transform.position[i].to_be_bytes().to_vec());
+				}
+
+				for i in 0..3 {
+					fields.push(transform.rotation[i].0.to_be_bytes().to_vec());
+				}
+
+				(entity, fields)
+			})
+			.collect()
+	}
+
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()> {
+		check_field_count(fields, 6)?;
+		let mut transform = Transform::default();
+
+		for i in 0..3 {
+			transform.position[i] = read_f32(&fields[i])?;
+			transform.rotation[i] = read_i32(&fields[i + 3])?.into();
+		}
+
+		let (client, mut interpolation_component, mut transform_component) = world.system_data::<(
+			ReadExpect<Client>,
+			WriteStorage<Interpolation>,
+			WriteStorage<Transform>,
+		)>();
+
+		// The player's own entity is predicted instead
+		match transform_component.get(entity) {
+			Some(&current)
+				if client.entity != Some(entity)
+					&& (transform.position - current.position).norm() < TELEPORT_DISTANCE =>
+			{
+				interpolation_component.insert(
+					entity,
+					Interpolation {
+						from: current,
+						to: transform,
+						start: Instant::now(),
+					},
+				)?;
+			}
+			_ => {
+				interpolation_component.remove(entity);
+				transform_component.insert(entity, transform)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn remove(world: &World, entity: Entity) {
+		world.write_component::<Interpolation>().remove(entity);
+		world.write_component::<Transform>().remove(entity);
+	}
+}
+
+impl Replicate for Velocity {
+	const ID: u8 = 2;
+	const NAME: &'static str = "Velocity";
+
+	fn write(world: &World) -> Vec<(Entity, Fields)> {
+		let (entities, velocity_component) =
+			world.system_data::<(Entities, ReadStorage<Velocity>)>();
+
+		(&entities, &velocity_component)
+			.join()
+			.map(|(entity, velocity)| {
+				let fields = (0..3)
+					.map(|i| velocity.velocity[i].to_be_bytes().to_vec())
+					.collect();
+				(entity, fields)
+			})
+			.collect()
+	}
+
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()> {
+		check_field_count(fields, 3)?;
+		let velocity = Velocity {
+			velocity: Vector3::new(
+				read_f32(&fields[0])?,
+				read_f32(&fields[1])?,
+				read_f32(&fields[2])?,
+			),
+		};
+
+		world
+			.write_component::<Velocity>()
+			.insert(entity, velocity)?;
+		Ok(())
+	}
+
+	fn remove(world: &World, entity: Entity) {
+		world.write_component::<Velocity>().remove(entity);
+	}
+}
+
+impl Replicate for SpriteRender {
+	const ID: u8 = 3;
+	const NAME: &'static str = "SpriteRender";
+
+	fn write(world: &World) -> Vec<(Entity, Fields)> {
+		let (entities, sprite_storage, sprite_render_component) = world.system_data::<(
+			Entities,
+			ReadExpect<AssetStorage<Sprite>>,
+			ReadStorage<SpriteRender>,
+		)>();
+
+		(&entities, &sprite_render_component)
+			.join()
+			.filter_map(|(entity, sprite_render)| {
+				let name = sprite_storage.name_for(&sprite_render.sprite)?;
+
+				Some((
+					entity,
+					vec![
+						name.as_bytes().to_owned(),
+						(sprite_render.frame as u32).to_be_bytes().to_vec(),
+						vec![sprite_render.full_bright as u8],
+						vec![sprite_render.render_style as u8],
+						vec![sprite_render.translation as u8],
+					],
+				))
+			})
+			.collect()
+	}
+
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()> {
+		check_field_count(fields, 5)?;
+		let name = std::str::from_utf8(&fields[0])?;
+		let sprite = world
+			.fetch::<AssetStorage<Sprite>>()
+			.handle_for(name)
+			.ok_or_else(|| anyhow!("Sprite {} is not loaded", name))?;
+
+		world.write_component::<SpriteRender>().insert(
+			entity,
+			SpriteRender {
+				sprite,
+				frame: read_u32(&fields[1])? as usize,
+				full_bright: fields[2].first().map_or(false, |&b| b != 0),
+				render_style: match fields[3].first() {
+					Some(0) => RenderStyle::Normal,
+					Some(1) => RenderStyle::Fuzz,
+					Some(2) => RenderStyle::Translucent,
+					x => bail!("unknown render style {:?}", x),
+				},
+				translation: match fields[4].first() {
+					Some(&x) if (x as usize) < NUM_PLAYER_TRANSLATIONS => x as usize,
+					x => bail!("unknown translation {:?}", x),
+				},
+			},
+		)?;
+		Ok(())
+	}
+
+	fn remove(world: &World, entity: Entity) {
+		world.write_component::<SpriteRender>().remove(entity);
+	}
+}
+
+// Sectors and linedefs are known to the client already, so they are only replicated
+// while they differ from their initial state. The client applies them to its own map.
+impl Replicate for SectorRef {
+	const ID: u8 = 4;
+	const NAME: &'static str = "SectorDynamic";
+
+	fn write(world: &World) -> Vec<(Entity, Fields)> {
+		let (entities, map_storage, map_dynamic_component, sector_ref_component) = world
+			.system_data::<(
+				Entities,
+				ReadExpect<AssetStorage<Map>>,
+				ReadStorage<MapDynamic>,
+				ReadStorage<SectorRef>,
+			)>();
+
+		(&entities, &sector_ref_component)
+			.join()
+			.filter_map(|(entity, sector_ref)| {
+				let map_dynamic = map_dynamic_component.get(sector_ref.map_entity)?;
+				let map = map_storage.get(&map_dynamic.map)?;
+				let sector = &map.sectors[sector_ref.index];
+				let sector_dynamic = &map_dynamic.sectors[sector_ref.index];
+
+				if sector_dynamic.light_level == sector.light_level
+					&& sector_dynamic.interval.min == sector.interval.min
+					&& sector_dynamic.interval.max == sector.interval.max
+				{
+					return None;
+				}
+
+				Some((
+					entity,
+					vec![
+						(sector_ref.index as u32).to_be_bytes().to_vec(),
+						sector_dynamic.light_level.to_be_bytes().to_vec(),
+						sector_dynamic.interval.min.to_be_bytes().to_vec(),
+						sector_dynamic.interval.max.to_be_bytes().to_vec(),
+					],
+				))
+			})
+			.collect()
+	}
+
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()> {
+		check_field_count(fields, 4)?;
+		let index = read_u32(&fields[0])? as usize;
+
+		let (entities, mut map_dynamic_component, mut sector_ref_component) =
+			world.system_data::<(Entities, WriteStorage<MapDynamic>, WriteStorage<SectorRef>)>();
+		let (map_entity, map_dynamic) = (&entities, &mut map_dynamic_component)
+			.join()
+			.next()
+			.ok_or_else(|| anyhow!("No map is loaded"))?;
+		let sector_dynamic = map_dynamic
+			.sectors
+			.get_mut(index)
+			.ok_or_else(|| anyhow!("Sector index {} is out of range", index))?;
+
+		sector_dynamic.light_level = read_f32(&fields[1])?;
+		sector_dynamic.interval.min = read_f32(&fields[2])?;
+		sector_dynamic.interval.max = read_f32(&fields[3])?;
+		sector_ref_component.insert(entity, SectorRef { map_entity, index })?;
+		Ok(())
+	}
+
+	fn remove(world: &World, entity: Entity) {
+		let (map_storage, mut map_dynamic_component, mut sector_ref_component) = world
+			.system_data::<(
+				ReadExpect<AssetStorage<Map>>,
+				WriteStorage<MapDynamic>,
+				WriteStorage<SectorRef>,
+			)>();
+
+		if let Some(sector_ref) = sector_ref_component.remove(entity) {
+			if let Some(map_dynamic) = map_dynamic_component.get_mut(sector_ref.map_entity) {
+				let map = map_storage.get(&map_dynamic.map).unwrap();
+				let sector = &map.sectors[sector_ref.index];
+				let sector_dynamic = &mut map_dynamic.sectors[sector_ref.index];
+
+				sector_dynamic.light_level = sector.light_level;
+				sector_dynamic.interval = sector.interval;
+			}
+		}
+	}
+}
+
+impl Replicate for LinedefRef {
+	const ID: u8 = 5;
+	const NAME: &'static str = "LinedefDynamic";
+
+	fn write(world: &World) -> Vec<(Entity, Fields)> {
+		let (entities, map_storage, wall_storage, linedef_ref_component, map_dynamic_component) =
+			world.system_data::<(
+				Entities,
+				ReadExpect<AssetStorage<Map>>,
+				ReadExpect<AssetStorage<Wall>>,
+				ReadStorage<LinedefRef>,
+				ReadStorage<MapDynamic>,
+			)>();
+
+		(&entities, &linedef_ref_component)
+			.join()
+			.filter_map(|(entity, linedef_ref)| {
+				let map_dynamic = map_dynamic_component.get(linedef_ref.map_entity)?;
+				let map = map_storage.get(&map_dynamic.map)?;
+				let linedef = &map.linedefs[linedef_ref.index];
+				let linedef_dynamic = &map_dynamic.linedefs[linedef_ref.index];
+
+				let changed = linedef
+					.sidedefs
+					.iter()
+					.zip(linedef_dynamic.sidedefs.iter())
+					.any(|sidedefs| match sidedefs {
+						(Some(sidedef), Some(sidedef_dynamic)) => {
+							sidedef.textures != sidedef_dynamic.textures
+						}
+						_ => false,
+					});
+
+				if !changed {
+					return None;
+				}
+
+				// The index, then the three textures of each side
+				let mut fields = vec![(linedef_ref.index as u32).to_be_bytes().to_vec()];
+
+				for sidedef_dynamic in linedef_dynamic.sidedefs.iter() {
+					for i in 0..3 {
+						fields.push(match sidedef_dynamic.as_ref().map(|s| &s.textures[i]) {
+							None | Some(TextureType::None) => vec![0],
+							Some(TextureType::Sky) => vec![1],
+							Some(TextureType::Normal(handle)) => {
+								let mut field = vec![2];
+								field.extend(wall_storage.name_for(handle)?.as_bytes());
+								field
+							}
+						});
+					}
+				}
+
+				Some((entity, fields))
+			})
+			.collect()
+	}
+
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()> {
+		check_field_count(fields, 7)?;
+		let index = read_u32(&fields[0])? as usize;
+		let mut textures = Vec::with_capacity(6);
+
+		for field in &fields[1..] {
+			textures.push(match field.first() {
+				Some(0) => TextureType::None,
+				Some(1) => TextureType::Sky,
+				Some(2) => {
+					let name = std::str::from_utf8(&field[1..])?;
+					TextureType::Normal(
+						world
+							.fetch::<AssetStorage<Wall>>()
+							.handle_for(name)
+							.ok_or_else(|| anyhow!("Wall texture {} is not loaded", name))?,
+					)
+				}
+				x => bail!("unknown texture type {:?}", x),
+			});
+		}
+
+		let (entities, mut map_dynamic_component, mut linedef_ref_component) =
+			world.system_data::<(Entities, WriteStorage<MapDynamic>, WriteStorage<LinedefRef>)>();
+		let (map_entity, map_dynamic) = (&entities, &mut map_dynamic_component)
+			.join()
+			.next()
+			.ok_or_else(|| anyhow!("No map is loaded"))?;
+		let linedef_dynamic = map_dynamic
+			.linedefs
+			.get_mut(index)
+			.ok_or_else(|| anyhow!("Linedef index {} is out of range", index))?;
+
+		for (sidedef_dynamic, textures) in
+			linedef_dynamic.sidedefs.iter_mut().zip(textures.chunks(3))
+		{
+			if let Some(sidedef_dynamic) = sidedef_dynamic {
+				sidedef_dynamic.textures.clone_from_slice(textures);
+			}
+		}
+
+		linedef_ref_component.insert(entity, LinedefRef { map_entity, index })?;
+		Ok(())
+	}
+
+	fn remove(world: &World, entity: Entity) {
+		let (map_storage, mut map_dynamic_component, mut linedef_ref_component) = world
+			.system_data::<(
+				ReadExpect<AssetStorage<Map>>,
+				WriteStorage<MapDynamic>,
+				WriteStorage<LinedefRef>,
+			)>();
+
+		if let Some(linedef_ref) = linedef_ref_component.remove(entity) {
+			if let Some(map_dynamic) = map_dynamic_component.get_mut(linedef_ref.map_entity) {
+				let map = map_storage.get(&map_dynamic.map).unwrap();
+				let linedef = &map.linedefs[linedef_ref.index];
+				let linedef_dynamic = &mut map_dynamic.linedefs[linedef_ref.index];
+
+				for (sidedef_dynamic, sidedef) in linedef_dynamic
+					.sidedefs
+					.iter_mut()
+					.zip(linedef.sidedefs.iter())
+				{
+					if let (Some(sidedef_dynamic), Some(sidedef)) = (sidedef_dynamic, sidedef) {
+						sidedef_dynamic.textures = sidedef.textures.clone();
+					}
+				}
+			}
+		}
+	}
+}
+
+impl Replicate for PlayerEffects {
+	const ID: u8 = 6;
+	const NAME: &'static str = "PlayerEffects";
+
+	fn write(world: &World) -> Vec<(Entity, Fields)> {
+		let (entities, player_effects_component) =
+			world.system_data::<(Entities, ReadStorage<PlayerEffects>)>();
+
+		(&entities, &player_effects_component)
+			.join()
+			.map(|(entity, player_effects)| {
+				// Powers are sent in milliseconds
+				let fields = vec![
+					player_effects.damage_count.to_be_bytes().to_vec(),
+					player_effects.bonus_count.to_be_bytes().to_vec(),
+					(player_effects.invulnerability.as_millis() as u32)
+						.to_be_bytes()
+						.to_vec(),
+					(player_effects.light_amp.as_millis() as u32)
+						.to_be_bytes()
+						.to_vec(),
+					(player_effects.radiation_suit.as_millis() as u32)
+						.to_be_bytes()
+						.to_vec(),
+				];
+				(entity, fields)
+			})
+			.collect()
+	}
+
+	fn apply(world: &World, entity: Entity, fields: &[Vec<u8>]) -> anyhow::Result<()> {
+		check_field_count(fields, 5)?;
+		let player_effects = PlayerEffects {
+			damage_count: read_u32(&fields[0])?,
+			bonus_count: read_u32(&fields[1])?,
+			invulnerability: Duration::from_millis(read_u32(&fields[2])? as u64),
+			light_amp: Duration::from_millis(read_u32(&fields[3])? as u64),
+			radiation_suit: Duration::from_millis(read_u32(&fields[4])? as u64),
+		};
+
+		world
+			.write_component::<PlayerEffects>()
+			.insert(entity, player_effects)?;
+		Ok(())
+	}
+
+	fn remove(world: &World, entity: Entity) {
+		world.write_component::<PlayerEffects>().remove(entity);
+	}
+}
+
+/*
+	Server side
+*/
+
+// Bytes per second available for snapshots, per client
+const BANDWIDTH: f64 = 64_000.0;
+
+// Distance at which an entity's priority is halved
+const PRIORITY_DISTANCE: f32 = 512.0;
+
+// Number of snapshots kept around to be used as baselines
+const SNAPSHOT_HISTORY: u32 = 64;
+
+pub type WorldState<K = Entity> = HashMap<K, HashMap<u8, Arc<Fields>>>;
+
+/// Collects the replicated state of all entities in the world.
+pub fn write_world_state(world: &World) -> WorldState {
+	let registry = world.fetch::<ReplicationRegistry>();
+	let mut state = WorldState::new();
+
+	for (&id, replicated_type) in registry.types.iter() {
+		for (entity, fields) in (replicated_type.write)(world) {
+			state
+				.entry(entity)
+				.or_default()
+				.insert(id, Arc::new(fields));
+		}
+	}
+
+	state
+}
+
+struct SentSnapshot {
+	number: u32,
+	state: WorldState,
+	included: Vec<Entity>,
+}
+
+/// Tracks the world state that a client has acknowledged, and builds snapshots against it.
+#[derive(Default)]
+pub struct ClientReplication {
+	acked: Option<(u32, WorldState)>,
+	sent: VecDeque<SentSnapshot>,
+	last_acked: HashMap<Entity, u32>,
+}
+
+impl ClientReplication {
+	pub fn ack(&mut self, number: u32) {
+		while let Some(sent) = self.sent.front() {
+			if sent.number > number {
+				break;
+			}
+
+			let sent = self.sent.pop_front().unwrap();
+
+			if sent.number == number {
+				for entity in sent.included {
+					self.last_acked.insert(entity, number);
+				}
+
+				self.acked = Some((number, sent.state));
+			}
+		}
+	}
+
+	/// Returns whether the client has been sent the entity.
+	pub fn knows(&self, entity: Entity) -> bool {
+		self.sent
+			.back()
+			.map(|sent| &sent.state)
+			.or_else(|| self.acked.as_ref().map(|(_, state)| state))
+			.map_or(false, |state| state.contains_key(&entity))
+	}
+
+	/// Builds a snapshot of the changes since the last acknowledged one.
+	/// Entities are included in order of priority until the bandwidth budget is used up,
+	/// with entities closer to the view entity and those that went without updates for
+	/// longer taking priority. The view entity itself is always included, as the client
+	/// predicts its movement and needs its current state to correct it.
+	pub fn build_snapshot(
+		&mut self,
+		number: u32,
+		state: &WorldState,
+		positions: &HashMap<Entity, Vector3<f32>>,
+		view_entity: Option<Entity>,
+	) -> Snapshot {
+		// The client fell too far behind, start from scratch
+		if let Some((acked_number, _)) = &self.acked {
+			if number - acked_number > SNAPSHOT_HISTORY {
+				*self = ClientReplication::default();
+			}
+		}
+
+		let empty = WorldState::new();
+		let (baseline_number, baseline) = match &self.acked {
+			Some((number, state)) => (*number, state),
+			None => (0, &empty),
+		};
+
+		let mut deltas = Vec::new();
+		let mut new_state = baseline.clone();
+		let mut size = 0;
+
+		for entity in baseline.keys().filter(|e| !state.contains_key(e)) {
+			deltas.push(SnapshotDelta::EntityDelete(entity.id()));
+			new_state.remove(entity);
+			size += 5;
+		}
+
+		// Entities sent since the baseline must be sent again even if over budget,
+		// otherwise the client would revert them to the baseline state
+		let pending: HashSet<Entity> = self
+			.sent
+			.iter()
+			.flat_map(|sent| sent.included.iter().copied())
+			.chain(view_entity)
+			.collect();
+		let view_position = view_entity.and_then(|e| positions.get(&e));
+
+		struct Candidate {
+			entity: Entity,
+			pending: bool,
+			priority: f32,
+			deltas: Vec<SnapshotDelta>,
+			size: usize,
+		}
+
+		let mut candidates: Vec<Candidate> = state
+			.iter()
+			.filter_map(|(&entity, components)| {
+				let base = baseline.get(&entity);
+				let mut entity_deltas = Vec::new();
+				let mut entity_size = 0;
+
+				for &id in base.into_iter().flat_map(|b| b.keys()) {
+					if !components.contains_key(&id) {
+						entity_deltas.push(SnapshotDelta::ComponentDelete(entity.id(), id));
+						entity_size += 6;
+					}
+				}
+
+				for (&id, fields) in components.iter() {
+					let base_fields = base.and_then(|b| b.get(&id)).map(|f| &**f);
+
+					if let Some(data) = encode_delta(base_fields, fields) {
+						entity_size += 10 + data.len();
+						entity_deltas.push(SnapshotDelta::ComponentDelta(entity.id(), id, data));
+					}
+				}
+
+				if entity_deltas.is_empty() {
+					return None;
+				}
+
+				let starvation =
+					number - self.last_acked.get(&entity).map_or(0, |&n| n.min(number));
+				let distance = match (positions.get(&entity), view_position) {
+					(Some(position), Some(view_position)) => (position - view_position).norm(),
+					_ => 0.0,
+				};
+
+				Some(Candidate {
+					entity,
+					pending: pending.contains(&entity),
+					priority: starvation as f32 / (1.0 + distance / PRIORITY_DISTANCE),
+					deltas: entity_deltas,
+					size: entity_size,
+				})
+			})
+			.collect();
+
+		candidates.sort_unstable_by(|a, b| {
+			b.pending.cmp(&a.pending).then(
+				b.priority
+					.partial_cmp(&a.priority)
+					.unwrap_or(Ordering::Equal),
+			)
+		});
+
+		let budget = (BANDWIDTH * FRAME_TIME.as_secs_f64()) as usize;
+		let mut included = Vec::new();
+
+		for candidate in candidates {
+			if !candidate.pending && size + candidate.size > budget {
+				continue;
+			}
+
+			size += candidate.size;
+			deltas.extend(candidate.deltas);
+			new_state.insert(candidate.entity, state[&candidate.entity].clone());
+			included.push(candidate.entity);
+		}
+
+		self.sent.push_back(SentSnapshot {
+			number,
+			state: new_state,
+			included,
+		});
+
+		if self.sent.len() > SNAPSHOT_HISTORY as usize {
+			self.sent.pop_front();
+		}
+
+		self.last_acked
+			.retain(|entity, _| state.contains_key(entity));
+
+		Snapshot {
+			number,
+			baseline: baseline_number,
+			deltas,
+		}
+	}
+}
+
+/*
+	Client side
+*/
+
+/// Applies snapshots received from the server to the client world.
+#[derive(Default)]
+pub struct SnapshotReceiver {
+	entities: HashMap<u32, Entity>,
+	history: VecDeque<(u32, WorldState<u32>)>,
+}
+
+impl SnapshotReceiver {
+	pub fn entity(&self, entity_id: u32) -> anyhow::Result<Entity> {
+		self.entities
+			.get(&entity_id)
+			.copied()
+			.ok_or_else(|| anyhow!("Server entity {} does not exist", entity_id))
+	}
+
+	/// The number of the most recently applied snapshot.
+	pub fn latest(&self) -> Option<u32> {
+		self.history.back().map(|(number, _)| *number)
+	}
+
+	pub fn clear(&mut self) {
+		self.entities.clear();
+		self.history.clear();
+	}
+
+	/// Applies the last received state of an entity again, undoing any local changes.
+	pub fn restore(&self, world: &World, entity: Entity) -> anyhow::Result<()> {
+		let registry = world.fetch::<ReplicationRegistry>();
+		let components = self
+			.entities
+			.iter()
+			.find(|(_, &e)| e == entity)
+			.and_then(|(entity_id, _)| self.history.back()?.1.get(entity_id))
+			.ok_or_else(|| anyhow!("Entity {:?} has no server state", entity))?;
+
+		for (&component_id, fields) in components.iter() {
+			(registry.get(component_id)?.apply)(world, entity, fields)?;
+		}
+
+		Ok(())
+	}
+
+	pub fn apply(&mut self, world: &World, snapshot: Snapshot) -> anyhow::Result<()> {
+		if self
+			.latest()
+			.map_or(false, |latest| snapshot.number <= latest)
+		{
+			// Arrived out of order
+			return Ok(());
+		}
+
+		let mut state = if snapshot.baseline == 0 {
+			WorldState::new()
+		} else {
+			self.history
+				.iter()
+				.find(|(number, _)| *number == snapshot.baseline)
+				.map(|(_, state)| state.clone())
+				.ok_or_else(|| anyhow!("Baseline snapshot {} is unknown", snapshot.baseline))?
+		};
+		let mut deleted = HashSet::new();
+
+		for delta in snapshot.deltas {
+			match delta {
+				SnapshotDelta::EntityDelete(entity_id) => {
+					state.remove(&entity_id);
+					deleted.insert(entity_id);
+				}
+				SnapshotDelta::ComponentDelete(entity_id, component_id) => {
+					if let Some(components) = state.get_mut(&entity_id) {
+						components.remove(&component_id);
+					}
+				}
+				SnapshotDelta::ComponentDelta(entity_id, component_id, data) => {
+					let components = state.entry(entity_id).or_default();
+					let fields = decode_delta(components.get(&component_id).map(|f| &**f), &data)?;
+					components.insert(component_id, Arc::new(fields));
+				}
+			}
+		}
+
+		state.retain(|_, components| !components.is_empty());
+		self.update_world(world, &state, &deleted)?;
+		self.history.push_back((snapshot.number, state));
+
+		if self.history.len() > SNAPSHOT_HISTORY as usize {
+			self.history.pop_front();
+		}
+
+		Ok(())
+	}
+
+	fn update_world(
+		&mut self,
+		world: &World,
+		state: &WorldState<u32>,
+		deleted: &HashSet<u32>,
+	) -> anyhow::Result<()> {
+		let registry = world.fetch::<ReplicationRegistry>();
+		let empty = WorldState::new();
+		let old_state = self.history.back().map_or(&empty, |(_, state)| state);
+
+		// A deleted id that is present again belongs to a new server entity
+		let to_delete: Vec<u32> = self
+			.entities
+			.keys()
+			.copied()
+			.filter(|id| !state.contains_key(id) || deleted.contains(id))
+			.collect();
+
+		for entity_id in to_delete {
+			let entity = self.entities.remove(&entity_id).unwrap();
+
+			// Some components change the map rather than the entity, so undo them first
+			for &component_id in old_state.get(&entity_id).into_iter().flat_map(|c| c.keys()) {
+				(registry.get(component_id)?.remove)(world, entity);
+			}
+
+			world.entities().delete(entity)?;
+
+			let mut client = world.fetch_mut::<Client>();
+
+			if client.entity == Some(entity) {
+				client.entity = None;
+			}
+		}
+
+		for (entity_id, components) in state.iter() {
+			let (entity, old_components) = match self.entities.get(entity_id) {
+				Some(&entity) => (entity, old_state.get(entity_id)),
+				None => {
+					let entity = world.entities().create();
+					self.entities.insert(*entity_id, entity);
+					(entity, None)
+				}
+			};
+
+			for &component_id in old_components.into_iter().flat_map(|c| c.keys()) {
+				if !components.contains_key(&component_id) {
+					(registry.get(component_id)?.remove)(world, entity);
+				}
+			}
+
+			for (&component_id, fields) in components.iter() {
+				if old_components.and_then(|c| c.get(&component_id)) != Some(fields) {
+					(registry.get(component_id)?.apply)(world, entity, fields)?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+}
@@ -0,0 +1,508 @@
+use crate::{
+	assets::{AssetHandle, AssetStorage},
+	audio::Sound,
+	doom::{
+		client::User,
+		components::Transform,
+		input::UserCommand,
+		map::spawn_player,
+		replication::{self, ClientReplication},
+		sound::SoundOrigin,
+	},
+	net::{Addr, SequencedChannel, Socket},
+	protocol::{ClientMessage, Packet, ServerMessage},
+	quadtree::Quadtree,
+};
+use nalgebra::Vector3;
+use specs::{
+	Entities, Entity, Join, ReadExpect, ReadStorage, RunNow, World, WorldExt, WriteExpect,
+};
+use std::{
+	collections::{HashMap, VecDeque},
+	convert::TryFrom,
+	net::IpAddr,
+	sync::Arc,
+	time::{Duration, Instant},
+};
+
+const MAX_PLAYERS: usize = 4;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Commands that arrive faster than they are run are dropped beyond this
+const MAX_QUEUED_COMMANDS: usize = 8;
+
+// After this many wrong rcon passwords, a host is ignored for a while
+const RCON_MAX_FAILURES: u32 = 3;
+const RCON_BLOCK_TIME: Duration = Duration::from_secs(30);
+
+pub struct Server {
+	clients: HashMap<Addr, ServerClient>,
+	map: Option<String>,
+	rcon_commands: Vec<(Addr, String)>,
+	rcon_failures: HashMap<IpAddr, (u32, Instant)>,
+	rcon_password: Option<String>,
+	scores_changed: bool,
+	snapshot_number: u32,
+	socket: Arc<Socket>,
+}
+
+struct ServerClient {
+	channel: SequencedChannel<ServerMessage, ClientMessage>,
+	command: UserCommand,
+	command_number: u32,
+	commands: VecDeque<(u32, UserCommand)>,
+	entity: Option<Entity>,
+	frags: i32,
+	last_received: Instant,
+	needs_map: bool,
+	player_num: usize,
+	replication: ClientReplication,
+}
+
+impl Server {
+	pub fn new(socket: Socket, rcon_password: Option<String>) -> Server {
+		Server {
+			clients: HashMap::new(),
+			map: None,
+			rcon_commands: Vec::new(),
+			rcon_failures: HashMap::new(),
+			rcon_password,
+			scores_changed: false,
+			snapshot_number: 0,
+			socket: Arc::new(socket),
+		}
+	}
+
+	/// Informs the connected clients of a map change.
+	/// Their player entities were deleted along with the old map, and are spawned again.
+	pub fn set_map(&mut self, name: &str) {
+		self.map = Some(name.to_owned());
+		self.scores_changed = true;
+
+		for client in self.clients.values_mut() {
+			client.entity = None;
+			client.frags = 0;
+			client.needs_map = true;
+			client.replication = ClientReplication::default();
+		}
+	}
+
+	pub fn log_status(&self) {
+		if let Some(map) = &self.map {
+			log::info!("Map: {}", map);
+		}
+
+		log::info!("{} client(s) connected", self.clients.len());
+
+		for (addr, client) in self.clients.iter() {
+			let stats = client.channel.stats();
+			log::info!(
+				"Player {}: {}, RTT {} ms, packet loss {:.1}%",
+				client.player_num,
+				addr,
+				stats.rtt.as_millis(),
+				stats.packet_loss * 100.0,
+			);
+		}
+	}
+
+	pub fn has_map(&self) -> bool {
+		self.map.is_some()
+	}
+
+	pub fn map(&self) -> Option<&str> {
+		self.map.as_deref()
+	}
+
+	/// Player numbers and frags, highest first.
+	pub fn scores(&self) -> Vec<(usize, i32)> {
+		let mut scores: Vec<(usize, i32)> = self
+			.clients
+			.values()
+			.map(|client| (client.player_num, client.frags))
+			.collect();
+		scores.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+		scores
+	}
+
+	pub fn log_scores(&self) {
+		for (player_num, frags) in self.scores() {
+			log::info!("Player {}: {} frag(s)", player_num, frags);
+		}
+	}
+
+	pub fn add_frags(&mut self, player_num: usize, frags: i32) {
+		if let Some(client) = self
+			.clients
+			.values_mut()
+			.find(|client| client.player_num == player_num)
+		{
+			client.frags += frags;
+			self.scores_changed = true;
+		}
+	}
+
+	/// Takes the entity away from a player, so that they respawn on the next tic.
+	pub fn kill_player(&mut self, player_num: usize) -> Option<Entity> {
+		self.clients
+			.values_mut()
+			.find(|client| client.player_num == player_num)
+			.and_then(|client| client.entity.take())
+	}
+
+	/// Returns the rcon commands that were received and authenticated.
+	pub fn take_rcon_commands(&mut self) -> Vec<(Addr, String)> {
+		std::mem::take(&mut self.rcon_commands)
+	}
+
+	/// Sends the output of an rcon command back, one message per line.
+	pub fn rcon_reply(&self, addr: Addr, lines: Vec<String>) {
+		for line in lines {
+			self.socket.send_to(
+				Packet::Unsequenced(vec![ServerMessage::RConOutput(line)]).into(),
+				addr,
+			);
+		}
+	}
+
+	fn rcon_authenticate(
+		&mut self,
+		addr: Addr,
+		password: &str,
+		now: Instant,
+	) -> Result<(), &'static str> {
+		let rcon_password = match &self.rcon_password {
+			Some(rcon_password) => rcon_password,
+			None => return Err("Rcon is disabled on this server"),
+		};
+
+		// Forget old failures, so that the map doesn't keep growing
+		self.rcon_failures
+			.retain(|_, (_, last)| now - *last < RCON_BLOCK_TIME);
+
+		let failures = addr
+			.ip()
+			.and_then(|ip| self.rcon_failures.get(&ip))
+			.copied();
+
+		if let Some((count, _)) = failures {
+			if count >= RCON_MAX_FAILURES {
+				return Err("Too many failed attempts, try again later");
+			}
+		}
+
+		// Compare without exiting early, so the time taken doesn't give away the password
+		let matches = password.len() == rcon_password.len()
+			&& password
+				.bytes()
+				.zip(rcon_password.bytes())
+				.fold(0, |acc, (a, b)| acc | (a ^ b))
+				== 0;
+
+		if let Some(ip) = addr.ip() {
+			if matches {
+				self.rcon_failures.remove(&ip);
+			} else {
+				let (count, last) = self.rcon_failures.entry(ip).or_insert((0, now));
+				*count += 1;
+				*last = now;
+			}
+		}
+
+		if matches {
+			Ok(())
+		} else {
+			log::warn!("Wrong rcon password from {}", addr);
+			Err("Wrong password")
+		}
+	}
+
+	pub fn shutdown(&mut self) {
+		for client in self.clients.values_mut() {
+			client.channel.send_reliable(ServerMessage::Disconnect);
+			client.channel.flush();
+		}
+
+		self.clients.clear();
+	}
+}
+
+#[derive(Default)]
+pub struct ServerReceiveSystem;
+
+impl<'a> RunNow<'a> for ServerReceiveSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let now = Instant::now();
+		let mut to_delete = Vec::new();
+		let mut to_spawn = Vec::new();
+
+		{
+			let mut server = world.fetch_mut::<Server>();
+			let server = &mut *server;
+
+			while let Some((data, addr)) = server.socket.next() {
+				let packet = match Packet::<ClientMessage>::try_from(data) {
+					Ok(packet) => packet,
+					Err(err) => {
+						log::warn!("Received a malformed packet from {}: {}", addr, err);
+						continue;
+					}
+				};
+
+				let messages = match packet {
+					Packet::Unsequenced(messages) => messages,
+					Packet::Sequenced(packet) => match server.clients.get_mut(&addr) {
+						Some(client) => client.channel.process(packet),
+						None => continue,
+					},
+				};
+
+				for message in messages {
+					match message {
+						ClientMessage::Connect => {
+							if server.clients.contains_key(&addr) {
+								// Already connected, the response is on its way
+								continue;
+							}
+
+							let player_num = (1..=MAX_PLAYERS)
+								.find(|num| server.clients.values().all(|c| c.player_num != *num));
+
+							let player_num = match player_num {
+								Some(player_num) => player_num,
+								None => {
+									log::info!("Rejected connection from {}: server is full", addr);
+									server.socket.send_to(
+										Packet::Unsequenced(vec![ServerMessage::Disconnect]).into(),
+										addr,
+									);
+									continue;
+								}
+							};
+
+							log::info!("Player {} connected from {}", player_num, addr);
+							let mut client = ServerClient {
+								channel: SequencedChannel::new(server.socket.clone(), addr),
+								command: UserCommand::default(),
+								command_number: 0,
+								commands: VecDeque::new(),
+								entity: None,
+								frags: 0,
+								last_received: now,
+								needs_map: true,
+								player_num,
+								replication: ClientReplication::default(),
+							};
+							client.channel.send_reliable(ServerMessage::ConnectResponse);
+							server.clients.insert(addr, client);
+							server.scores_changed = true;
+						}
+						ClientMessage::Disconnect => {
+							if let Some(client) = server.clients.remove(&addr) {
+								log::info!("Player {} disconnected", client.player_num);
+								to_delete.extend(client.entity);
+								server.scores_changed = true;
+							}
+						}
+						ClientMessage::RCon(password, command) => {
+							match server.rcon_authenticate(addr, &password, now) {
+								Ok(()) => server.rcon_commands.push((addr, command)),
+								Err(err) => server.rcon_reply(addr, vec![err.to_owned()]),
+							}
+						}
+						ClientMessage::SnapshotAck(number) => {
+							if let Some(client) = server.clients.get_mut(&addr) {
+								client.replication.ack(number);
+							}
+						}
+						ClientMessage::UserCommand(number, command) => {
+							if let Some(client) = server.clients.get_mut(&addr) {
+								// Commands are sent more than once in case of packet loss
+								let last_number = client
+									.commands
+									.back()
+									.map_or(client.command_number, |c| c.0);
+
+								if number > last_number {
+									client.commands.push_back((number, command));
+								}
+							}
+						}
+					}
+				}
+
+				if let Some(client) = server.clients.get_mut(&addr) {
+					client.last_received = now;
+				}
+			}
+
+			// Drop clients that have gone silent
+			let timed_out: Vec<Addr> = server
+				.clients
+				.iter()
+				.filter(|(_, client)| now - client.last_received >= TIMEOUT)
+				.map(|(addr, _)| *addr)
+				.collect();
+
+			for addr in timed_out {
+				let client = server.clients.remove(&addr).unwrap();
+				log::info!("Player {} timed out", client.player_num);
+				to_delete.extend(client.entity);
+				server.scores_changed = true;
+			}
+
+			if server.map.is_some() {
+				to_spawn.extend(
+					server
+						.clients
+						.iter()
+						.filter(|(_, client)| client.entity.is_none())
+						.map(|(addr, client)| (*addr, client.player_num)),
+				);
+			}
+		}
+
+		// Remove players that left
+		for entity in to_delete {
+			world.fetch_mut::<Quadtree>().remove(entity);
+			world.entities().delete(entity).ok();
+		}
+
+		// Spawn players for new clients
+		for (addr, player_num) in to_spawn {
+			match spawn_player(world, player_num) {
+				Ok(entity) => {
+					let mut server = world.fetch_mut::<Server>();
+					server.clients.get_mut(&addr).unwrap().entity = Some(entity);
+				}
+				Err(err) => log::error!("Could not spawn player {}: {}", player_num, err),
+			}
+		}
+
+		// Pass the commands on to the player entities, one per tic.
+		// If none arrived in time, the last one is repeated.
+		let mut server = world.fetch_mut::<Server>();
+		let mut user_component = world.write_component::<User>();
+
+		for client in server.clients.values_mut() {
+			while client.commands.len() > MAX_QUEUED_COMMANDS {
+				client.commands.pop_front();
+			}
+
+			if let Some((number, command)) = client.commands.pop_front() {
+				client.command_number = number;
+				client.command = command;
+			}
+
+			if let Some(user) = client.entity.and_then(|e| user_component.get_mut(e)) {
+				user.previous_command = user.command;
+				user.command = client.command;
+			}
+		}
+	}
+}
+
+#[derive(Default)]
+pub struct ServerSendSystem;
+
+impl<'a> RunNow<'a> for ServerSendSystem {
+	fn setup(&mut self, _world: &mut World) {}
+
+	fn run_now(&mut self, world: &'a World) {
+		let state = replication::write_world_state(world);
+		let (entities, mut server, sound_storage, mut sound_queue, transform_component) = world
+			.system_data::<(
+				Entities,
+				WriteExpect<Server>,
+				ReadExpect<AssetStorage<Sound>>,
+				WriteExpect<Vec<(AssetHandle<Sound>, SoundOrigin)>>,
+				ReadStorage<Transform>,
+			)>();
+		let server = &mut *server;
+		let sounds: Vec<(SoundOrigin, String)> = sound_queue
+			.drain(..)
+			.filter_map(|(handle, origin)| {
+				sound_storage
+					.name_for(&handle)
+					.map(|name| (origin, name.to_owned()))
+			})
+			.collect();
+		let positions: HashMap<Entity, Vector3<f32>> = (&entities, &transform_component)
+			.join()
+			.map(|(entity, transform)| (entity, transform.position))
+			.collect();
+
+		server.snapshot_number += 1;
+
+		let scoreboard = if std::mem::take(&mut server.scores_changed) {
+			Some(
+				server
+					.scores()
+					.into_iter()
+					.map(|(player_num, frags)| (player_num as u8, frags))
+					.collect::<Vec<_>>(),
+			)
+		} else {
+			None
+		};
+
+		for client in server.clients.values_mut() {
+			if client.needs_map {
+				if let Some(map) = &server.map {
+					client
+						.channel
+						.send_reliable(ServerMessage::MapChange(map.clone()));
+					client.needs_map = false;
+				} else {
+					client.channel.flush();
+					continue;
+				}
+			}
+
+			if let Some(scoreboard) = &scoreboard {
+				client
+					.channel
+					.send_reliable(ServerMessage::Scoreboard(scoreboard.clone()));
+			}
+
+			let snapshot = client.replication.build_snapshot(
+				server.snapshot_number,
+				&state,
+				&positions,
+				client.entity,
+			);
+			client
+				.channel
+				.send_unreliable(ServerMessage::Snapshot(snapshot));
+
+			if let Some(entity) = client.entity {
+				client
+					.channel
+					.send_unreliable(ServerMessage::PlayerEntity(entity.id()));
+			}
+
+			client
+				.channel
+				.send_unreliable(ServerMessage::CommandAck(client.command_number));
+
+			for (origin, name) in sounds.iter() {
+				let origin = match *origin {
+					SoundOrigin::Global => None,
+					SoundOrigin::Player(entity) if client.entity == Some(entity) => None,
+					SoundOrigin::Entity(entity, channel) if client.replication.knows(entity) => {
+						Some((entity.id(), channel as u8))
+					}
+					_ => continue,
+				};
+
+				client
+					.channel
+					.send_unreliable(ServerMessage::SoundPlay(origin, name.clone()));
+			}
+
+			client.channel.flush();
+		}
+	}
+}
@@ -1,20 +1,28 @@
 use crate::{
 	assets::{Asset, AssetHandle, AssetStorage, DataSource},
-	audio::{Sound, SoundController, SoundSource},
-	doom::{client::Client, components::Transform},
+	audio::{compressed_format, decode_compressed, ChannelHandle, Mixer, Sound, OUTPUT_RATE},
+	configvars::ConfigVariables,
+	doom::{
+		client::Client,
+		components::Transform,
+		data::sound_info,
+		map::{Map, MapDynamic},
+	},
 	geometry::Angle,
 };
-use anyhow::ensure;
+use anyhow::{anyhow, bail, ensure, Context};
 use byteorder::{ReadBytesExt, LE};
-use crossbeam_channel::Sender;
-use nalgebra::Vector2;
+use nalgebra::{Vector2, Vector3};
 use rodio::Source;
 use specs::{
 	Component, DenseVecStorage, Entities, Entity, Join, ReadExpect, ReadStorage, RunNow, World,
 	WriteExpect, WriteStorage,
 };
 use specs_derive::Component;
-use std::io::{Cursor, Read};
+use std::{
+	convert::TryFrom,
+	io::{Cursor, Read},
+};
 
 impl Asset for Sound {
 	type Data = Self;
@@ -27,20 +35,53 @@ impl Asset for Sound {
 }
 
 pub fn build_sound(data: Vec<u8>) -> anyhow::Result<Sound> {
+	let sound = if compressed_format(&data).is_some() {
+		build_compressed_sound(data)?
+	} else if data.starts_with(b"RIFF") {
+		build_wav_sound(&data)?
+	} else {
+		build_dmx_sound(&data)?
+	};
+
+	// The mixer's interpolation doesn't filter out frequencies that are too high to play
+	if sound.sample_rate > OUTPUT_RATE {
+		Ok(Sound {
+			data: resample(&sound.data, sound.sample_rate, OUTPUT_RATE).into(),
+			sample_rate: OUTPUT_RATE,
+		})
+	} else {
+		Ok(sound)
+	}
+}
+
+fn build_dmx_sound(data: &[u8]) -> anyhow::Result<Sound> {
 	let mut reader = Cursor::new(data);
 	let signature = reader.read_u16::<LE>()?;
 
 	ensure!(signature == 3, "No Doom sound file signature found");
 
 	let sample_rate = reader.read_u16::<LE>()? as u32;
-	let sample_count = reader.read_u32::<LE>()? as usize;
+	let mut sample_count = reader.read_u32::<LE>()? as usize;
+
+	// Some lumps claim to have more samples than they do
+	let available = data.len() - reader.position() as usize;
+
+	if sample_count > available {
+		log::warn!(
+			"Sound has {} samples, but its header says {}",
+			available,
+			sample_count
+		);
+		sample_count = available;
+	}
 
-	// Read in the samples
+	ensure!(sample_count > 32, "Sound has no samples");
+
+	// Read in the samples, skipping the padding at both ends
 	let mut data = vec![0u8; sample_count - 32];
 	let mut padding = [0u8; 16];
 	reader.read_exact(&mut padding)?;
 	reader.read_exact(&mut data)?;
-	reader.read_exact(&mut padding)?;
 
 	// Convert to i16
 	let data = data
@@ -54,6 +95,142 @@ pub fn build_sound(data: Vec<u8>) -> anyhow::Result<Sound> {
 	})
 }
 
+fn build_wav_sound(data: &[u8]) -> anyhow::Result<Sound> {
+	let mut reader = Cursor::new(data);
+	let mut id = [0u8; 4];
+	reader.read_exact(&mut id)?;
+	reader.read_u32::<LE>()?;
+	reader.read_exact(&mut id)?;
+
+	ensure!(&id == b"WAVE", "No WAVE signature found");
+
+	// Format tag, channels, sample rate, bits per sample
+	let mut format = None;
+
+	loop {
+		reader.read_exact(&mut id)?;
+		let size = reader.read_u32::<LE>()? as usize;
+		let start = reader.position() as usize;
+		let end = start
+			.checked_add(size)
+			.unwrap_or(usize::MAX)
+			.min(data.len());
+
+		match &id {
+			b"fmt " => {
+				let mut format_tag = reader.read_u16::<LE>()?;
+				let channels = reader.read_u16::<LE>()? as usize;
+				let sample_rate = reader.read_u32::<LE>()?;
+				reader.read_u32::<LE>()?; // Bytes per second
+				reader.read_u16::<LE>()?; // Bytes per frame
+				let bits = reader.read_u16::<LE>()?;
+
+				// The extensible format has the real format tag in its subformat GUID
+				if format_tag == 0xFFFE && size >= 26 {
+					reader.set_position(start as u64 + 24);
+					format_tag = reader.read_u16::<LE>()?;
+				}
+
+				ensure!(channels > 0, "WAV sound has no channels");
+				format = Some((format_tag, channels, sample_rate, bits));
+			}
+			b"data" => {
+				let (format_tag, channels, sample_rate, bits) =
+					format.ok_or_else(|| anyhow!("WAV sound has no format chunk"))?;
+				let samples = &data[start..end];
+
+				// Convert to i16
+				let data: Vec<i16> = match (format_tag, bits) {
+					(1, 8) => samples.iter().map(|&x| ((x ^ 0x80) as i16) << 8).collect(),
+					(1, 16) => samples
+						.chunks_exact(2)
+						.map(|x| i16::from_le_bytes([x[0], x[1]]))
+						.collect(),
+					(1, 24) => samples
+						.chunks_exact(3)
+						.map(|x| i16::from_le_bytes([x[1], x[2]]))
+						.collect(),
+					(1, 32) => samples
+						.chunks_exact(4)
+						.map(|x| i16::from_le_bytes([x[2], x[3]]))
+						.collect(),
+					(3, 32) => samples
+						.chunks_exact(4)
+						.map(|x| {
+							let sample = f32::from_le_bytes([x[0], x[1], x[2], x[3]]);
+							(sample.clamp(-1.0, 1.0) * 32767.0) as i16
+						})
+						.collect(),
+					_ => bail!(
+						"Unsupported WAV sample format {} with {} bits",
+						format_tag,
+						bits
+					),
+				};
+
+				let data = mix_down(&data, channels);
+
+				ensure!(!data.is_empty(), "Sound has no samples");
+
+				return Ok(Sound {
+					sample_rate,
+					data: data.into(),
+				});
+			}
+			_ => {}
+		}
+
+		// Chunks are padded to an even size
+		reader.set_position((end + (size & 1)) as u64);
+	}
+}
+
+fn build_compressed_sound(data: Vec<u8>) -> anyhow::Result<Sound> {
+	let decoder = decode_compressed(data.into()).context("Couldn't decode sound")?;
+	let channels = decoder.channels() as usize;
+	let sample_rate = decoder.sample_rate();
+
+	ensure!(channels > 0, "Sound has no channels");
+
+	let data = mix_down(&decoder.collect::<Vec<i16>>(), channels);
+
+	ensure!(!data.is_empty(), "Sound has no samples");
+
+	Ok(Sound {
+		sample_rate,
+		data: data.into(),
+	})
+}
+
+/// Averages interleaved channels together into one.
+fn mix_down(data: &[i16], channels: usize) -> Vec<i16> {
+	data.chunks_exact(channels)
+		.map(|frame| (frame.iter().map(|&x| x as i32).sum::<i32>() / channels as i32) as i16)
+		.collect()
+}
+
+/// Resamples a sound to a new sample rate, by averaging together the samples that fall in
+/// each new sample, or interpolating between them if there are fewer.
+fn resample(data: &[i16], from_rate: u32, to_rate: u32) -> Vec<i16> {
+	let step = from_rate as f64 / to_rate as f64;
+	let len = (data.len() as f64 / step) as usize;
+
+	(0..len)
+		.map(|i| {
+			let start = i as f64 * step;
+			let end = ((start + step) as usize).min(data.len());
+			let start = start as usize;
+
+			if end > start + 1 {
+				(data[start..end].iter().map(|&x| x as i32).sum::<i32>() / (end - start) as i32)
+					as i16
+			} else {
+				data[start]
+			}
+		})
+		.collect()
+}
+
 #[derive(Default)]
 pub struct SoundSystem;
 
@@ -64,25 +241,64 @@ impl<'a> RunNow<'a> for SoundSystem {
 		let (
 			entities,
 			client,
-			sound_sender,
+			config_variables,
+			map_storage,
+			mixer,
 			sound_storage,
+			map_dynamic_component,
 			transform_component,
 			mut sound_queue,
 			mut sound_playing_component,
 		) = world.system_data::<(
 			Entities,
 			ReadExpect<Client>,
-			ReadExpect<Sender<Box<dyn Source<Item = f32> + Send>>>,
+			ReadExpect<ConfigVariables>,
+			ReadExpect<AssetStorage<Map>>,
+			ReadExpect<Mixer>,
 			ReadExpect<AssetStorage<Sound>>,
+			ReadStorage<MapDynamic>,
 			ReadStorage<Transform>,
-			WriteExpect<Vec<(AssetHandle<Sound>, Entity)>>,
+			WriteExpect<Vec<(AssetHandle<Sound>, SoundOrigin)>>,
 			WriteStorage<SoundPlaying>,
 		)>();
 
 		let mut to_remove = Vec::new();
 
 		// Update currently playing sounds
-		let client_transform = transform_component.get(client.entity.unwrap()).unwrap();
+		let client_transform = match client.entity.and_then(|e| transform_component.get(e)) {
+			Some(transform) => transform,
+			None => {
+				sound_queue.clear();
+				return;
+			}
+		};
+
+		// Find where sound from the client's sector carries, if the map should block sound
+		let occlusion = if *config_variables.get::<bool>("sound-occlusion") {
+			map_dynamic_component.join().next().map(|map_dynamic| {
+				let map = map_storage.get(&map_dynamic.map).unwrap();
+				let sector_index = map
+					.find_subsector(Vector2::new(
+						client_transform.position[0],
+						client_transform.position[1],
+					))
+					.sector_index;
+				(map, map.sound_propagation(map_dynamic, sector_index))
+			})
+		} else {
+			None
+		};
+
+		let volumes_for = |transform: &Transform| {
+			let volumes = calculate_volumes(client_transform, transform);
+
+			match &occlusion {
+				Some((map, propagation)) => {
+					volumes * occlusion_factor(map, propagation, transform.position)
+				}
+				None => volumes,
+			}
+		};
 
 		for (entity, transform, sound_playing) in (
 			&entities,
@@ -91,14 +307,22 @@ impl<'a> RunNow<'a> for SoundSystem {
 		)
 			.join()
 		{
-			if sound_playing.controller.is_done() {
-				to_remove.push(entity);
-				continue;
+			// Set distance falloff and stereo panning
+			let volumes = volumes_for(transform);
+
+			for slot in sound_playing.channels.iter_mut() {
+				if let Some(channel) = *slot {
+					if mixer.is_playing(channel) {
+						mixer.set_volumes(channel, volumes.into());
+					} else {
+						*slot = None;
+					}
+				}
 			}
 
-			// Set distance falloff and stereo panning
-			let volumes = calculate_volumes(client_transform, transform);
-			sound_playing.controller.set_volumes(volumes.into());
+			if sound_playing.channels.iter().all(Option::is_none) {
+				to_remove.push(entity);
+			}
 		}
 
 		// Remove finished sounds
@@ -107,24 +331,53 @@ impl<'a> RunNow<'a> for SoundSystem {
 		}
 
 		// Play new sounds
-		for (handle, entity) in sound_queue.drain(..) {
-			let sound = sound_storage.get(&handle).unwrap();
-			let (controller, source) = SoundController::new(SoundSource::new(&sound));
+		for (handle, origin) in sound_queue.drain(..) {
+			// Sounds that failed to load have already logged an error
+			let sound = match sound_storage.get(&handle) {
+				Some(sound) => sound,
+				None => continue,
+			};
+			let info = sound_storage
+				.name_for(&handle)
+				.map(sound_info)
+				.unwrap_or_else(|| sound_info(""));
 
-			// Set distance falloff and stereo panning
-			let transform = transform_component.get(entity).unwrap();
-			let volumes = calculate_volumes(client_transform, transform);
-			controller.set_volumes(volumes.into());
-
-			// Insert component
-			if let Ok(Some(sound_playing)) =
-				sound_playing_component.insert(entity, SoundPlaying { controller })
-			{
-				// Stop old sound on this entity, if any
-				sound_playing.controller.stop();
-			}
+			match origin {
+				SoundOrigin::Global => {
+					mixer.play(sound, info.priority, info.singular, pan_volumes(0.0).into());
+				}
+				SoundOrigin::Player(entity) => {
+					if client.entity == Some(entity) {
+						mixer.play(sound, info.priority, info.singular, pan_volumes(0.0).into());
+					}
+				}
+				SoundOrigin::Entity(entity, channel) => {
+					let transform = match transform_component.get(entity) {
+						Some(transform) => transform,
+						None => continue,
+					};
+
+					// Sounds that are too far away to hear aren't started at all
+					let volumes = volumes_for(transform);
+
+					if volumes == Vector2::zeros() {
+						continue;
+					}
 
-			sound_sender.send(Box::from(source.convert_samples())).ok();
+					// Stop the old sound on this channel of the entity, if any
+					let sound_playing = sound_playing_component
+						.entry(entity)
+						.unwrap()
+						.or_insert_with(SoundPlaying::default);
+
+					if let Some(old) = sound_playing.channels[channel as usize].take() {
+						mixer.stop(old);
+					}
+
+					sound_playing.channels[channel as usize] =
+						mixer.play(sound, info.priority, info.singular, volumes.into());
+				}
+			}
 		}
 	}
 }
@@ -151,16 +404,73 @@ fn calculate_volumes(client_transform: &Transform, entity_transform: &Transform)
 	let angle = client_transform.rotation[2]
 		- Angle::from_radians(f64::atan2(to_entity_vec[1] as f64, to_entity_vec[0] as f64));
 	let pan = MAX_PAN * angle.sin() as f32;
-	let volumes = Vector2::new(
+
+	// Final result
+	pan_volumes(pan) * distance_factor
+}
+
+/// Left and right volumes for a pan from -1 (left) to 1 (right).
+fn pan_volumes(pan: f32) -> Vector2<f32> {
+	Vector2::new(
 		1.0 - 0.25 * (pan + 1.0).powi(2),
 		1.0 - 0.25 * (pan - 1.0).powi(2),
-	);
+	)
+}
 
-	// Final result
-	volumes * distance_factor
+/// How much of a sound gets through the map from its position to the client.
+/// Sound travels both ways, so propagation from the client's sector works for every sound.
+fn occlusion_factor(map: &Map, propagation: &[Option<usize>], position: Vector3<f32>) -> f32 {
+	// Sound through one sound-blocking linedef is muffled
+	const BLOCKED_FACTOR: f32 = 0.4;
+
+	let sector_index = map
+		.find_subsector(Vector2::new(position[0], position[1]))
+		.sector_index;
+
+	match map.sound_blocks(propagation, sector_index) {
+		Some(0) => 1.0,
+		Some(_) => BLOCKED_FACTOR,
+		None => 0.0,
+	}
+}
+
+/// Where a sound is heard from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SoundOrigin {
+	/// Heard by every player, without distance or panning.
+	Global,
+	/// Heard only by the player of the entity, without distance or panning.
+	Player(Entity),
+	/// Heard from the position of the entity, on one of its channels.
+	Entity(Entity, SoundChannel),
+}
+
+/// Each entity can play one sound on each channel at a time.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SoundChannel {
+	Body = 0,
+	Voice = 1,
+	Weapon = 2,
+	Item = 3,
+}
+
+const SOUND_CHANNEL_COUNT: usize = 4;
+
+impl TryFrom<u8> for SoundChannel {
+	type Error = anyhow::Error;
+
+	fn try_from(value: u8) -> anyhow::Result<SoundChannel> {
+		Ok(match value {
+			0 => SoundChannel::Body,
+			1 => SoundChannel::Voice,
+			2 => SoundChannel::Weapon,
+			3 => SoundChannel::Item,
+			_ => bail!("Invalid sound channel {}", value),
+		})
+	}
 }
 
-#[derive(Component)]
+#[derive(Component, Default)]
 pub struct SoundPlaying {
-	pub controller: SoundController,
+	pub channels: [Option<ChannelHandle>; SOUND_CHANNEL_COUNT],
 }
@@ -11,6 +11,7 @@ use std::{
 	vec::Vec,
 };
 
+#[derive(Clone)]
 struct Lump {
 	path: PathBuf,
 	name: String,
@@ -18,7 +19,7 @@ struct Lump {
 	size: usize,
 }
 
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct WadLoader {
 	lumps: Vec<Lump>,
 	lump_names: HashSet<String>,
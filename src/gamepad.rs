@@ -0,0 +1,233 @@
+use crate::input::{GamepadAxis, GamepadButton};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GamepadEvent {
+	ButtonPressed(GamepadButton),
+	ButtonReleased(GamepadButton),
+	/// The value is between -1 and 1, positive is right or up.
+	AxisChanged(GamepadAxis, f64),
+	Disconnected,
+}
+
+/// Where gamepad events come from. All connected gamepads are merged into one.
+pub trait GamepadBackend {
+	fn next_event(&mut self) -> Option<GamepadEvent>;
+}
+
+/// Used when gamepads aren't supported, never gives any events.
+pub struct NullBackend;
+
+impl GamepadBackend for NullBackend {
+	fn next_event(&mut self) -> Option<GamepadEvent> {
+		None
+	}
+}
+
+#[cfg(feature = "gamepad")]
+pub struct GilrsBackend {
+	gilrs: gilrs::Gilrs,
+}
+
+#[cfg(feature = "gamepad")]
+impl GamepadBackend for GilrsBackend {
+	fn next_event(&mut self) -> Option<GamepadEvent> {
+		while let Some(gilrs::Event { id, event, .. }) = self.gilrs.next_event() {
+			let event = match event {
+				gilrs::EventType::ButtonPressed(button, _) => {
+					convert_button(button).map(GamepadEvent::ButtonPressed)
+				}
+				gilrs::EventType::ButtonReleased(button, _) => {
+					convert_button(button).map(GamepadEvent::ButtonReleased)
+				}
+				gilrs::EventType::AxisChanged(axis, value, _) => {
+					convert_axis(axis).map(|axis| GamepadEvent::AxisChanged(axis, value as f64))
+				}
+				gilrs::EventType::Connected => {
+					log::info!("Gamepad connected: {}", self.gilrs.gamepad(id).name());
+					None
+				}
+				gilrs::EventType::Disconnected => {
+					log::info!("Gamepad disconnected: {}", self.gilrs.gamepad(id).name());
+					Some(GamepadEvent::Disconnected)
+				}
+				_ => None,
+			};
+
+			if event.is_some() {
+				return event;
+			}
+		}
+
+		None
+	}
+}
+
+/// Uses gilrs if it works on this system, or gives no gamepad input otherwise.
+#[cfg(feature = "gamepad")]
+pub fn init() -> Box<dyn GamepadBackend> {
+	match gilrs::Gilrs::new() {
+		Ok(gilrs) => {
+			for (_, gamepad) in gilrs.gamepads() {
+				log::info!("Gamepad found: {}", gamepad.name());
+			}
+
+			Box::new(GilrsBackend { gilrs })
+		}
+		Err(err) => {
+			log::warn!("Couldn't initialise gamepad input: {}", err);
+			Box::new(NullBackend)
+		}
+	}
+}
+
+#[cfg(not(feature = "gamepad"))]
+pub fn init() -> Box<dyn GamepadBackend> {
+	log::info!("Gamepad support is not included in this build");
+	Box::new(NullBackend)
+}
+
+#[cfg(feature = "gamepad")]
+fn convert_button(button: gilrs::Button) -> Option<GamepadButton> {
+	Some(match button {
+		gilrs::Button::South => GamepadButton::South,
+		gilrs::Button::East => GamepadButton::East,
+		gilrs::Button::North => GamepadButton::North,
+		gilrs::Button::West => GamepadButton::West,
+		gilrs::Button::LeftTrigger => GamepadButton::LeftTrigger,
+		gilrs::Button::LeftTrigger2 => GamepadButton::LeftTrigger2,
+		gilrs::Button::RightTrigger => GamepadButton::RightTrigger,
+		gilrs::Button::RightTrigger2 => GamepadButton::RightTrigger2,
+		gilrs::Button::Select => GamepadButton::Select,
+		gilrs::Button::Start => GamepadButton::Start,
+		gilrs::Button::Mode => GamepadButton::Mode,
+		gilrs::Button::LeftThumb => GamepadButton::LeftThumb,
+		gilrs::Button::RightThumb => GamepadButton::RightThumb,
+		gilrs::Button::DPadUp => GamepadButton::DPadUp,
+		gilrs::Button::DPadDown => GamepadButton::DPadDown,
+		gilrs::Button::DPadLeft => GamepadButton::DPadLeft,
+		gilrs::Button::DPadRight => GamepadButton::DPadRight,
+		_ => return None,
+	})
+}
+
+#[cfg(feature = "gamepad")]
+fn convert_axis(axis: gilrs::Axis) -> Option<GamepadAxis> {
+	Some(match axis {
+		gilrs::Axis::LeftStickX => GamepadAxis::LeftStickX,
+		gilrs::Axis::LeftStickY => GamepadAxis::LeftStickY,
+		gilrs::Axis::LeftZ => GamepadAxis::LeftZ,
+		gilrs::Axis::RightStickX => GamepadAxis::RightStickX,
+		gilrs::Axis::RightStickY => GamepadAxis::RightStickY,
+		gilrs::Axis::RightZ => GamepadAxis::RightZ,
+		gilrs::Axis::DPadX => GamepadAxis::DPadX,
+		gilrs::Axis::DPadY => GamepadAxis::DPadY,
+		_ => return None,
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::{GamepadBackend, GamepadEvent};
+	use crate::input::{Axis, Button, GamepadAxis, GamepadButton, InputState};
+	use std::collections::VecDeque;
+
+	/// Gives a fixed list of events.
+	struct ScriptedBackend(VecDeque<GamepadEvent>);
+
+	impl GamepadBackend for ScriptedBackend {
+		fn next_event(&mut self) -> Option<GamepadEvent> {
+			self.0.pop_front()
+		}
+	}
+
+	fn feed(input_state: &mut InputState, events: &[GamepadEvent]) {
+		let mut backend: Box<dyn GamepadBackend> =
+			Box::new(ScriptedBackend(events.iter().copied().collect()));
+
+		while let Some(event) = backend.next_event() {
+			input_state.process_gamepad_event(event);
+		}
+	}
+
+	fn stick(axis: GamepadAxis, curve: f64, invert: bool) -> Axis {
+		Axis::Gamepad {
+			axis,
+			scale: 2.0,
+			deadzone: 0.2,
+			curve,
+			invert,
+		}
+	}
+
+	fn assert_near(value: f64, expected: f64) {
+		assert!(
+			(value - expected).abs() < 1e-9,
+			"{} is not {}",
+			value,
+			expected
+		);
+	}
+
+	#[test]
+	fn axis_processing() {
+		let mut input_state = InputState::new();
+		let linear = stick(GamepadAxis::LeftStickX, 1.0, false);
+		let squared = stick(GamepadAxis::LeftStickX, 2.0, false);
+		let inverted = stick(GamepadAxis::LeftStickX, 1.0, true);
+
+		// Inside the deadzone
+		feed(
+			&mut input_state,
+			&[GamepadEvent::AxisChanged(GamepadAxis::LeftStickX, -0.15)],
+		);
+		assert_near(linear.value(&input_state), 0.0);
+
+		// The range outside the deadzone is stretched to cover 0 to 1, then curved and scaled
+		feed(
+			&mut input_state,
+			&[
+				GamepadEvent::AxisChanged(GamepadAxis::LeftStickX, 0.2),
+				GamepadEvent::AxisChanged(GamepadAxis::LeftStickX, 0.6),
+			],
+		);
+		assert_near(linear.value(&input_state), 1.0);
+		assert_near(squared.value(&input_state), 0.5);
+		assert_near(inverted.value(&input_state), -1.0);
+
+		// The curve keeps the sign
+		feed(
+			&mut input_state,
+			&[GamepadEvent::AxisChanged(GamepadAxis::LeftStickX, -1.0)],
+		);
+		assert_near(linear.value(&input_state), -2.0);
+		assert_near(squared.value(&input_state), -2.0);
+		assert_near(inverted.value(&input_state), 2.0);
+
+		// Other axes are unaffected
+		assert_near(
+			stick(GamepadAxis::RightStickY, 1.0, false).value(&input_state),
+			0.0,
+		);
+	}
+
+	#[test]
+	fn disconnect_resets() {
+		let mut input_state = InputState::new();
+		let button = Button::Gamepad(GamepadButton::South);
+		let axis = stick(GamepadAxis::LeftZ, 1.0, false);
+
+		feed(
+			&mut input_state,
+			&[
+				GamepadEvent::ButtonPressed(GamepadButton::South),
+				GamepadEvent::AxisChanged(GamepadAxis::LeftZ, 1.0),
+			],
+		);
+		assert!(input_state.button_is_down(button));
+		assert_near(axis.value(&input_state), 2.0);
+
+		feed(&mut input_state, &[GamepadEvent::Disconnected]);
+		assert!(!input_state.button_is_down(button));
+		assert_near(axis.value(&input_state), 0.0);
+	}
+}
@@ -1,18 +1,147 @@
+use crate::gamepad::GamepadEvent;
+use anyhow::bail;
 use derivative::Derivative;
-use serde::{Deserialize, Serialize};
+use lazy_static::lazy_static;
+use serde::{
+	de::{value::Error as ValueError, IntoDeserializer},
+	Deserialize, Serialize,
+};
 use std::{
 	collections::{hash_map::Entry, HashMap},
-	fmt::Debug,
+	fmt::{self, Debug},
 	hash::Hash,
+	str::FromStr,
 };
 use winit::event::{
-	DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent,
+	DeviceEvent, ElementState, Event, KeyboardInput, MouseButton, MouseScrollDelta, VirtualKeyCode,
+	WindowEvent,
 };
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Button {
 	Key(VirtualKeyCode),
 	Mouse(MouseButton),
+	Wheel(WheelDirection),
+	Gamepad(GamepadButton),
+}
+
+/// The wheel counts as a button that is down during the tic it was turned in.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WheelDirection {
+	Up,
+	Down,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum GamepadButton {
+	South,
+	East,
+	North,
+	West,
+	LeftTrigger,
+	LeftTrigger2,
+	RightTrigger,
+	RightTrigger2,
+	Select,
+	Start,
+	Mode,
+	LeftThumb,
+	RightThumb,
+	DPadUp,
+	DPadDown,
+	DPadLeft,
+	DPadRight,
+}
+
+lazy_static! {
+	// Lowercase names, used to parse them case-insensitively
+	static ref KEY_NAMES: Vec<(String, VirtualKeyCode)> = variant_names();
+	static ref GAMEPAD_BUTTON_NAMES: Vec<(String, GamepadButton)> = variant_names();
+	static ref GAMEPAD_AXIS_NAMES: Vec<(String, GamepadAxis)> = variant_names();
+}
+
+/// All variants of a fieldless enum with their lowercase names,
+/// found by deserializing the variant indices.
+fn variant_names<T: for<'de> Deserialize<'de> + Debug>() -> Vec<(String, T)> {
+	(0u32..)
+		.map_while(|index| {
+			let deserializer = IntoDeserializer::<ValueError>::into_deserializer(index);
+			T::deserialize(deserializer).ok()
+		})
+		.map(|variant| (format!("{:?}", variant).to_ascii_lowercase(), variant))
+		.collect()
+}
+
+fn find_name<T: Copy>(names: &[(String, T)], name: &str) -> Option<T> {
+	names
+		.iter()
+		.find(|(variant_name, _)| variant_name == name)
+		.map(|(_, variant)| *variant)
+}
+
+/// Buttons are named after their `VirtualKeyCode`, in any case. Digit keys can also be written
+/// as just the digit. Mouse buttons are `mouse1` (left), `mouse2` (right), `mouse3` (middle)
+/// and so on, and the wheel is `mwheelup` and `mwheeldown`. Gamepad buttons are `pad_` followed
+/// by the `GamepadButton` name.
+impl FromStr for Button {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<Button> {
+		let lower = name.to_ascii_lowercase();
+
+		match lower.as_str() {
+			"mwheelup" => return Ok(Button::Wheel(WheelDirection::Up)),
+			"mwheeldown" => return Ok(Button::Wheel(WheelDirection::Down)),
+			_ => {}
+		}
+
+		if let Some(button) = lower.strip_prefix("pad_") {
+			return match find_name(&GAMEPAD_BUTTON_NAMES, button) {
+				Some(button) => Ok(Button::Gamepad(button)),
+				None => bail!("Unknown button: {}", name),
+			};
+		}
+
+		if let Some(number) = lower.strip_prefix("mouse") {
+			if let Ok(number) = number.parse::<u8>() {
+				return Ok(Button::Mouse(match number {
+					0 => bail!("Unknown button: {}", name),
+					1 => MouseButton::Left,
+					2 => MouseButton::Right,
+					3 => MouseButton::Middle,
+					_ => MouseButton::Other(number),
+				}));
+			}
+		}
+
+		let lower = if lower.len() == 1 && lower.as_bytes()[0].is_ascii_digit() {
+			format!("key{}", lower)
+		} else {
+			lower
+		};
+
+		match find_name(&KEY_NAMES, &lower) {
+			Some(key_code) => Ok(Button::Key(key_code)),
+			None => bail!("Unknown button: {}", name),
+		}
+	}
+}
+
+impl fmt::Display for Button {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Button::Key(key_code) => write!(f, "{:?}", key_code),
+			Button::Mouse(MouseButton::Left) => write!(f, "mouse1"),
+			Button::Mouse(MouseButton::Right) => write!(f, "mouse2"),
+			Button::Mouse(MouseButton::Middle) => write!(f, "mouse3"),
+			Button::Mouse(MouseButton::Other(number)) => write!(f, "mouse{}", number),
+			Button::Wheel(WheelDirection::Up) => write!(f, "mwheelup"),
+			Button::Wheel(WheelDirection::Down) => write!(f, "mwheeldown"),
+			Button::Gamepad(button) => {
+				write!(f, "pad_{}", format!("{:?}", button).to_ascii_lowercase())
+			}
+		}
+	}
 }
 
 impl From<VirtualKeyCode> for Button {
@@ -27,22 +156,144 @@ impl From<MouseButton> for Button {
 	}
 }
 
+pub const DEFAULT_DEADZONE: f64 = 0.15;
+
+// Touchpads scroll in pixels, convert it to something like wheel clicks
+const PIXELS_PER_LINE: f64 = 20.0;
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Axis {
-	Emulated { pos: Button, neg: Button },
-	Mouse { axis: MouseAxis, scale: f64 },
+	Emulated {
+		pos: Vec<Button>,
+		neg: Vec<Button>,
+	},
+	Mouse {
+		axis: MouseAxis,
+		scale: f64,
+	},
+	/// Values inside the deadzone are zero, the rest is rescaled to 0..1 and raised to the
+	/// power of `curve`, so that higher values give more precision near the centre.
+	Gamepad {
+		axis: GamepadAxis,
+		scale: f64,
+		deadzone: f64,
+		curve: f64,
+		invert: bool,
+	},
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum MouseAxis {
 	X,
 	Y,
+	Wheel,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum GamepadAxis {
+	LeftStickX,
+	LeftStickY,
+	LeftZ,
+	RightStickX,
+	RightStickY,
+	RightZ,
+	DPadX,
+	DPadY,
+}
+
+/// Anything that gives an analog value: `mousex`, `mousey`, `mousewheel`, or `pad_` followed by the
+/// `GamepadAxis` name.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AnalogAxis {
+	Mouse(MouseAxis),
+	Gamepad(GamepadAxis),
+}
+
+impl FromStr for AnalogAxis {
+	type Err = anyhow::Error;
+
+	fn from_str(name: &str) -> anyhow::Result<AnalogAxis> {
+		let lower = name.to_ascii_lowercase();
+
+		Ok(match lower.as_str() {
+			"mousex" => AnalogAxis::Mouse(MouseAxis::X),
+			"mousey" => AnalogAxis::Mouse(MouseAxis::Y),
+			"mousewheel" => AnalogAxis::Mouse(MouseAxis::Wheel),
+			_ => match lower
+				.strip_prefix("pad_")
+				.and_then(|axis| find_name(&GAMEPAD_AXIS_NAMES, axis))
+			{
+				Some(axis) => AnalogAxis::Gamepad(axis),
+				None => bail!("Unknown axis: {}", name),
+			},
+		})
+	}
+}
+
+impl fmt::Display for AnalogAxis {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			AnalogAxis::Mouse(axis) => fmt::Display::fmt(axis, f),
+			AnalogAxis::Gamepad(axis) => {
+				write!(f, "pad_{}", format!("{:?}", axis).to_ascii_lowercase())
+			}
+		}
+	}
+}
+
+impl Axis {
+	/// The analog axis that gives the value, or `None` for emulated axes.
+	pub fn source(&self) -> Option<AnalogAxis> {
+		match *self {
+			Axis::Emulated { .. } => None,
+			Axis::Mouse { axis, .. } => Some(AnalogAxis::Mouse(axis)),
+			Axis::Gamepad { axis, .. } => Some(AnalogAxis::Gamepad(axis)),
+		}
+	}
+
+	pub fn value(&self, input_state: &InputState) -> f64 {
+		match *self {
+			Axis::Emulated { ref pos, ref neg } => {
+				let is_down =
+					|buttons: &[Button]| buttons.iter().any(|&b| input_state.button_is_down(b));
+				(is_down(pos) as i32 - is_down(neg) as i32) as f64
+			}
+			Axis::Mouse { axis, scale } => input_state.mouse_delta(axis) * scale,
+			Axis::Gamepad {
+				axis,
+				scale,
+				deadzone,
+				curve,
+				invert,
+			} => {
+				let value = input_state.gamepad_axis(axis);
+				let magnitude = ((value.abs() - deadzone) / (1.0 - deadzone))
+					.max(0.0)
+					.powf(curve);
+				let value = magnitude.copysign(value) * scale;
+
+				if invert {
+					-value
+				} else {
+					value
+				}
+			}
+		}
+	}
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Derivative)]
+#[derivative(Default)]
 pub struct InputState {
-	mouse_delta: [f64; 2],
+	gamepad_axes: [f64; 8],
+	mouse_acceleration: f64,
+	mouse_delta: [f64; 3],
 	mouse_delta_enabled: bool,
+	mouse_filter: bool,
+	#[derivative(Default(value = "1.0"))]
+	mouse_sensitivity: f64,
+	previous_mouse_delta: [f64; 2],
+	pressed_gamepad_buttons: Vec<GamepadButton>,
 	pressed_keys: Vec<VirtualKeyCode>,
 	pressed_mouse_buttons: Vec<MouseButton>,
 }
@@ -50,15 +301,22 @@ pub struct InputState {
 impl InputState {
 	pub fn new() -> InputState {
 		InputState {
-			mouse_delta: [0.0, 0.0],
+			gamepad_axes: [0.0; 8],
+			mouse_acceleration: 0.0,
+			mouse_delta: [0.0; 3],
 			mouse_delta_enabled: false,
+			mouse_filter: false,
+			mouse_sensitivity: 1.0,
+			previous_mouse_delta: [0.0; 2],
+			pressed_gamepad_buttons: Vec::new(),
 			pressed_keys: Vec::new(),
 			pressed_mouse_buttons: Vec::new(),
 		}
 	}
 
 	pub fn reset(&mut self) {
-		self.mouse_delta = [0.0, 0.0];
+		self.previous_mouse_delta = [self.mouse_delta[0], self.mouse_delta[1]];
+		self.mouse_delta = [0.0; 3];
 	}
 
 	pub fn button_is_down(&self, button: Button) -> bool {
@@ -68,18 +326,87 @@ impl InputState {
 				.pressed_mouse_buttons
 				.iter()
 				.any(|&mb| mb == mouse_button),
+			Button::Wheel(WheelDirection::Up) => self.mouse_delta[2] > 0.0,
+			Button::Wheel(WheelDirection::Down) => self.mouse_delta[2] < 0.0,
+			Button::Gamepad(gamepad_button) => {
+				self.pressed_gamepad_buttons.contains(&gamepad_button)
+			}
 		}
 	}
 
+	pub fn gamepad_axis(&self, axis: GamepadAxis) -> f64 {
+		self.gamepad_axes[axis as usize]
+	}
+
+	/// Movement since the last tic, with filtering, acceleration and sensitivity applied.
+	/// The wheel is in lines, and is left alone.
 	pub fn mouse_delta(&self, axis: MouseAxis) -> f64 {
-		self.mouse_delta[axis as usize]
+		if axis == MouseAxis::Wheel {
+			return self.mouse_delta[2];
+		}
+
+		// Filtering averages with the previous tic to smooth out jitter
+		let mut delta = [self.mouse_delta[0], self.mouse_delta[1]];
+
+		if self.mouse_filter {
+			delta[0] = (delta[0] + self.previous_mouse_delta[0]) * 0.5;
+			delta[1] = (delta[1] + self.previous_mouse_delta[1]) * 0.5;
+		}
+
+		let speed = (delta[0] * delta[0] + delta[1] * delta[1]).sqrt();
+		delta[axis as usize] * self.mouse_sensitivity * (1.0 + self.mouse_acceleration * speed)
+	}
+
+	pub fn set_mouse_acceleration(&mut self, acceleration: f64) {
+		self.mouse_acceleration = acceleration;
+	}
+
+	pub fn set_mouse_filter(&mut self, filter: bool) {
+		self.mouse_filter = filter;
+	}
+
+	pub fn set_mouse_sensitivity(&mut self, sensitivity: f64) {
+		self.mouse_sensitivity = sensitivity;
 	}
 
 	pub fn set_mouse_delta_enabled(&mut self, enabled: bool) {
 		self.mouse_delta_enabled = enabled;
 
 		if !enabled {
-			self.mouse_delta = [0.0, 0.0];
+			self.mouse_delta = [0.0; 3];
+			self.previous_mouse_delta = [0.0; 2];
+		}
+	}
+
+	/// Forgets the keys that are held, so that they don't stay pressed while something
+	/// else takes the keyboard.
+	pub fn release_keys(&mut self) {
+		self.pressed_keys.clear();
+	}
+
+	pub fn process_gamepad_event(&mut self, event: GamepadEvent) {
+		match event {
+			GamepadEvent::ButtonPressed(button) => {
+				if self.pressed_gamepad_buttons.iter().all(|&b| b != button) {
+					self.pressed_gamepad_buttons.push(button);
+				}
+			}
+			GamepadEvent::ButtonReleased(button) => {
+				if let Some(i) = self
+					.pressed_gamepad_buttons
+					.iter()
+					.position(|&b| b == button)
+				{
+					self.pressed_gamepad_buttons.swap_remove(i);
+				}
+			}
+			GamepadEvent::AxisChanged(axis, value) => {
+				self.gamepad_axes[axis as usize] = value;
+			}
+			GamepadEvent::Disconnected => {
+				self.pressed_gamepad_buttons.clear();
+				self.gamepad_axes = [0.0; 8];
+			}
 		}
 	}
 
@@ -130,6 +457,12 @@ impl InputState {
 						self.pressed_mouse_buttons.swap_remove(i);
 					}
 				}
+				WindowEvent::MouseWheel { delta, .. } if self.mouse_delta_enabled => {
+					self.mouse_delta[2] += match delta {
+						MouseScrollDelta::LineDelta(_, y) => y as f64,
+						MouseScrollDelta::PixelDelta(position) => position.y / PIXELS_PER_LINE,
+					};
+				}
 				WindowEvent::Focused(false) => {
 					self.pressed_keys.clear();
 					self.pressed_mouse_buttons.clear();
@@ -150,11 +483,123 @@ impl InputState {
 	}
 }
 
+impl fmt::Display for MouseAxis {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			MouseAxis::X => write!(f, "mousex"),
+			MouseAxis::Y => write!(f, "mousey"),
+			MouseAxis::Wheel => write!(f, "mousewheel"),
+		}
+	}
+}
+
+/// Waits for the next button press, to bind it to something.
+#[derive(Debug, Derivative)]
+#[derivative(Default(bound = ""))]
+pub struct ButtonCapture<T> {
+	target: Option<T>,
+	captured: Option<(Button, T)>,
+}
+
+impl<T> ButtonCapture<T> {
+	pub fn start(&mut self, target: T) {
+		self.target = Some(target);
+	}
+
+	/// What the next button press will be bound to, while waiting for it.
+	pub fn target(&self) -> Option<&T> {
+		self.target.as_ref()
+	}
+
+	/// The button that was pressed and what to bind it to, if capturing finished.
+	pub fn take_captured(&mut self) -> Option<(Button, T)> {
+		self.captured.take()
+	}
+
+	/// Handles an event, returns whether it was used. Escape cancels.
+	pub fn process_event(&mut self, event: &Event<()>) -> bool {
+		if self.target.is_none() {
+			return false;
+		}
+
+		let button = match event {
+			Event::WindowEvent { event, .. } => match *event {
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(VirtualKeyCode::Escape),
+							..
+						},
+					..
+				} => {
+					self.target = None;
+					return true;
+				}
+				WindowEvent::KeyboardInput {
+					input:
+						KeyboardInput {
+							state: ElementState::Pressed,
+							virtual_keycode: Some(key_code),
+							..
+						},
+					..
+				} => Button::Key(key_code),
+				WindowEvent::MouseInput {
+					state: ElementState::Pressed,
+					button,
+					..
+				} => Button::Mouse(button),
+				WindowEvent::MouseWheel { delta, .. } => {
+					let y = match delta {
+						MouseScrollDelta::LineDelta(_, y) => y as f64,
+						MouseScrollDelta::PixelDelta(position) => position.y,
+					};
+
+					if y > 0.0 {
+						Button::Wheel(WheelDirection::Up)
+					} else if y < 0.0 {
+						Button::Wheel(WheelDirection::Down)
+					} else {
+						return true;
+					}
+				}
+				WindowEvent::KeyboardInput { .. } | WindowEvent::ReceivedCharacter(_) => {
+					return true
+				}
+				_ => return false,
+			},
+			_ => return false,
+		};
+
+		self.press(button)
+	}
+
+	/// Captures a button press that didn't come from a window event.
+	/// Returns whether it was used.
+	pub fn press(&mut self, button: Button) -> bool {
+		match self.target.take() {
+			Some(target) => {
+				self.captured = Some((button, target));
+				true
+			}
+			None => false,
+		}
+	}
+}
+
 #[derive(Derivative, Deserialize, Serialize)]
 #[derivative(Debug(bound = ""), Default(bound = ""), Clone(bound = ""))]
+#[serde(bound(
+	serialize = "A: Serialize, X: Serialize",
+	deserialize = "A: Deserialize<'de>, X: Deserialize<'de>"
+))]
 pub struct Bindings<A: Clone + Debug + Hash + Eq, X: Clone + Debug + Hash + Eq> {
+	// Stored as lists, because JSON only allows strings as map keys
+	#[serde(with = "as_pairs")]
 	actions: HashMap<A, Vec<Button>>,
-	axes: HashMap<X, Axis>,
+	#[serde(with = "as_pairs")]
+	axes: HashMap<X, Vec<Axis>>,
 }
 
 impl<A: Clone + Debug + Hash + Eq, X: Clone + Debug + Hash + Eq> Bindings<A, X> {
@@ -168,7 +613,9 @@ impl<A: Clone + Debug + Hash + Eq, X: Clone + Debug + Hash + Eq> Bindings<A, X>
 	pub fn bind_action(&mut self, id: A, button: Button) {
 		match self.actions.entry(id) {
 			Entry::Occupied(mut entry) => {
-				entry.get_mut().push(button);
+				if !entry.get().contains(&button) {
+					entry.get_mut().push(button);
+				}
 			}
 			Entry::Vacant(entry) => {
 				entry.insert(vec![button]);
@@ -176,8 +623,89 @@ impl<A: Clone + Debug + Hash + Eq, X: Clone + Debug + Hash + Eq> Bindings<A, X>
 		}
 	}
 
+	/// Adds an analog axis binding, after unbinding its source from anything else.
+	/// The values of all bindings of an axis are added together.
 	pub fn bind_axis(&mut self, id: X, axis: Axis) {
-		self.axes.insert(id, axis);
+		if let Some(source) = axis.source() {
+			self.unbind_axis(source);
+		}
+
+		self.axes.entry(id).or_default().push(axis);
+	}
+
+	/// Adds a button to one direction of an emulated axis.
+	pub fn bind_axis_button(&mut self, id: X, button: Button, positive: bool) {
+		let axes = self.axes.entry(id).or_default();
+		let is_emulated = |axis: &Axis| matches!(axis, Axis::Emulated { .. });
+
+		if !axes.iter().any(is_emulated) {
+			axes.push(Axis::Emulated {
+				pos: Vec::new(),
+				neg: Vec::new(),
+			});
+		}
+
+		if let Some(Axis::Emulated { pos, neg }) = axes.iter_mut().find(|axis| is_emulated(axis)) {
+			let buttons = if positive { pos } else { neg };
+
+			if !buttons.contains(&button) {
+				buttons.push(button);
+			}
+		}
+	}
+
+	/// Removes a button from everything it's bound to, returns whether it was bound.
+	pub fn unbind(&mut self, button: Button) -> bool {
+		let mut found = false;
+		let mut remove = |buttons: &mut Vec<Button>| {
+			let len = buttons.len();
+			buttons.retain(|&b| b != button);
+			found |= buttons.len() != len;
+			!buttons.is_empty()
+		};
+
+		self.actions.retain(|_, buttons| remove(buttons));
+
+		for axes in self.axes.values_mut() {
+			axes.retain_mut(|axis| match axis {
+				Axis::Emulated { pos, neg } => remove(pos) | remove(neg),
+				Axis::Mouse { .. } | Axis::Gamepad { .. } => true,
+			});
+		}
+
+		self.axes.retain(|_, axes| !axes.is_empty());
+		found
+	}
+
+	/// Removes an analog axis from everything it's bound to, returns whether it was bound.
+	pub fn unbind_axis(&mut self, source: AnalogAxis) -> bool {
+		let mut found = false;
+
+		for axes in self.axes.values_mut() {
+			let len = axes.len();
+			axes.retain(|axis| axis.source() != Some(source));
+			found |= axes.len() != len;
+		}
+
+		self.axes.retain(|_, axes| !axes.is_empty());
+		found
+	}
+
+	pub fn clear(&mut self) {
+		self.actions.clear();
+		self.axes.clear();
+	}
+
+	pub fn actions(&self) -> impl Iterator<Item = (&A, &[Button])> {
+		self.actions
+			.iter()
+			.map(|(id, buttons)| (id, buttons.as_slice()))
+	}
+
+	pub fn axes(&self) -> impl Iterator<Item = (&X, &Axis)> {
+		self.axes
+			.iter()
+			.flat_map(|(id, axes)| axes.iter().map(move |axis| (id, axis)))
 	}
 
 	pub fn action_is_down(&self, id: &A, input_state: &InputState) -> bool {
@@ -194,13 +722,58 @@ impl<A: Clone + Debug + Hash + Eq, X: Clone + Debug + Hash + Eq> Bindings<A, X>
 	pub fn axis_value(&self, id: &X, input_state: &InputState) -> f64 {
 		self.axes
 			.get(id)
-			.map(|a| match *a {
-				Axis::Emulated { pos, neg } => {
-					(input_state.button_is_down(pos) as i32
-						- input_state.button_is_down(neg) as i32) as f64
-				}
-				Axis::Mouse { axis, scale } => input_state.mouse_delta(axis) * scale,
+			.map(|axes| axes.iter().map(|axis| axis.value(input_state)).sum())
+			.unwrap_or(0.0)
+	}
+
+	/// The value of only the analog axes bound to the axis.
+	pub fn analog_axis_value(&self, id: &X, input_state: &InputState) -> f64 {
+		self.axes
+			.get(id)
+			.map(|axes| {
+				axes.iter()
+					.filter(|axis| axis.source().is_some())
+					.map(|axis| axis.value(input_state))
+					.sum()
 			})
 			.unwrap_or(0.0)
 	}
+
+	/// The value of only the buttons bound to the axis.
+	pub fn button_axis_value(&self, id: &X, input_state: &InputState) -> f64 {
+		self.axes
+			.get(id)
+			.map(|axes| {
+				axes.iter()
+					.filter(|axis| axis.source().is_none())
+					.map(|axis| axis.value(input_state))
+					.sum()
+			})
+			.unwrap_or(0.0)
+	}
+}
+
+mod as_pairs {
+	use serde::{Deserialize, Deserializer, Serialize, Serializer};
+	use std::{collections::HashMap, hash::Hash};
+
+	pub fn serialize<K, V, S>(map: &HashMap<K, V>, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		K: Serialize,
+		V: Serialize,
+		S: Serializer,
+	{
+		serializer.collect_seq(map.iter())
+	}
+
+	pub fn deserialize<'de, K, V, D>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+	where
+		K: Deserialize<'de> + Eq + Hash,
+		V: Deserialize<'de>,
+		D: Deserializer<'de>,
+	{
+		Ok(Vec::<(K, V)>::deserialize(deserializer)?
+			.into_iter()
+			.collect())
+	}
 }
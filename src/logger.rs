@@ -1,15 +1,18 @@
 use clap::ArgMatches;
 use colored::Colorize;
+use crossbeam_channel::{Receiver, Sender};
+use lazy_static::lazy_static;
 use log::{self, Level, LevelFilter, Log, Metadata, Record};
+use std::{cell::RefCell, sync::Mutex};
 
 pub static LOGGER: Logger = Logger;
 pub struct Logger;
 
 #[cfg(debug_assertions)]
-const LOG_LEVEL: LevelFilter = LevelFilter::Debug;
+pub const LOG_LEVEL: LevelFilter = LevelFilter::Debug;
 
 #[cfg(not(debug_assertions))]
-const LOG_LEVEL: LevelFilter = LevelFilter::Info;
+pub const LOG_LEVEL: LevelFilter = LevelFilter::Info;
 
 pub fn init(arg_matches: &ArgMatches) -> anyhow::Result<()> {
 	log::set_logger(&LOGGER)?;
@@ -22,6 +25,31 @@ pub fn init(arg_matches: &ArgMatches) -> anyhow::Result<()> {
 	Ok(())
 }
 
+thread_local! {
+	static CAPTURED: RefCell<Option<Vec<String>>> = RefCell::new(None);
+}
+
+lazy_static! {
+	static ref SUBSCRIBERS: Mutex<Vec<Sender<(Level, String)>>> = Mutex::new(Vec::new());
+}
+
+/// Returns a receiver that gets a copy of every message logged from now on, from any thread.
+/// Dropping the receiver unsubscribes it.
+pub fn subscribe() -> Receiver<(Level, String)> {
+	let (sender, receiver) = crossbeam_channel::unbounded();
+	SUBSCRIBERS.lock().unwrap().push(sender);
+	receiver
+}
+
+/// Runs a function, and returns the log messages it produced on the current thread
+/// along with its result. The messages are still logged as normal.
+pub fn capture<T>(func: impl FnOnce() -> T) -> (T, Vec<String>) {
+	let previous = CAPTURED.with(|captured| captured.replace(Some(Vec::new())));
+	let result = func();
+	let messages = CAPTURED.with(|captured| captured.replace(previous));
+	(result, messages.unwrap_or_default())
+}
+
 impl Log for Logger {
 	fn enabled(&self, metadata: &Metadata<'_>) -> bool {
 		metadata.level() <= log::STATIC_MAX_LEVEL
@@ -29,6 +57,18 @@ impl Log for Logger {
 
 	fn log(&self, record: &Record<'_>) {
 		if self.enabled(record.metadata()) {
+			CAPTURED.with(|captured| {
+				if let Some(messages) = captured.borrow_mut().as_mut() {
+					messages.push(format!("{}: {}", record.level(), record.args()));
+				}
+			});
+
+			SUBSCRIBERS.lock().unwrap().retain(|sender| {
+				sender
+					.send((record.level(), record.args().to_string()))
+					.is_ok()
+			});
+
 			match record.level() {
 				Level::Error => {
 					eprintln!("{}: {}", "ERROR".bright_red(), record.args());
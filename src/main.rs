@@ -2,31 +2,54 @@ mod assets;
 mod audio;
 mod commands;
 mod component;
+mod config;
 mod configvars;
+mod console;
 mod doom;
+mod gamepad;
 mod geometry;
 mod input;
 mod logger;
+mod net;
+mod opl;
+mod protocol;
 mod quadtree;
+// Only built as part of the game to test it against the protocol
+#[cfg(test)]
+#[allow(dead_code)]
+#[path = "bin/ferret-rcon.rs"]
+mod rcon_client;
 mod renderer;
 
 use crate::{
 	assets::{AssetHandle, AssetStorage, DataSource},
-	audio::Sound,
+	audio::{AudioBackend, AudioOutput, Mixer, Sound},
+	commands::{Command, CommandList, CommandTarget},
 	component::EntityTemplate,
-	input::{Axis, Bindings, Button, InputState, MouseAxis},
+	config::Config,
+	configvars::{ConfigVariable, ConfigVariables},
+	console::Console,
+	gamepad::GamepadEvent,
+	input::{
+		AnalogAxis, Axis, Bindings, Button, ButtonCapture, GamepadAxis, GamepadButton, InputState,
+		MouseAxis, WheelDirection,
+	},
+	net::{Addr, Socket},
+	protocol::ServerMessage,
 	quadtree::Quadtree,
 	renderer::{AsBytes, RenderContext},
 };
-use anyhow::{bail, Context};
+use anyhow::{anyhow, bail, Context};
 use clap::{App, Arg, ArgMatches};
+use crossbeam_channel::Receiver;
 use nalgebra::{Matrix4, Vector3};
 use rand::SeedableRng;
 use rand_pcg::Pcg64Mcg;
-use shrev::EventChannel;
-use specs::{DispatcherBuilder, Entity, ReadExpect, RunNow, World, WorldExt, WriteExpect};
+use shrev::{EventChannel, ReaderId};
+use specs::{Dispatcher, DispatcherBuilder, ReadExpect, RunNow, World, WorldExt, WriteExpect};
 use std::{
-	path::PathBuf,
+	net::{Ipv4Addr, Ipv6Addr, ToSocketAddrs},
+	path::{Path, PathBuf},
 	time::{Duration, Instant},
 };
 use vulkano::{
@@ -69,98 +92,226 @@ fn main() -> anyhow::Result<()> {
 				.value_name("LEVEL")
 				.possible_values(&["ERROR", "WARN", "INFO", "DEBUG", "TRACE"]),
 		)
+		.arg(
+			Arg::with_name("audio-backend")
+				.help("Where to send sound and music")
+				.long("audio-backend")
+				.value_name("BACKEND")
+				.possible_values(&["device", "null", "wav"]),
+		)
+		.arg(
+			Arg::with_name("audio-file")
+				.help("File for the wav audio backend to write to")
+				.long("audio-file")
+				.value_name("FILE"),
+		)
+		.arg(
+			Arg::with_name("dedicated")
+				.help("Run a dedicated server without a local client")
+				.long("dedicated")
+				.conflicts_with("connect"),
+		)
+		.arg(
+			Arg::with_name("port")
+				.help("UDP port for the dedicated server to listen on")
+				.long("port")
+				.value_name("PORT")
+				.requires("dedicated"),
+		)
+		.arg(
+			Arg::with_name("rcon-password")
+				.help("Password to allow remote console access to the server")
+				.long("rcon-password")
+				.value_name("PASSWORD")
+				.conflicts_with("connect"),
+		)
+		.arg(
+			Arg::with_name("deathmatch")
+				.help("Play deathmatch instead of cooperative")
+				.long("deathmatch")
+				.conflicts_with("connect"),
+		)
+		.arg(
+			Arg::with_name("altdeath")
+				.help("Play altdeath, which is plain deathmatch until item pickups are implemented")
+				.long("altdeath")
+				.conflicts_with_all(&["connect", "deathmatch"]),
+		)
+		.arg(
+			Arg::with_name("fraglimit")
+				.help("End the map when a player reaches this many frags")
+				.long("fraglimit")
+				.value_name("FRAGS")
+				.conflicts_with("connect"),
+		)
+		.arg(
+			Arg::with_name("timelimit")
+				.help("End the map after this many minutes")
+				.long("timelimit")
+				.value_name("MINUTES")
+				.conflicts_with("connect"),
+		)
+		.arg(
+			Arg::with_name("connect")
+				.help("Connect to a server instead of starting a local game")
+				.short("c")
+				.long("connect")
+				.value_name("ADDRESS"),
+		)
 		.get_matches();
 
 	logger::init(&arg_matches)?;
+	let log_receiver = logger::subscribe();
+
+	// Don't overwrite a config file that couldn't be loaded
+	let (mut config, save_config) = match Config::load() {
+		Ok(config) => (config, true),
+		Err(err) => {
+			log::warn!("{:#}", err);
+			(Config::default(), false)
+		}
+	};
 
 	let mut loader = doom::wad::WadLoader::new();
-	load_wads(&mut loader, &arg_matches)?;
+	let iwad = load_wads(&mut loader, &arg_matches, config.last_iwad.as_ref())?;
+	config.last_iwad = Some(iwad.canonicalize().unwrap_or(iwad));
 
 	let (command_sender, command_receiver) = commands::init()?;
-	let mut event_loop = EventLoop::new();
-	let (render_context, _debug_callback) =
-		RenderContext::new(&event_loop).context("Could not create rendering context")?;
-	let sound_sender = audio::init()?;
-	let bindings = get_bindings();
+	let mut commands = CommandList::new();
+	register_commands(&mut commands);
 
-	// Select map
-	let map =
-		if let Some(map) = arg_matches.value_of("map") {
-			map
-		} else {
-			let wad = loader.wads().next().unwrap().file_name().unwrap();
-
-			if wad == "doom.wad" || wad == "doom1.wad" || wad == "doomu.wad" {
-				"E1M1"
-			} else if wad == "doom2.wad" || wad == "tnt.wad" || wad == "plutonia.wad" {
-				"MAP01"
-			} else {
-				bail!("No default map is known for this IWAD. Try specifying one with the \"-m\" option.")
+	match config::autoexec_path() {
+		Ok(Some(path)) => {
+			if let Err(err) = commands.push_file(&path) {
+				log::error!("{:#}", err);
 			}
+		}
+		Ok(None) => {}
+		Err(err) => log::warn!("{:#}", err),
+	}
+
+	if arg_matches.is_present("dedicated") {
+		drop(log_receiver);
+
+		let port = match arg_matches.value_of("port") {
+			Some(port) => port
+				.parse()
+				.context(format!("Invalid port number: {}", port))?,
+			None => net::DEFAULT_PORT,
 		};
-	command_sender.send(format!("map {}", map)).ok();
+		let socket = Socket::new(Ipv4Addr::UNSPECIFIED, Ipv6Addr::UNSPECIFIED, port)
+			.context("Could not create server socket")?;
+		log::info!("Server socket is {}", socket.mode());
+
+		command_sender
+			.send(format!("map {}", default_map(&loader, &arg_matches)?))
+			.ok();
+		let (server_world, server_dispatcher) = create_server(
+			loader,
+			socket,
+			arg_matches.value_of("rcon-password"),
+			game_rules(&arg_matches)?,
+		);
+
+		return run_dedicated(commands, command_receiver, server_world, server_dispatcher);
+	}
+
+	// Set up the connection to the server, starting a local one if needed
+	let (socket, addr, server) = if let Some(address) = arg_matches.value_of("connect") {
+		let socket = Socket::new(Ipv4Addr::UNSPECIFIED, Ipv6Addr::UNSPECIFIED, 0)
+			.context("Could not create client socket")?;
+		let addr = match address.to_socket_addrs() {
+			Ok(mut addrs) => addrs.find(socket.filter_supported()),
+			Err(_) => (address, net::DEFAULT_PORT)
+				.to_socket_addrs()
+				.context(format!("Could not resolve address: {}", address))?
+				.find(socket.filter_supported()),
+		}
+		.ok_or(anyhow!("No supported address found for {}", address))?;
+
+		(socket, Addr::from(addr), None)
+	} else {
+		command_sender
+			.send(format!("map {}", default_map(&loader, &arg_matches)?))
+			.ok();
+		let (client_socket, server_socket) = Socket::new_local_pair();
+
+		(
+			client_socket,
+			Addr::Local,
+			Some(create_server(
+				loader.clone(),
+				server_socket,
+				arg_matches.value_of("rcon-password"),
+				game_rules(&arg_matches)?,
+			)),
+		)
+	};
+
+	let mut event_loop = EventLoop::new();
+	let (render_context, _debug_callback) =
+		RenderContext::new(&event_loop).context("Could not create rendering context")?;
+	let mixer = Mixer::new();
+	let mut gamepad_backend = gamepad::init();
+	let bindings = config.bindings.clone().unwrap_or_else(default_bindings);
 
 	// Set up world
 	let mut world = World::new();
+	register_components(&mut world);
+	insert_asset_storages(&mut world);
 
-	// Register components
-	world.register::<doom::client::UseAction>();
-	world.register::<doom::components::SpawnOnCeiling>();
-	world.register::<doom::components::SpawnPoint>();
-	world.register::<doom::components::Transform>();
-	world.register::<doom::components::Velocity>();
-	world.register::<doom::door::DoorActive>();
-	world.register::<doom::door::SwitchActive>();
-	world.register::<doom::light::LightFlash>();
-	world.register::<doom::light::LightGlow>();
-	world.register::<doom::map::LinedefRef>();
-	world.register::<doom::map::MapDynamic>();
-	world.register::<doom::map::SectorRef>();
-	world.register::<doom::physics::BoxCollider>();
-	world.register::<doom::render::sprite::SpriteRender>();
-	world.register::<doom::sound::SoundPlaying>();
-	world.register::<doom::update::TextureScroll>();
+	// Insert other resources
+	let mut config_variables = ConfigVariables::default();
+	register_config_variables(&mut config_variables);
 
-	// Insert asset storages
-	world.insert(AssetStorage::<EntityTemplate>::default());
-	world.insert(AssetStorage::<Sound>::default());
-	world.insert(AssetStorage::<doom::map::Map>::default());
-	world.insert(AssetStorage::<doom::map::textures::Flat>::default());
-	world.insert(AssetStorage::<doom::map::textures::Wall>::default());
-	world.insert(AssetStorage::<doom::image::Palette>::default());
-	world.insert(AssetStorage::<doom::sprite::Sprite>::default());
-	world.insert(AssetStorage::<doom::sprite::SpriteImage>::default());
+	// Registered before loading, so that the loaded values get applied too
+	let mut config_reader = config_variables.register_reader();
+	config.apply_variables(&mut config_variables);
 
-	// Insert other resources
-	world.insert(Pcg64Mcg::from_entropy());
+	if let Some(log_level) = arg_matches.value_of("log-level") {
+		config_variables.set_string("log-level", log_level)?;
+	}
+
+	if let Some(backend) = arg_matches.value_of("audio-backend") {
+		config_variables.set_string("audio-backend", backend)?;
+	}
+
+	if let Some(path) = arg_matches.value_of("audio-file") {
+		config_variables.set_string("audio-file", path)?;
+	}
+
+	let audio_output = start_audio_output(&mixer, &config_variables)?;
+
+	world.insert(config_variables);
+	world.insert(Console::new(log_receiver));
 	world.insert(render_context);
-	world.insert(sound_sender);
+	world.insert(mixer);
+	world.insert(audio_output);
 	world.insert(loader);
 	world.insert(InputState::new());
+	world.insert(ButtonCapture::<doom::input::BindTarget>::default());
+	world.insert(doom::menu::ControlsMenu::default());
 	world.insert(bindings);
-	world.insert(Vec::<(AssetHandle<Sound>, Entity)>::new());
+	world.insert(Vec::<(AssetHandle<Sound>, doom::sound::SoundOrigin)>::new());
+	world.insert(doom::music::Music::default());
 	world.insert(doom::client::Client::default());
+	world.insert(doom::client::Prediction::default());
+	world.insert(doom::client::ServerConnection::new(socket, addr));
+	world.insert(doom::replication::SnapshotReceiver::default());
 	world.insert(doom::data::FRAME_TIME);
-	world.insert(EventChannel::<doom::client::UseEvent>::new());
 
 	// Create systems
 	let mut render_system =
 		doom::render::RenderSystem::new(&world).context("Couldn't create RenderSystem")?;
+	let mut interpolation_system = doom::client::InterpolationSystem::default();
 	let mut sound_system = doom::sound::SoundSystem;
 	let mut update_dispatcher = DispatcherBuilder::new()
 		.with_thread_local(doom::client::PlayerCommandSystem::default())
-		.with_thread_local(doom::client::PlayerMoveSystem::default())
-		.with_thread_local(doom::client::PlayerUseSystem::default())
-		.with_thread_local(doom::physics::PhysicsSystem::default())
-		.with_thread_local(doom::door::DoorUpdateSystem::new(
-			world
-				.get_mut::<EventChannel<doom::client::UseEvent>>()
-				.unwrap()
-				.register_reader(),
-		))
-		.with_thread_local(doom::light::LightUpdateSystem::default())
+		.with_thread_local(doom::client::ClientSendSystem::default())
+		.with_thread_local(doom::client::ClientPredictSystem::default())
 		.with_thread_local(doom::update::TextureAnimSystem::default())
 		.build();
+	let mut server = server;
 
 	let mut should_quit = false;
 	let mut old_time = Instant::now();
@@ -182,9 +333,41 @@ fn main() -> anyhow::Result<()> {
 
 		// Process events from the system
 		event_loop.run_return(|event, _, control_flow| {
-			let (mut input_state, render_context) =
-				world.system_data::<(WriteExpect<InputState>, ReadExpect<RenderContext>)>();
-			input_state.process_event(&event);
+			let (
+				mut button_capture,
+				mut console,
+				config_variables,
+				mut input_state,
+				mut menu,
+				render_context,
+			) = world.system_data::<(
+				WriteExpect<ButtonCapture<doom::input::BindTarget>>,
+				WriteExpect<Console>,
+				ReadExpect<ConfigVariables>,
+				WriteExpect<InputState>,
+				WriteExpect<doom::menu::ControlsMenu>,
+				ReadExpect<RenderContext>,
+			)>();
+
+			// The console and the menu take the keyboard while they're open
+			let was_open = console.is_open();
+			let menu_was_open = menu.is_open();
+
+			if button_capture.process_event(&event) {
+				input_state.release_keys();
+			} else if console.process_event(&event, |command| {
+				completions(&commands, &config_variables, command)
+			}) {
+				if console.is_open() && !was_open {
+					input_state.release_keys();
+				}
+			} else if menu.process_event(&event, &mut button_capture) {
+				if menu.is_open() && !menu_was_open {
+					input_state.release_keys();
+				}
+			} else {
+				input_state.process_event(&event);
+			}
 
 			match event {
 				Event::WindowEvent { event, .. } => match event {
@@ -234,29 +417,79 @@ fn main() -> anyhow::Result<()> {
 			}
 		});
 
-		// Execute console commands
-		while let Some(command) = command_receiver.try_iter().next() {
-			// Split into tokens
-			let tokens = match commands::tokenize(&command) {
-				Ok(tokens) => tokens,
-				Err(e) => {
-					log::error!("Invalid syntax: {}", e);
-					continue;
+		// Process events from gamepads
+		{
+			let (mut button_capture, mut input_state) = world.system_data::<(
+				WriteExpect<ButtonCapture<doom::input::BindTarget>>,
+				WriteExpect<InputState>,
+			)>();
+
+			while let Some(event) = gamepad_backend.next_event() {
+				if let GamepadEvent::ButtonPressed(button) = event {
+					if button_capture.press(Button::Gamepad(button)) {
+						continue;
+					}
 				}
-			};
-
-			// Split further into subcommands
-			for args in tokens.split(|tok| tok == ";") {
-				match args[0].as_str() {
-					"map" => load_map(&args[1], &mut world)?,
-					"quit" => should_quit = true,
-					_ => log::error!("Unknown command: {}", args[0]),
+
+				input_state.process_gamepad_event(event);
+			}
+		}
+
+		// Bind the button that was pressed for the rebind command or the menu
+		let captured = world
+			.fetch_mut::<ButtonCapture<doom::input::BindTarget>>()
+			.take_captured();
+
+		if let Some((button, target)) = captured {
+			doom::input::bind(&mut world.fetch_mut(), button, target);
+			log::info!("{} = {}", button, target);
+		}
+
+		// Execute console commands
+		for line in world.fetch_mut::<Console>().take_submitted() {
+			if let Err(err) = commands.push(&line) {
+				log::error!("{:#}", err);
+			}
+		}
+
+		should_quit = execute_commands(
+			&mut commands,
+			&command_receiver,
+			Some(&mut world),
+			server.as_mut().map(|(server_world, _)| server_world),
+		);
+		apply_config_changes(&world, &mut config_reader);
+
+		// Process messages from the server
+		let messages = world
+			.fetch_mut::<doom::client::ServerConnection>()
+			.receive();
+
+		for message in messages {
+			if let ServerMessage::MapChange(name) = message {
+				if let Err(err) = client_change_map(&name, &mut world) {
+					log::error!("Could not change to map {}: {:#}", name, err);
+					world
+						.fetch_mut::<doom::client::ServerConnection>()
+						.disconnect();
+					break;
 				}
+			} else if let Err(err) = doom::client::handle_message(message, &world) {
+				log::warn!("Could not process message from the server: {}", err);
 			}
 		}
 
+		world.maintain();
+
+		if world
+			.fetch::<doom::client::ServerConnection>()
+			.is_disconnected()
+		{
+			should_quit = true;
+		}
+
 		if should_quit {
-			return Ok(());
+			break;
 		}
 
 		// Run game frames
@@ -266,6 +499,16 @@ fn main() -> anyhow::Result<()> {
 			leftover_time -= doom::data::FRAME_TIME;
 
 			update_dispatcher.dispatch(&world);
+			world
+				.fetch_mut::<AudioOutput>()
+				.advance(doom::data::FRAME_TIME);
+
+			if let Some((server_world, server_dispatcher)) = &mut server {
+				server_dispatcher.dispatch(server_world);
+				server_world.maintain();
+				should_quit |= execute_rcon_commands(&mut commands, server_world);
+				end_map(server_world);
+			}
 
 			// Reset input delta state
 			{
@@ -274,22 +517,815 @@ fn main() -> anyhow::Result<()> {
 			}
 		}
 
+		// Move remote entities smoothly between snapshots
+		interpolation_system.run_now(&world);
+
 		// Update sound
 		sound_system.run_now(&world);
 
 		// Draw frame
+		world.fetch_mut::<Console>().update();
 		render_system.run_now(&world);
 	}
 
+	world
+		.fetch_mut::<doom::client::ServerConnection>()
+		.disconnect();
+
+	if let Some((server_world, _)) = &mut server {
+		server_world.fetch_mut::<doom::server::Server>().shutdown();
+	}
+
+	// Save settings for next time
+	if save_config {
+		config.bindings = Some(Bindings::clone(&world.fetch()));
+		config.store_variables(&world.fetch::<ConfigVariables>());
+
+		if let Err(err) = config.save() {
+			log::error!("{:#}", err);
+		}
+	}
+
+	Ok(())
+}
+
+fn run_dedicated(
+	mut commands: CommandList,
+	command_receiver: Receiver<String>,
+	mut world: World,
+	mut dispatcher: Dispatcher<'static, 'static>,
+) -> anyhow::Result<()> {
+	let mut next_tic = Instant::now();
+
+	loop {
+		if execute_commands(&mut commands, &command_receiver, None, Some(&mut world)) {
+			break;
+		}
+
+		dispatcher.dispatch(&world);
+		world.maintain();
+
+		if execute_rcon_commands(&mut commands, &mut world) {
+			break;
+		}
+
+		end_map(&mut world);
+
+		// Sleep until the next tic is due
+		next_tic += doom::data::FRAME_TIME;
+		let now = Instant::now();
+
+		if next_tic > now {
+			std::thread::sleep(next_tic - now);
+		} else {
+			next_tic = now;
+		}
+	}
+
+	world.fetch_mut::<doom::server::Server>().shutdown();
+
 	Ok(())
 }
 
-fn load_wads(loader: &mut doom::wad::WadLoader, arg_matches: &ArgMatches) -> anyhow::Result<()> {
+/// Runs the console commands that were entered, returns whether to quit.
+fn execute_commands(
+	commands: &mut CommandList,
+	command_receiver: &Receiver<String>,
+	client_world: Option<&mut World>,
+	server_world: Option<&mut World>,
+) -> bool {
+	for line in command_receiver.try_iter() {
+		if let Err(err) = commands.push(&line) {
+			log::error!("{:#}", err);
+		}
+	}
+
+	commands.execute(client_world, server_world)
+}
+
+/// Runs the commands received by the server's remote console, and sends back their output.
+/// Returns whether to quit.
+fn execute_rcon_commands(commands: &mut CommandList, server_world: &mut World) -> bool {
+	let mut should_quit = false;
+	let rcon_commands = server_world
+		.fetch_mut::<doom::server::Server>()
+		.take_rcon_commands();
+
+	for (addr, command) in rcon_commands {
+		let (quit, output) = logger::capture(|| {
+			log::info!("Rcon command from {}: {}", addr, command);
+			commands.execute_now(&command, None, Some(server_world))
+		});
+
+		should_quit |= quit;
+		server_world
+			.fetch::<doom::server::Server>()
+			.rcon_reply(addr, output);
+	}
+
+	should_quit
+}
+
+fn register_config_variables(config_variables: &mut ConfigVariables) {
+	config_variables.add(
+		ConfigVariable::new(
+			"always-run",
+			"Run without holding the run button, which then walks instead",
+			true,
+		)
+		.with_archive(),
+	);
+	config_variables.add(ConfigVariable::new(
+		"audio-backend",
+		"Where to send sound and music: device, null or wav",
+		AudioBackend::Device,
+	));
+	config_variables.add(ConfigVariable::new(
+		"audio-file",
+		"File for the wav audio backend to write to",
+		"ferret.wav".to_owned(),
+	));
+	config_variables.add(
+		ConfigVariable::new(
+			"colormap-lighting",
+			"Light with the original's colormaps, with their banding, instead of smoothly",
+			true,
+		)
+		.with_archive(),
+	);
+	config_variables.add(
+		ConfigVariable::new("fov", "Horizontal field of view in degrees", 90.0f32)
+			.with_archive()
+			.with_validator(|fov| *fov >= 1.0 && *fov <= 179.0),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"fov-aspect",
+			"Vertical stretching of the view, 1.2 matches the original's non-square pixels",
+			1.2f32,
+		)
+		.with_archive()
+		.with_validator(|aspect| *aspect > 0.0),
+	);
+	config_variables.add(ConfigVariable::new(
+		"log-level",
+		"Highest log level to display",
+		logger::LOG_LEVEL,
+	));
+	config_variables.add(
+		ConfigVariable::new(
+			"mouse-acceleration",
+			"How much faster mouse movement turns more, 0 turns it off",
+			0.0f64,
+		)
+		.with_archive()
+		.with_validator(|acceleration| *acceleration >= 0.0),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"mouse-filter",
+			"Smooth mouse movement by averaging it over two tics",
+			false,
+		)
+		.with_archive(),
+	);
+	config_variables.add(
+		ConfigVariable::new("mouse-sensitivity", "Mouse movement multiplier", 1.0f64)
+			.with_archive()
+			.with_validator(|sensitivity| *sensitivity >= 0.0),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"mouselook",
+			"Look up and down with the mouse, off keeps the view level as in the original",
+			true,
+		)
+		.with_archive(),
+	);
+	config_variables.add(
+		ConfigVariable::new("music-volume", "Music volume, from 0 to 1", 1.0f32)
+			.with_archive()
+			.with_validator(|volume| *volume >= 0.0 && *volume <= 1.0),
+	);
+	config_variables.add(
+		ConfigVariable::new("sfx-volume", "Sound effect volume, from 0 to 1", 1.0f32)
+			.with_archive()
+			.with_validator(|volume| *volume >= 0.0 && *volume <= 1.0),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"sound-channels",
+			"How many sounds can play at once, the original has 8",
+			8usize,
+		)
+		.with_archive()
+		.with_validator(|channels| *channels >= 1 && *channels <= 64),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"sound-occlusion",
+			"Closed doors and sound-blocking lines muffle or silence sounds behind them",
+			false,
+		)
+		.with_archive(),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"texture-filtering",
+			"Smooth textures instead of showing pixels",
+			false,
+		)
+		.with_archive(),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"translucency",
+			"Draw some projectiles, effects and powerups translucently, like Boom",
+			true,
+		)
+		.with_archive(),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"volume",
+			"Master volume of sound and music, from 0 to 1",
+			1.0f32,
+		)
+		.with_archive()
+		.with_validator(|volume| *volume >= 0.0 && *volume <= 1.0),
+	);
+	config_variables.add(
+		ConfigVariable::new(
+			"vsync",
+			"Wait for the display before showing a new frame",
+			true,
+		)
+		.with_archive(),
+	);
+}
+
+/// Applies the config variables that aren't handled by any system.
+fn apply_config_changes(world: &World, config_reader: &mut ReaderId<&'static str>) {
+	let config_variables = world.fetch::<ConfigVariables>();
+
+	for name in config_variables.changes(config_reader) {
+		match *name {
+			"audio-backend" | "audio-file" => {
+				let mut audio_output = world.fetch_mut::<AudioOutput>();
+				let backend = *config_variables.get::<AudioBackend>("audio-backend");
+				let path = Path::new(config_variables.get::<String>("audio-file"));
+
+				if audio_output.backend() != backend
+					|| (backend == AudioBackend::Wav && audio_output.path() != path)
+				{
+					// Stop the old one first, so that it's done with the file
+					audio_output.stop();
+
+					match start_audio_output(&world.fetch::<Mixer>(), &config_variables) {
+						Ok(new_output) => *audio_output = new_output,
+						Err(err) => log::error!("{:#}", err),
+					}
+				}
+			}
+			"log-level" => log::set_max_level(*config_variables.get("log-level")),
+			"mouse-acceleration" => world
+				.fetch_mut::<InputState>()
+				.set_mouse_acceleration(*config_variables.get("mouse-acceleration")),
+			"mouse-filter" => world
+				.fetch_mut::<InputState>()
+				.set_mouse_filter(*config_variables.get("mouse-filter")),
+			"mouse-sensitivity" => world
+				.fetch_mut::<InputState>()
+				.set_mouse_sensitivity(*config_variables.get("mouse-sensitivity")),
+			"music-volume" => world
+				.fetch::<Mixer>()
+				.set_music_volume(*config_variables.get("music-volume")),
+			"sfx-volume" => world
+				.fetch::<Mixer>()
+				.set_sfx_volume(*config_variables.get("sfx-volume")),
+			"sound-channels" => world
+				.fetch::<Mixer>()
+				.set_channel_count(*config_variables.get("sound-channels")),
+			"volume" => world
+				.fetch::<Mixer>()
+				.set_master_volume(*config_variables.get("volume")),
+			_ => {}
+		}
+	}
+}
+
+/// Starts the audio backend chosen in the config variables.
+/// If it can't be started, there is no audio, but the game still runs.
+fn start_audio_output(
+	mixer: &Mixer,
+	config_variables: &ConfigVariables,
+) -> anyhow::Result<AudioOutput> {
+	let backend = *config_variables.get::<AudioBackend>("audio-backend");
+	let path = Path::new(config_variables.get::<String>("audio-file"));
+
+	AudioOutput::new(mixer, backend, path).or_else(|err| {
+		log::error!("{:#}", err);
+		log::warn!("Audio backend \"{}\" failed, sound is disabled", backend);
+		AudioOutput::new(mixer, AudioBackend::Null, path)
+	})
+}
+
+/// Words that can be typed for a command, or command names if it's `None`.
+/// Used for tab completion in the console.
+fn completions(
+	commands: &CommandList,
+	config_variables: &ConfigVariables,
+	command: Option<&str>,
+) -> Vec<String> {
+	match command {
+		None | Some("help") => commands.names().into_iter().map(str::to_owned).collect(),
+		Some("get") | Some("reset") | Some("set") | Some("toggle") => config_variables
+			.variables()
+			.into_iter()
+			.map(|variable| variable.name().to_owned())
+			.collect(),
+		Some(_) => Vec::new(),
+	}
+}
+
+fn register_commands(commands: &mut CommandList) {
+	commands.add(
+		Command::new(
+			"bind",
+			"Binds a button to an action or to +AXIS/-AXIS, or shows what it's bound to",
+			CommandTarget::Client,
+			|args, world| {
+				let button: Button = args.get(0)?;
+				let mut bindings =
+					world.fetch_mut::<Bindings<doom::input::Action, doom::input::Axis>>();
+
+				match args.get_optional(1)? {
+					Some(target) => doom::input::bind(&mut bindings, button, target),
+					None => {
+						let name = button.to_string();
+						let targets: Vec<_> = doom::input::binding_list(&bindings)
+							.into_iter()
+							.filter(|(b, _)| *b == name)
+							.map(|(_, target)| target)
+							.collect();
+
+						if targets.is_empty() {
+							log::info!("{} is not bound", name);
+						} else {
+							log::info!("{} = {}", name, targets.join(", "));
+						}
+					}
+				}
+
+				Ok(())
+			},
+		)
+		.with_arg("BUTTON")
+		.with_optional_arg("TARGET"),
+	);
+	commands.add(
+		Command::new(
+			"bindaxis",
+			"Binds the mouse or a gamepad axis to an axis, a - before SOURCE inverts it",
+			CommandTarget::Client,
+			|args, world| {
+				let source: String = args.get(0)?;
+				let (source, invert) = match source.strip_prefix('-') {
+					Some(source) => (source, true),
+					None => (source.as_str(), false),
+				};
+				let source: AnalogAxis = source.parse()?;
+				let axis: doom::input::Axis = args.get(1)?;
+				let scale = args.get_optional(2)?.unwrap_or(1.0);
+
+				let binding = match source {
+					AnalogAxis::Mouse(axis) => Axis::Mouse {
+						axis,
+						scale: if invert { -scale } else { scale },
+					},
+					AnalogAxis::Gamepad(axis) => Axis::Gamepad {
+						axis,
+						scale,
+						deadzone: args.get_optional(3)?.unwrap_or(input::DEFAULT_DEADZONE),
+						curve: args.get_optional(4)?.unwrap_or(1.0),
+						invert,
+					},
+				};
+
+				world
+					.fetch_mut::<Bindings<doom::input::Action, doom::input::Axis>>()
+					.bind_axis(axis, binding);
+				Ok(())
+			},
+		)
+		.with_arg("SOURCE")
+		.with_arg("AXIS")
+		.with_optional_arg("SCALE")
+		.with_optional_arg("DEADZONE")
+		.with_optional_arg("CURVE"),
+	);
+	commands.add(Command::new(
+		"bindlist",
+		"Shows all button bindings",
+		CommandTarget::Client,
+		|_, world| {
+			let bindings = world.fetch::<Bindings<doom::input::Action, doom::input::Axis>>();
+
+			for (button, target) in doom::input::binding_list(&bindings) {
+				log::info!("{} = {}", button, target);
+			}
+
+			Ok(())
+		},
+	));
+	commands.add(
+		Command::new(
+			"changemus",
+			"Changes the music, NAME is a music lump or a map whose music to play",
+			CommandTarget::Client,
+			|args, world| {
+				let name: String = args.get(0)?;
+				let name = doom::music::map_music(&name).unwrap_or(name);
+				doom::music::play_music(world, &name)
+			},
+		)
+		.with_arg("NAME"),
+	);
+	commands.add(
+		Command::new(
+			"get",
+			"Shows the value of a config variable, or of all of them",
+			CommandTarget::Client,
+			|args, world| {
+				let config_variables = world.fetch::<ConfigVariables>();
+
+				match args.get_optional::<String>(0)? {
+					Some(name) => {
+						let variable = config_variables.variable(&name)?;
+						log::info!(
+							"{} = {} (default {})",
+							name,
+							variable,
+							variable.default_string()
+						);
+						log::info!("{}", variable.help());
+					}
+					None => {
+						for variable in config_variables.variables() {
+							log::info!("{} = {}", variable.name(), variable);
+						}
+					}
+				}
+
+				Ok(())
+			},
+		)
+		.with_optional_arg("NAME"),
+	);
+	commands.add(
+		Command::new(
+			"kill",
+			"Kills a player",
+			CommandTarget::Server,
+			|args, world| doom::game::kill_player(world, args.get(0)?, None),
+		)
+		.with_arg("PLAYER"),
+	);
+	commands.add(
+		Command::new(
+			"map",
+			"Changes to a new map",
+			CommandTarget::Server,
+			|args, world| server_change_map(&args.get::<String>(0)?, world),
+		)
+		.with_arg("NAME"),
+	);
+	commands.add(
+		Command::new(
+			"playsound",
+			"Plays a sound lump, heard the same everywhere",
+			CommandTarget::Client,
+			|args, world| {
+				let name: String = args.get(0)?;
+				let (mut loader, mut sound_storage, mut sound_queue) = world.system_data::<(
+					WriteExpect<doom::wad::WadLoader>,
+					WriteExpect<AssetStorage<Sound>>,
+					WriteExpect<Vec<(AssetHandle<Sound>, doom::sound::SoundOrigin)>>,
+				)>();
+				let handle = sound_storage.load(&name.to_ascii_uppercase(), &mut *loader);
+				sound_storage.build_waiting(doom::sound::build_sound);
+				sound_queue.push((handle, doom::sound::SoundOrigin::Global));
+				Ok(())
+			},
+		)
+		.with_arg("NAME"),
+	);
+	commands.add(
+		Command::new(
+			"rebind",
+			"Binds the next button that is pressed, Escape cancels",
+			CommandTarget::Client,
+			|args, world| {
+				let target: doom::input::BindTarget = args.get(0)?;
+				log::info!("Press a button to bind to {}", target);
+				world
+					.fetch_mut::<ButtonCapture<doom::input::BindTarget>>()
+					.start(target);
+				Ok(())
+			},
+		)
+		.with_arg("TARGET"),
+	);
+	commands.add(
+		Command::new(
+			"reset",
+			"Sets a config variable back to its default",
+			CommandTarget::Client,
+			|args, world| {
+				world
+					.fetch_mut::<ConfigVariables>()
+					.reset(&args.get::<String>(0)?)
+			},
+		)
+		.with_arg("NAME"),
+	);
+	commands.add(Command::new(
+		"scores",
+		"Shows the frags of each player",
+		CommandTarget::Client,
+		|_, world| {
+			for (player_num, frags) in world.fetch::<doom::client::Client>().scores.iter() {
+				log::info!("Player {}: {} frag(s)", player_num, frags);
+			}
+
+			Ok(())
+		},
+	));
+	commands.add(
+		Command::new(
+			"set",
+			"Sets a config variable",
+			CommandTarget::Client,
+			|args, world| {
+				world
+					.fetch_mut::<ConfigVariables>()
+					.set_string(&args.get::<String>(0)?, &args.get::<String>(1)?)
+			},
+		)
+		.with_arg("NAME")
+		.with_arg("VALUE"),
+	);
+	commands.add(Command::new(
+		"status",
+		"Shows the server's map, rules and players",
+		CommandTarget::Server,
+		|_, world| {
+			let server = world.fetch::<doom::server::Server>();
+			server.log_status();
+			world.fetch::<doom::game::GameRules>().log_status();
+			server.log_scores();
+			Ok(())
+		},
+	));
+	commands.add(
+		Command::new(
+			"toggle",
+			"Switches a boolean config variable on or off",
+			CommandTarget::Client,
+			|args, world| {
+				world
+					.fetch_mut::<ConfigVariables>()
+					.toggle(&args.get::<String>(0)?)
+			},
+		)
+		.with_arg("NAME"),
+	);
+	commands.add(
+		Command::new(
+			"unbind",
+			"Removes a button or analog axis from everything it's bound to",
+			CommandTarget::Client,
+			|args, world| {
+				let name: String = args.get(0)?;
+				let mut bindings =
+					world.fetch_mut::<Bindings<doom::input::Action, doom::input::Axis>>();
+
+				let found = match name.parse::<AnalogAxis>() {
+					Ok(source) => bindings.unbind_axis(source),
+					Err(_) => bindings.unbind(args.get(0)?),
+				};
+
+				if !found {
+					log::info!("{} is not bound", name);
+				}
+
+				Ok(())
+			},
+		)
+		.with_arg("BUTTON"),
+	);
+	commands.add(Command::new(
+		"unbindall",
+		"Removes all button bindings",
+		CommandTarget::Client,
+		|_, world| {
+			world
+				.fetch_mut::<Bindings<doom::input::Action, doom::input::Axis>>()
+				.clear();
+			Ok(())
+		},
+	));
+}
+
+/// Moves on to the next map once the rules say the current one is over.
+fn end_map(server_world: &mut World) {
+	if !server_world.fetch::<doom::game::GameRules>().map_ended {
+		return;
+	}
+
+	let current = match server_world.fetch::<doom::server::Server>().map() {
+		Some(map) => map.to_owned(),
+		None => return,
+	};
+
+	// Play the same map again if there's nothing after it
+	let next = {
+		let loader = server_world.fetch::<doom::wad::WadLoader>();
+		doom::game::next_map(&current, loader.names()).unwrap_or_else(|| current.clone())
+	};
+
+	if let Err(err) = server_change_map(&next, server_world) {
+		log::error!("Could not change to map {}: {:#}", next, err);
+
+		if next != current {
+			log::info!("Restarting the current map instead");
+
+			match server_change_map(&current, server_world) {
+				Ok(()) => return,
+				Err(err) => log::error!("Could not restart map {}: {:#}", current, err),
+			}
+		}
+
+		// Keep playing instead of trying again every tic
+		let mut rules = server_world.fetch_mut::<doom::game::GameRules>();
+		rules.map_time = Duration::default();
+		rules.map_ended = false;
+	}
+}
+
+fn game_rules(arg_matches: &ArgMatches) -> anyhow::Result<doom::game::GameRules> {
+	let mode = if arg_matches.is_present("altdeath") {
+		doom::game::GameMode::AltDeath
+	} else if arg_matches.is_present("deathmatch") {
+		doom::game::GameMode::Deathmatch
+	} else {
+		doom::game::GameMode::Coop
+	};
+
+	let frag_limit = match arg_matches.value_of("fraglimit") {
+		Some(frags) => Some(
+			frags
+				.parse()
+				.context(format!("Invalid frag limit: {}", frags))?,
+		),
+		None => None,
+	};
+
+	let time_limit = match arg_matches.value_of("timelimit") {
+		Some(minutes) => {
+			let minutes: u64 = minutes
+				.parse()
+				.context(format!("Invalid time limit: {}", minutes))?;
+			Some(Duration::from_secs(minutes * 60))
+		}
+		None => None,
+	};
+
+	Ok(doom::game::GameRules {
+		mode,
+		frag_limit,
+		time_limit,
+		..doom::game::GameRules::default()
+	})
+}
+
+fn default_map<'a>(
+	loader: &doom::wad::WadLoader,
+	arg_matches: &'a ArgMatches,
+) -> anyhow::Result<&'a str> {
+	if let Some(map) = arg_matches.value_of("map") {
+		Ok(map)
+	} else {
+		let wad = loader.wads().next().unwrap().file_name().unwrap();
+
+		if wad == "doom.wad" || wad == "doom1.wad" || wad == "doomu.wad" {
+			Ok("E1M1")
+		} else if wad == "doom2.wad" || wad == "tnt.wad" || wad == "plutonia.wad" {
+			Ok("MAP01")
+		} else {
+			bail!(
+				"No default map is known for this IWAD. Try specifying one with the \"-m\" option."
+			)
+		}
+	}
+}
+
+fn register_components(world: &mut World) {
+	world.register::<doom::client::Interpolation>();
+	world.register::<doom::client::UseAction>();
+	world.register::<doom::client::User>();
+	world.register::<doom::components::DeathmatchStart>();
+	world.register::<doom::components::SpawnOnCeiling>();
+	world.register::<doom::components::SpawnPoint>();
+	world.register::<doom::components::Transform>();
+	world.register::<doom::components::Velocity>();
+	world.register::<doom::door::DoorActive>();
+	world.register::<doom::door::SwitchActive>();
+	world.register::<doom::effects::PlayerEffects>();
+	world.register::<doom::light::LightFlash>();
+	world.register::<doom::light::LightGlow>();
+	world.register::<doom::map::LinedefRef>();
+	world.register::<doom::map::MapDynamic>();
+	world.register::<doom::map::SectorRef>();
+	world.register::<doom::physics::BoxCollider>();
+	world.register::<doom::render::sprite::SpriteRender>();
+	world.register::<doom::sound::SoundPlaying>();
+	world.register::<doom::update::TextureScroll>();
+
+	let mut registry = doom::replication::ReplicationRegistry::default();
+	registry.register::<doom::components::Transform>();
+	registry.register::<doom::components::Velocity>();
+	registry.register::<doom::effects::PlayerEffects>();
+	registry.register::<doom::map::LinedefRef>();
+	registry.register::<doom::map::SectorRef>();
+	registry.register::<doom::render::sprite::SpriteRender>();
+	world.insert(registry);
+}
+
+fn insert_asset_storages(world: &mut World) {
+	world.insert(AssetStorage::<EntityTemplate>::default());
+	world.insert(AssetStorage::<Sound>::default());
+	world.insert(AssetStorage::<doom::map::Map>::default());
+	world.insert(AssetStorage::<doom::map::textures::Flat>::default());
+	world.insert(AssetStorage::<doom::map::textures::Wall>::default());
+	world.insert(AssetStorage::<doom::sprite::Sprite>::default());
+	world.insert(AssetStorage::<doom::sprite::SpriteImage>::default());
+}
+
+fn create_server(
+	loader: doom::wad::WadLoader,
+	socket: Socket,
+	rcon_password: Option<&str>,
+	rules: doom::game::GameRules,
+) -> (World, Dispatcher<'static, 'static>) {
+	let mut world = World::new();
+	register_components(&mut world);
+	insert_asset_storages(&mut world);
+
+	world.insert(Pcg64Mcg::from_entropy());
+	world.insert(loader);
+	world.insert(Vec::<(AssetHandle<Sound>, doom::sound::SoundOrigin)>::new());
+	world.insert(doom::data::FRAME_TIME);
+	world.insert(EventChannel::<doom::client::UseEvent>::new());
+	world.insert(doom::server::Server::new(
+		socket,
+		rcon_password.map(ToOwned::to_owned),
+	));
+	world.insert(rules);
+
+	let dispatcher = DispatcherBuilder::new()
+		.with_thread_local(doom::server::ServerReceiveSystem::default())
+		.with_thread_local(doom::client::PlayerMoveSystem::default())
+		.with_thread_local(doom::client::PlayerUseSystem::default())
+		.with_thread_local(doom::physics::PhysicsSystem::default())
+		.with_thread_local(doom::door::DoorUpdateSystem::new(
+			world
+				.get_mut::<EventChannel<doom::client::UseEvent>>()
+				.unwrap()
+				.register_reader(),
+		))
+		.with_thread_local(doom::light::LightUpdateSystem::default())
+		.with_thread_local(doom::effects::PlayerEffectsSystem::default())
+		.with_thread_local(doom::game::GameRulesSystem::default())
+		.with_thread_local(doom::server::ServerSendSystem::default())
+		.build();
+
+	(world, dispatcher)
+}
+
+/// Loads the IWAD and PWADs, returns the path of the IWAD.
+fn load_wads(
+	loader: &mut doom::wad::WadLoader,
+	arg_matches: &ArgMatches,
+	last_iwad: Option<&PathBuf>,
+) -> anyhow::Result<PathBuf> {
 	let mut wads = Vec::new();
 	const IWADS: [&str; 6] = ["doom2", "plutonia", "tnt", "doomu", "doom", "doom1"];
 
 	let iwad = if let Some(iwad) = arg_matches.value_of("iwad") {
 		PathBuf::from(iwad)
+	} else if let Some(iwad) = last_iwad.filter(|p| p.is_file()) {
+		iwad.clone()
 	} else if let Some(iwad) = IWADS
 		.iter()
 		.map(|p| PathBuf::from(format!("{}.wad", p)))
@@ -300,7 +1336,7 @@ fn load_wads(loader: &mut doom::wad::WadLoader, arg_matches: &ArgMatches) -> any
 		bail!("No iwad file found. Try specifying one with the \"-i\" command line option.")
 	};
 
-	wads.push(iwad);
+	wads.push(iwad.clone());
 
 	if let Some(iter) = arg_matches.values_of("PWADS") {
 		wads.extend(iter.map(PathBuf::from));
@@ -325,10 +1361,10 @@ fn load_wads(loader: &mut doom::wad::WadLoader, arg_matches: &ArgMatches) -> any
 		}
 	}
 
-	Ok(())
+	Ok(iwad)
 }
 
-fn get_bindings() -> Bindings<doom::input::Action, doom::input::Axis> {
+fn default_bindings() -> Bindings<doom::input::Action, doom::input::Axis> {
 	let mut bindings = Bindings::new();
 	bindings.bind_action(
 		doom::input::Action::Attack,
@@ -337,25 +1373,61 @@ fn get_bindings() -> Bindings<doom::input::Action, doom::input::Axis> {
 	bindings.bind_action(doom::input::Action::Use, Button::Key(VirtualKeyCode::Space));
 	bindings.bind_action(doom::input::Action::Use, Button::Mouse(MouseButton::Middle));
 	bindings.bind_action(
-		doom::input::Action::Walk,
+		doom::input::Action::Run,
 		Button::Key(VirtualKeyCode::LShift),
 	);
 	bindings.bind_action(
-		doom::input::Action::Walk,
+		doom::input::Action::Run,
 		Button::Key(VirtualKeyCode::RShift),
 	);
+	bindings.bind_action(
+		doom::input::Action::Strafe,
+		Button::Key(VirtualKeyCode::LAlt),
+	);
+	bindings.bind_action(
+		doom::input::Action::Strafe,
+		Button::Key(VirtualKeyCode::RAlt),
+	);
+	bindings.bind_action(
+		doom::input::Action::PreviousWeapon,
+		Button::Wheel(WheelDirection::Up),
+	);
+	bindings.bind_action(
+		doom::input::Action::NextWeapon,
+		Button::Wheel(WheelDirection::Down),
+	);
 	bindings.bind_axis(
 		doom::input::Axis::Forward,
 		Axis::Emulated {
-			pos: Button::Key(VirtualKeyCode::W),
-			neg: Button::Key(VirtualKeyCode::S),
+			pos: vec![
+				Button::Key(VirtualKeyCode::W),
+				Button::Key(VirtualKeyCode::Up),
+			],
+			neg: vec![
+				Button::Key(VirtualKeyCode::S),
+				Button::Key(VirtualKeyCode::Down),
+			],
 		},
 	);
 	bindings.bind_axis(
 		doom::input::Axis::Strafe,
 		Axis::Emulated {
-			pos: Button::Key(VirtualKeyCode::A),
-			neg: Button::Key(VirtualKeyCode::D),
+			pos: vec![Button::Key(VirtualKeyCode::A)],
+			neg: vec![Button::Key(VirtualKeyCode::D)],
+		},
+	);
+	bindings.bind_axis(
+		doom::input::Axis::Yaw,
+		Axis::Emulated {
+			pos: vec![Button::Key(VirtualKeyCode::Right)],
+			neg: vec![Button::Key(VirtualKeyCode::Left)],
+		},
+	);
+	bindings.bind_axis(
+		doom::input::Axis::Pitch,
+		Axis::Emulated {
+			pos: vec![Button::Key(VirtualKeyCode::PageDown)],
+			neg: vec![Button::Key(VirtualKeyCode::PageUp)],
 		},
 	);
 	bindings.bind_axis(
@@ -372,25 +1444,62 @@ fn get_bindings() -> Bindings<doom::input::Action, doom::input::Axis> {
 			scale: 3.0,
 		},
 	);
-	//println!("{}", serde_json::to_string(&bindings)?);
 
+	// Gamepad
+	bindings.bind_action(
+		doom::input::Action::Attack,
+		Button::Gamepad(GamepadButton::RightTrigger2),
+	);
+	bindings.bind_action(
+		doom::input::Action::Use,
+		Button::Gamepad(GamepadButton::South),
+	);
+	bindings.bind_axis(
+		doom::input::Axis::Forward,
+		Axis::Gamepad {
+			axis: GamepadAxis::LeftStickY,
+			scale: 1.0,
+			deadzone: input::DEFAULT_DEADZONE,
+			curve: 1.0,
+			invert: false,
+		},
+	);
+	bindings.bind_axis(
+		doom::input::Axis::Strafe,
+		Axis::Gamepad {
+			axis: GamepadAxis::LeftStickX,
+			scale: 1.0,
+			deadzone: input::DEFAULT_DEADZONE,
+			curve: 1.0,
+			invert: true,
+		},
+	);
+	bindings.bind_axis(
+		doom::input::Axis::Yaw,
+		Axis::Gamepad {
+			axis: GamepadAxis::RightStickX,
+			scale: 40.0,
+			deadzone: input::DEFAULT_DEADZONE,
+			curve: 2.0,
+			invert: false,
+		},
+	);
+	bindings.bind_axis(
+		doom::input::Axis::Pitch,
+		Axis::Gamepad {
+			axis: GamepadAxis::RightStickY,
+			scale: 20.0,
+			deadzone: input::DEFAULT_DEADZONE,
+			curve: 2.0,
+			invert: true,
+		},
+	);
 	bindings
 }
 
-fn load_map(name: &str, world: &mut World) -> anyhow::Result<()> {
+/// Loads the parts of a map that both the server and the client need.
+fn load_map(name: &str, world: &mut World) -> anyhow::Result<AssetHandle<doom::map::Map>> {
 	log::info!("Starting map {}...", name);
-	let start_time = Instant::now();
-
-	// Load palette
-	let palette_handle = {
-		let (mut loader, mut palette_storage) = world.system_data::<(
-			WriteExpect<doom::wad::WadLoader>,
-			WriteExpect<AssetStorage<crate::doom::image::Palette>>,
-		)>();
-		let handle = palette_storage.load("PLAYPAL", &mut *loader);
-		palette_storage.build_waiting(Ok);
-		handle
-	};
 
 	// Load entity type data
 	log::info!("Loading entity data...");
@@ -398,39 +1507,59 @@ fn load_map(name: &str, world: &mut World) -> anyhow::Result<()> {
 	world.insert(doom::data::SectorTypes::new(&world));
 	world.insert(doom::data::LinedefTypes::new(&world));
 
+	// Load map
+	log::info!("Loading map...");
+	let map_handle = {
+		let (mut loader, mut map_storage, mut flat_storage, mut wall_storage) = world
+			.system_data::<(
+				WriteExpect<doom::wad::WadLoader>,
+				WriteExpect<AssetStorage<doom::map::Map>>,
+				WriteExpect<AssetStorage<doom::map::textures::Flat>>,
+				WriteExpect<AssetStorage<doom::map::textures::Wall>>,
+			)>();
+		let map_handle = map_storage.load(name, &mut *loader);
+		map_storage.build_waiting(|data| {
+			doom::map::load::build_map(
+				data,
+				doom::map::load::sky_for_map(name),
+				&mut *loader,
+				&mut *flat_storage,
+				&mut *wall_storage,
+			)
+		});
+
+		map_handle
+	};
+
+	// Create quadtree
+	let bbox = {
+		let map_storage = world.system_data::<ReadExpect<AssetStorage<doom::map::Map>>>();
+		let map = map_storage
+			.get(&map_handle)
+			.ok_or_else(|| anyhow!("Map {} could not be loaded", name))?;
+		map.bbox.clone()
+	};
+	world.insert(Quadtree::new(bbox));
+
+	Ok(map_handle)
+}
+
+/// Builds the assets that the client needs for rendering and sound.
+fn build_assets(world: &World) -> anyhow::Result<()> {
 	// Load sprite images
 	{
-		let (
-			palette_storage,
-			mut sprite_storage,
-			mut sprite_image_storage,
-			mut source,
-			render_context,
-		) = world.system_data::<(
-			ReadExpect<AssetStorage<crate::doom::image::Palette>>,
-			WriteExpect<AssetStorage<crate::doom::sprite::Sprite>>,
-			WriteExpect<AssetStorage<crate::doom::sprite::SpriteImage>>,
-			WriteExpect<crate::doom::wad::WadLoader>,
-			ReadExpect<crate::renderer::RenderContext>,
-		)>();
-		let palette = palette_storage.get(&palette_handle).unwrap();
+		let (mut sprite_storage, mut sprite_image_storage, mut source, render_context) = world
+			.system_data::<(
+				WriteExpect<AssetStorage<crate::doom::sprite::Sprite>>,
+				WriteExpect<AssetStorage<crate::doom::sprite::SpriteImage>>,
+				WriteExpect<crate::doom::wad::WadLoader>,
+				ReadExpect<crate::renderer::RenderContext>,
+			)>();
 		sprite_storage.build_waiting(|intermediate| {
 			Ok(intermediate.build(&mut *sprite_image_storage, &mut *source)?)
 		});
 
 		sprite_image_storage.build_waiting(|image| {
-			let data: Vec<_> = image
-				.data
-				.into_iter()
-				.map(|pixel| {
-					if pixel.a == 0xFF {
-						palette[pixel.i as usize]
-					} else {
-						crate::doom::image::RGBAColor::default()
-					}
-				})
-				.collect();
-
 			// Create the image
 			let matrix = Matrix4::new_translation(&Vector3::new(
 				0.0,
@@ -443,12 +1572,12 @@ fn load_map(name: &str, world: &mut World) -> anyhow::Result<()> {
 			));
 
 			let (image, _future) = ImmutableImage::from_iter(
-				data.as_bytes().iter().copied(),
+				image.data.as_bytes().iter().copied(),
 				Dimensions::Dim2d {
 					width: image.size[0] as u32,
 					height: image.size[1] as u32,
 				},
-				Format::R8G8B8A8Unorm,
+				Format::R8G8Unorm,
 				render_context.queues().graphics.clone(),
 			)?;
 
@@ -463,59 +1592,21 @@ fn load_map(name: &str, world: &mut World) -> anyhow::Result<()> {
 		sound_storage.build_waiting(|intermediate| doom::sound::build_sound(intermediate));
 	}
 
-	// Load map
-	log::info!("Loading map...");
-	let map_handle = {
-		let (mut loader, mut map_storage, mut flat_storage, mut wall_storage) = world
-			.system_data::<(
-				WriteExpect<doom::wad::WadLoader>,
-				WriteExpect<AssetStorage<doom::map::Map>>,
-				WriteExpect<AssetStorage<doom::map::textures::Flat>>,
-				WriteExpect<AssetStorage<doom::map::textures::Wall>>,
-			)>();
-		let map_handle = map_storage.load(name, &mut *loader);
-		map_storage.build_waiting(|data| {
-			doom::map::load::build_map(
-				data,
-				"SKY1",
-				&mut *loader,
-				&mut *flat_storage,
-				&mut *wall_storage,
-			)
-		});
-
-		map_handle
-	};
-
 	// Build flats and wall textures
 	{
-		let (palette_storage, mut flat_storage, render_context) = world.system_data::<(
-			ReadExpect<AssetStorage<doom::image::Palette>>,
+		let (mut flat_storage, render_context) = world.system_data::<(
 			WriteExpect<AssetStorage<doom::map::textures::Flat>>,
 			ReadExpect<RenderContext>,
 		)>();
-		let palette = palette_storage.get(&palette_handle).unwrap();
 		flat_storage.build_waiting(|image| {
-			let data: Vec<_> = image
-				.data
-				.into_iter()
-				.map(|pixel| {
-					if pixel.a == 0xFF {
-						palette[pixel.i as usize]
-					} else {
-						crate::doom::image::RGBAColor::default()
-					}
-				})
-				.collect();
-
 			// Create the image
 			let (image, _future) = ImmutableImage::from_iter(
-				data.as_bytes().iter().copied(),
+				image.data.as_bytes().iter().copied(),
 				Dimensions::Dim2d {
 					width: image.size[0] as u32,
 					height: image.size[1] as u32,
 				},
-				Format::R8G8B8A8Unorm,
+				Format::R8G8Unorm,
 				render_context.queues().graphics.clone(),
 			)?;
 
@@ -524,32 +1615,18 @@ fn load_map(name: &str, world: &mut World) -> anyhow::Result<()> {
 	}
 
 	{
-		let (palette_storage, mut wall_storage, render_context) = world.system_data::<(
-			ReadExpect<AssetStorage<doom::image::Palette>>,
+		let (mut wall_storage, render_context) = world.system_data::<(
 			WriteExpect<AssetStorage<doom::map::textures::Wall>>,
 			ReadExpect<RenderContext>,
 		)>();
-		let palette = palette_storage.get(&palette_handle).unwrap();
 		wall_storage.build_waiting(|image| {
-			let data: Vec<_> = image
-				.data
-				.into_iter()
-				.map(|pixel| {
-					if pixel.a == 0xFF {
-						palette[pixel.i as usize]
-					} else {
-						crate::doom::image::RGBAColor::default()
-					}
-				})
-				.collect();
-
 			let (image, _future) = ImmutableImage::from_iter(
-				data.as_bytes().iter().copied(),
+				image.data.as_bytes().iter().copied(),
 				Dimensions::Dim2d {
 					width: image.size[0] as u32,
 					height: image.size[1] as u32,
 				},
-				Format::R8G8B8A8Unorm,
+				Format::R8G8Unorm,
 				render_context.queues().graphics.clone(),
 			)?;
 
@@ -557,29 +1634,60 @@ fn load_map(name: &str, world: &mut World) -> anyhow::Result<()> {
 		});
 	}
 
-	log::info!("Spawning entities...");
-
-	// Create quadtree
-	let bbox = {
-		let map_storage = world.system_data::<ReadExpect<AssetStorage<doom::map::Map>>>();
-		let map = map_storage.get(&map_handle).unwrap();
-		map.bbox.clone()
-	};
-	world.insert(Quadtree::new(bbox));
+	Ok(())
+}
 
-	// Spawn map entities and things
+fn server_change_map(name: &str, world: &mut World) -> anyhow::Result<()> {
+	let start_time = Instant::now();
+	let map_handle = load_map(name, world)?;
 	let things = {
 		let loader = world.system_data::<WriteExpect<doom::wad::WadLoader>>();
 		doom::map::load::build_things(&loader.load(&format!("{}/+{}", name, 1))?)?
 	};
-	doom::map::spawn_map_entities(&world, &map_handle)?;
-	doom::map::spawn_things(things, &world, &map_handle)?;
 
-	// Spawn player
-	let entity = doom::map::spawn_player(&world)?;
+	// Only clear out the old map once the new one has loaded
+	world.delete_all();
+
+	// Spawn map entities and things
+	log::info!("Spawning entities...");
+	doom::map::spawn_map_entities(world, &map_handle)?;
+	doom::map::spawn_things(things, world, &map_handle)?;
+
+	// Players are spawned when the server next runs
+	world.fetch_mut::<doom::server::Server>().set_map(name);
+	{
+		let mut rules = world.fetch_mut::<doom::game::GameRules>();
+		rules.map_time = Duration::default();
+		rules.map_ended = false;
+	}
+
+	log::debug!(
+		"Loading took {} s",
+		(Instant::now() - start_time).as_secs_f32()
+	);
+
+	Ok(())
+}
+
+fn client_change_map(name: &str, world: &mut World) -> anyhow::Result<()> {
+	let start_time = Instant::now();
+	let map_handle = load_map(name, world)?;
+	build_assets(world)?;
+
+	world.delete_all();
 	world
-		.system_data::<WriteExpect<doom::client::Client>>()
-		.entity = Some(entity);
+		.fetch_mut::<doom::replication::SnapshotReceiver>()
+		.clear();
+	world.fetch_mut::<doom::client::Client>().entity = None;
+
+	// The server sends the things, but the map entities are needed locally
+	doom::map::spawn_map_entities(world, &map_handle)?;
+
+	if let Some(music) = doom::music::map_music(name) {
+		if let Err(err) = doom::music::play_music(world, &music) {
+			log::warn!("{:#}", err);
+		}
+	}
 
 	log::debug!(
 		"Loading took {} s",
@@ -1,71 +1,85 @@
+use crate::protocol::{read_data, write_data, SequencedPacket, TryRead};
+use anyhow::{bail, ensure};
 use byteorder::{NetworkEndian as NE, ReadBytesExt, WriteBytesExt};
+use crossbeam_channel::{Receiver, Sender, TryRecvError};
 use net2::UdpBuilder;
 use std::{
-	error::Error,
+	collections::{BTreeMap, HashMap, VecDeque},
 	fmt,
-	io::{self, Cursor, ErrorKind, Read, Write},
+	io::{self, Cursor, ErrorKind},
 	marker::PhantomData,
-	net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
-	rc::Rc,
-	sync::mpsc::{Receiver, Sender, TryRecvError},
+	net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, UdpSocket},
+	sync::Arc,
+	time::{Duration, Instant},
 };
-use crate::protocol::{SequencedPacket, TryRead};
 
+pub const DEFAULT_PORT: u16 = 5029;
+
+// Packets are kept below the typical internet MTU, so that they aren't fragmented along the way
+pub const MAX_PACKET_SIZE: usize = 1400;
 
 pub struct Socket {
 	v4: Option<UdpSocket>,
 	v6: Option<UdpSocket>,
-	sender: Sender<Vec<u8>>,
-	receiver: Receiver<Vec<u8>>,
+	local: Option<(Sender<Vec<u8>>, Receiver<Vec<u8>>)>,
 }
 
 impl Socket {
-	pub fn new(ipv4_addr: Ipv4Addr, ipv6_addr: Ipv6Addr, port: u16, sender: Sender<Vec<u8>>, receiver: Receiver<Vec<u8>>) -> anyhow::Result<Rc<Socket>> {
+	pub fn new(ipv4_addr: Ipv4Addr, ipv6_addr: Ipv6Addr, port: u16) -> anyhow::Result<Socket> {
 		let ipv4_addr_port = SocketAddrV4::new(ipv4_addr, port);
 		let v4 = bind_v4(ipv4_addr_port);
 
 		if let Err(ref err) = v4 {
-			debug!("could not bind IPv4 socket to {}: {}", ipv4_addr_port, err);
+			log::debug!("Could not bind IPv4 socket to {}: {}", ipv4_addr_port, err);
 		};
 
 		let ipv6_addr_port = SocketAddrV6::new(ipv6_addr, port, 0, 0);
 		let v6 = bind_v6(ipv6_addr_port);
 
 		if let Err(ref err) = v6 {
-			debug!("could not bind IPv6 socket to {}: {}", ipv6_addr_port, err);
+			log::debug!("Could not bind IPv6 socket to {}: {}", ipv6_addr_port, err);
 		};
 
 		if v4.is_err() && v6.is_err() {
-			Err(Box::from("both IPv4 and IPv6 bindings failed"))
-		} else {
-			Ok(Rc::new(Socket {
-				v4: v4.ok(),
-				v6: v6.ok(),
-				sender,
-				receiver,
-			}))
+			bail!("Both IPv4 and IPv6 bindings failed");
 		}
+
+		Ok(Socket {
+			v4: v4.ok(),
+			v6: v6.ok(),
+			local: None,
+		})
+	}
+
+	/// Creates a pair of sockets that are connected to each other through `Addr::Local`.
+	pub fn new_local_pair() -> (Socket, Socket) {
+		let (sender1, receiver1) = crossbeam_channel::unbounded();
+		let (sender2, receiver2) = crossbeam_channel::unbounded();
+
+		(
+			Socket {
+				v4: None,
+				v6: None,
+				local: Some((sender1, receiver2)),
+			},
+			Socket {
+				v4: None,
+				v6: None,
+				local: Some((sender2, receiver1)),
+			},
+		)
 	}
 
 	pub fn mode(&self) -> SocketMode {
 		let v4_addr = self.v4.as_ref().map(|s| s.local_addr().unwrap());
 		let v6_addr = self.v6.as_ref().map(|s| s.local_addr().unwrap());
 
-		if v4_addr.is_some() && v6_addr.is_some() {
-			SocketMode::DualStack(
-				if let SocketAddr::V4(s) = v4_addr.unwrap() { s } else { unreachable!() },
-				if let SocketAddr::V6(s) = v6_addr.unwrap() { s } else { unreachable!() },
-			)
-		} else if v4_addr.is_some() {
-			SocketMode::IPv4(
-				if let SocketAddr::V4(s) = v4_addr.unwrap() { s } else { unreachable!() },
-			)
-		} else if v6_addr.is_some() {
-			SocketMode::IPv6(
-				if let SocketAddr::V6(s) = v6_addr.unwrap() { s } else { unreachable!() },
-			)
-		} else {
-			unreachable!()
+		match (v4_addr, v6_addr) {
+			(Some(SocketAddr::V4(v4)), Some(SocketAddr::V6(v6))) => SocketMode::DualStack(v4, v6),
+			(Some(SocketAddr::V4(v4)), None) => SocketMode::IPv4(v4),
+			(None, Some(SocketAddr::V6(v6))) => SocketMode::IPv6(v6),
+			(None, None) => SocketMode::Local,
+			_ => unreachable!(),
 		}
 	}
 
@@ -73,21 +87,22 @@ impl Socket {
 		let v4 = self.v4.is_some();
 		let v6 = self.v6.is_some();
 
-		return move |&addr| -> bool {
-			addr.is_ipv4() && v4 || addr.is_ipv6() && v6
-		}
+		move |&addr| -> bool { addr.is_ipv4() && v4 || addr.is_ipv6() && v6 }
 	}
 
 	pub fn send_to(&self, packet: Vec<u8>, addr: Addr) {
-		if let Addr::Local = addr {
-			self.sender.send(packet).ok();
-			return;
-		}
-
 		let addr: SocketAddr = match addr {
+			Addr::Local => {
+				if let Some((sender, _)) = &self.local {
+					sender.send(packet).ok();
+				} else {
+					log::error!("Socket does not support local addresses");
+				}
+
+				return;
+			}
 			Addr::V4(addr) => addr.into(),
 			Addr::V6(addr) => addr.into(),
-			_ => unreachable!(),
 		};
 
 		let socket = match addr {
@@ -97,11 +112,11 @@ impl Socket {
 
 		if let Some(socket) = socket {
 			if let Err(err) = socket.send_to(packet.as_slice(), addr) {
-				error!("could not send packet to {}: {}", addr, err);
+				log::error!("Could not send packet to {}: {}", addr, err);
 			}
 		} else {
-			panic!(
-				"socket does not support {} addresses",
+			log::error!(
+				"Socket does not support {} addresses",
 				match addr {
 					SocketAddr::V4(_) => "IPv4",
 					SocketAddr::V6(_) => "IPv6",
@@ -111,34 +126,38 @@ impl Socket {
 	}
 
 	pub fn next(&self) -> Option<(Vec<u8>, Addr)> {
-		// Try the local mpsc channel first
-		match self.receiver.try_recv() {
-			Ok(buf) => return Some((buf, Addr::Local)),
-			Err(TryRecvError::Empty) => (),
-			Err(TryRecvError::Disconnected) => panic!("Socket mpsc receiver disconnected"),
+		// Try the local channel first
+		if let Some((_, receiver)) = &self.local {
+			match receiver.try_recv() {
+				Ok(buf) => return Some((buf, Addr::Local)),
+				Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => (),
+			}
 		}
 
 		let mut buf = vec![0u8; 8192];
 
 		// Try reading from available sockets, first from the IPv6 socket,
 		// then from the IPv4 socket.
-		for socket in [self.v6.as_ref(), self.v4.as_ref()].iter().filter_map(|s| *s) {
+		for socket in [self.v6.as_ref(), self.v4.as_ref()]
+			.iter()
+			.filter_map(|s| *s)
+		{
 			match socket.recv_from(&mut buf) {
 				Ok((bytes_read, addr)) => {
 					if bytes_read == buf.len() {
 						// Oversized packet, ignore it.
-						warn!("received an oversized packet from {}", addr);
+						log::warn!("Received an oversized packet from {}", addr);
 					} else {
-						// We got a packet, parse it, and return if valid.
+						// We got a packet, return it.
 						buf.truncate(bytes_read);
 						return Some((buf, addr.into()));
 					}
-				},
+				}
 				Err(err) => {
 					if err.kind() != ErrorKind::WouldBlock {
 						// Got an error.
-						error!(
-							"could not receive {} packet: {}",
+						log::error!(
+							"Could not receive {} packet: {}",
 							match socket.local_addr().unwrap() {
 								SocketAddr::V4(_) => "IPv4",
 								SocketAddr::V6(_) => "IPv6",
@@ -146,7 +165,7 @@ impl Socket {
 							err,
 						);
 					}
-				},
+				}
 			}
 		}
 
@@ -161,6 +180,17 @@ pub enum Addr {
 	V6(SocketAddrV6),
 }
 
+impl Addr {
+	/// The IP address of the remote host, `None` if it's local to the process.
+	pub fn ip(&self) -> Option<IpAddr> {
+		match *self {
+			Addr::Local => None,
+			Addr::V4(addr) => Some(IpAddr::V4(*addr.ip())),
+			Addr::V6(addr) => Some(IpAddr::V6(*addr.ip())),
+		}
+	}
+}
+
 impl From<SocketAddr> for Addr {
 	fn from(addr: SocketAddr) -> Addr {
 		match addr {
@@ -184,6 +214,7 @@ pub enum SocketMode {
 	DualStack(SocketAddrV4, SocketAddrV6),
 	IPv4(SocketAddrV4),
 	IPv6(SocketAddrV6),
+	Local,
 }
 
 impl fmt::Display for SocketMode {
@@ -192,6 +223,7 @@ impl fmt::Display for SocketMode {
 			SocketMode::DualStack(v4, v6) => write!(f, "dual-stack ({}, {})", v4, v6),
 			SocketMode::IPv4(v4) => write!(f, "IPv4-only ({})", v4),
 			SocketMode::IPv6(v6) => write!(f, "IPv6-only ({})", v6),
+			SocketMode::Local => write!(f, "local-only"),
 		}
 	}
 }
@@ -213,22 +245,116 @@ fn bind_v6(addr_port: SocketAddrV6) -> Result<UdpSocket, io::Error> {
 	Ok(socket)
 }
 
+// Sequence, ack and ack bits
+const PACKET_HEADER_SIZE: usize = 12;
+
+// Largest amount of message data that fits in one block of a packet,
+// leaving room for the packet header and the largest block header
+const MAX_BLOCK_DATA: usize = MAX_PACKET_SIZE - PACKET_HEADER_SIZE - 10;
+
+const BLOCK_RELIABLE: u8 = 1;
+const BLOCK_UNRELIABLE: u8 = 2;
+const BLOCK_FRAGMENT: u8 = 3;
+
+// Number of earlier packets that are acknowledged along with the latest one
+const ACK_WINDOW: u32 = 32;
+
+// Reliable blocks can only be this far ahead of the oldest one not yet delivered, so that the
+// receiver doesn't have to hold on to an unbounded number of them
+const RELIABLE_WINDOW: u32 = 1024;
+
+const MIN_RESEND_DELAY: Duration = Duration::from_millis(100);
+const FRAGMENT_TIMEOUT: Duration = Duration::from_secs(1);
+const LOSS_HISTORY_LEN: usize = 100;
+
+/// Statistics about the packets sent and received on a channel.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ChannelStats {
+	pub rtt: Duration,
+	pub packet_loss: f32,
+	pub packets_sent: u64,
+	pub packets_received: u64,
+}
+
+struct ReliableOut {
+	id: u32,
+	more: bool,
+	data: Vec<u8>,
+	last_sent: Option<Instant>,
+}
+
+struct SentPacket {
+	sequence: u32,
+	time: Instant,
+	reliable_ids: Vec<u32>,
+}
+
+struct FragmentsIn {
+	parts: Vec<Option<Vec<u8>>>,
+	remaining: usize,
+	first_received: Instant,
+}
+
+/// A connection to a single remote address.
+///
+/// Messages can be sent reliably, in which case they are resent until acknowledged and are
+/// received in the order they were sent. Unreliable messages are sent once, and are dropped
+/// by the receiver if a newer packet has already arrived. Messages too large to fit in one
+/// packet are split into fragments.
 pub struct SequencedChannel<S, R> {
 	addr: Addr,
-	socket: Rc<Socket>,
+	socket: Arc<Socket>,
+
 	in_sequence: u32,
+	in_ack_bits: u32,
+	ack_pending: bool,
 	out_sequence: u32,
+	out_ack: u32,
+	sent_packets: VecDeque<SentPacket>,
+
+	reliable_in: BTreeMap<u32, (bool, Vec<u8>)>,
+	reliable_in_next: u32,
+	reliable_in_partial: Vec<u8>,
+	reliable_out: VecDeque<ReliableOut>,
+	reliable_out_next: u32,
+
+	unreliable_out: Vec<Vec<u8>>,
+	fragments_in: HashMap<u16, FragmentsIn>,
+	fragment_out_next: u16,
+
+	loss_history: VecDeque<bool>,
+	stats: ChannelStats,
+
 	_phantom1: PhantomData<S>,
 	_phantom2: PhantomData<R>,
 }
 
 impl<S: Into<Vec<u8>>, R: TryRead<R>> SequencedChannel<S, R> {
-	pub fn new(socket: Rc<Socket>, addr: Addr) -> SequencedChannel<S, R> {
+	pub fn new(socket: Arc<Socket>, addr: Addr) -> SequencedChannel<S, R> {
 		SequencedChannel {
 			addr,
 			socket,
+
 			in_sequence: 0,
+			in_ack_bits: 0,
+			ack_pending: false,
 			out_sequence: 1,
+			out_ack: 0,
+			sent_packets: VecDeque::new(),
+
+			reliable_in: BTreeMap::new(),
+			reliable_in_next: 0,
+			reliable_in_partial: Vec::new(),
+			reliable_out: VecDeque::new(),
+			reliable_out_next: 0,
+
+			unreliable_out: Vec::new(),
+			fragments_in: HashMap::new(),
+			fragment_out_next: 0,
+
+			loss_history: VecDeque::new(),
+			stats: ChannelStats::default(),
+
 			_phantom1: PhantomData,
 			_phantom2: PhantomData,
 		}
@@ -238,47 +364,316 @@ impl<S: Into<Vec<u8>>, R: TryRead<R>> SequencedChannel<S, R> {
 		self.addr
 	}
 
-	pub fn send(&mut self, messages: Vec<S>) {
-		let data = messages.into_iter().map(|x| x.into()).collect::<Vec<Vec<u8>>>().concat();
+	pub fn stats(&self) -> ChannelStats {
+		self.stats
+	}
+
+	/// Queues a message that is resent until the other side acknowledges it.
+	pub fn send_reliable(&mut self, message: S) {
+		let data: Vec<u8> = message.into();
+		let mut chunks = data.chunks(MAX_BLOCK_DATA).peekable();
+
+		while let Some(chunk) = chunks.next() {
+			self.reliable_out.push_back(ReliableOut {
+				id: self.reliable_out_next,
+				more: chunks.peek().is_some(),
+				data: chunk.to_owned(),
+				last_sent: None,
+			});
+			self.reliable_out_next += 1;
+		}
+	}
+
+	/// Queues a message that is sent only once.
+	pub fn send_unreliable(&mut self, message: S) {
+		self.unreliable_out.push(message.into());
+	}
+
+	/// Sends all queued messages, packing them into as few packets as possible.
+	pub fn flush(&mut self) {
+		let now = Instant::now();
+		let resend_delay = std::cmp::max(self.stats.rtt * 2, MIN_RESEND_DELAY);
+		let mut data = Vec::new();
+		let mut reliable_ids = Vec::new();
+
+		// Reliable messages that were not sent yet, or not acknowledged in time
+		let window_end = self
+			.reliable_out
+			.front()
+			.map_or(0, |message| message.id + RELIABLE_WINDOW);
+
+		for i in 0..self.reliable_out.len() {
+			let message = &mut self.reliable_out[i];
+
+			if message.id >= window_end {
+				break;
+			}
+
+			if let Some(last_sent) = message.last_sent {
+				if now - last_sent < resend_delay {
+					continue;
+				}
+			}
+
+			message.last_sent = Some(now);
+
+			let mut block = Cursor::new(Vec::with_capacity(message.data.len() + 10));
+			block.write_u8(BLOCK_RELIABLE).unwrap();
+			block.write_u32::<NE>(message.id).unwrap();
+			block.write_u8(message.more as u8).unwrap();
+			write_data(&mut block, &message.data);
+			let id = message.id;
+
+			self.add_block(&mut data, &mut reliable_ids, block.into_inner());
+			reliable_ids.push(id);
+		}
+
+		// Unreliable messages
+		for message in std::mem::take(&mut self.unreliable_out) {
+			if message.len() <= MAX_BLOCK_DATA {
+				let mut block = Cursor::new(Vec::with_capacity(message.len() + 5));
+				block.write_u8(BLOCK_UNRELIABLE).unwrap();
+				write_data(&mut block, &message);
+				self.add_block(&mut data, &mut reliable_ids, block.into_inner());
+				continue;
+			}
+
+			let count = (message.len() + MAX_BLOCK_DATA - 1) / MAX_BLOCK_DATA;
+
+			if count > u8::MAX as usize {
+				log::error!(
+					"Could not send message of {} bytes to {}, it is too large",
+					message.len(),
+					self.addr
+				);
+				continue;
+			}
+
+			let group = self.fragment_out_next;
+			self.fragment_out_next = self.fragment_out_next.wrapping_add(1);
+
+			for (index, chunk) in message.chunks(MAX_BLOCK_DATA).enumerate() {
+				let mut block = Cursor::new(Vec::with_capacity(chunk.len() + 9));
+				block.write_u8(BLOCK_FRAGMENT).unwrap();
+				block.write_u16::<NE>(group).unwrap();
+				block.write_u8(index as u8).unwrap();
+				block.write_u8(count as u8).unwrap();
+				write_data(&mut block, chunk);
+				self.add_block(&mut data, &mut reliable_ids, block.into_inner());
+			}
+		}
+
+		// Send a packet even without data, so that the other side gets its acknowledgements
+		if !data.is_empty() || self.ack_pending {
+			self.send_packet(data, reliable_ids);
+		}
+	}
+
+	fn add_block(&mut self, data: &mut Vec<u8>, reliable_ids: &mut Vec<u32>, block: Vec<u8>) {
+		if !data.is_empty() && data.len() + block.len() > MAX_PACKET_SIZE - PACKET_HEADER_SIZE {
+			self.send_packet(std::mem::take(data), std::mem::take(reliable_ids));
+		}
+
+		data.extend(block);
+	}
 
+	fn send_packet(&mut self, data: Vec<u8>, reliable_ids: Vec<u32>) {
 		let packet = SequencedPacket {
 			sequence: self.out_sequence,
+			ack: self.in_sequence,
+			ack_bits: self.in_ack_bits,
 			data,
 		};
 
 		self.socket.send_to(packet.into(), self.addr);
+		self.sent_packets.push_back(SentPacket {
+			sequence: self.out_sequence,
+			time: Instant::now(),
+			reliable_ids,
+		});
 		self.out_sequence += 1;
+		self.ack_pending = false;
+		self.stats.packets_sent += 1;
 	}
 
-	pub fn process(&mut self, packet: SequencedPacket) -> Option<Vec<R>> {
-		if packet.sequence < self.in_sequence {
-			return None;
-		}
+	pub fn process(&mut self, packet: SequencedPacket) -> Vec<R> {
+		let now = Instant::now();
+		self.process_acks(packet.ack, packet.ack_bits, now);
+
+		// Update the sequence and the acknowledgements to send back
+		let is_newest = if packet.sequence > self.in_sequence {
+			let shift = packet.sequence - self.in_sequence;
+
+			self.in_ack_bits = if shift > ACK_WINDOW {
+				0
+			} else if shift == ACK_WINDOW {
+				1 << (ACK_WINDOW - 1)
+			} else {
+				(self.in_ack_bits << shift) | (1 << (shift - 1))
+			};
+			self.in_sequence = packet.sequence;
+			true
+		} else {
+			let age = self.in_sequence - packet.sequence;
+
+			if age == 0 || age > ACK_WINDOW || self.in_ack_bits & (1 << (age - 1)) != 0 {
+				// Duplicate or too old to acknowledge
+				return Vec::new();
+			}
+
+			self.in_ack_bits |= 1 << (age - 1);
+			false
+		};
+
+		self.ack_pending = true;
+		self.stats.packets_received += 1;
 
 		let mut reader = Cursor::new(packet.data);
 		let mut messages = Vec::new();
 
-		while reader.position() < reader.get_ref().len() as u64 {
-			let message = match R::try_read(&mut reader) {
-				Ok(message) => message,
-				Err(err) => {
-					warn!("received a malformed packet from {}", self.addr);
-					return None;
-				},
-			};
+		if let Err(err) = self.read_blocks(&mut reader, is_newest, &mut messages, now) {
+			log::warn!("Received a malformed packet from {}: {}", self.addr, err);
+		}
 
-			messages.push(message);
+		// Drop fragments of messages that will never be completed
+		self.fragments_in
+			.retain(|_, fragments| now - fragments.first_received < FRAGMENT_TIMEOUT);
+
+		// Deliver reliable messages in order, ahead of the unreliable ones
+		let mut reliable_messages = Vec::new();
+
+		while let Some((more, data)) = self.reliable_in.remove(&self.reliable_in_next) {
+			self.reliable_in_next += 1;
+			self.reliable_in_partial.extend(data);
+
+			if !more {
+				let data = std::mem::take(&mut self.reliable_in_partial);
+
+				match R::try_read(&mut Cursor::new(data)) {
+					Ok(message) => reliable_messages.push(message),
+					Err(err) => {
+						log::warn!("Received a malformed message from {}: {}", self.addr, err)
+					}
+				}
+			}
 		}
 
-		self.in_sequence = packet.sequence;
-		Some(messages)
+		reliable_messages.extend(messages);
+		reliable_messages
 	}
 
-	pub fn in_sequence(&self) -> u32 {
-		self.in_sequence
+	fn read_blocks(
+		&mut self,
+		reader: &mut Cursor<Vec<u8>>,
+		is_newest: bool,
+		messages: &mut Vec<R>,
+		now: Instant,
+	) -> anyhow::Result<()> {
+		while reader.position() < reader.get_ref().len() as u64 {
+			let block_type = reader.read_u8()?;
+
+			match block_type {
+				BLOCK_RELIABLE => {
+					let id = reader.read_u32::<NE>()?;
+					let more = reader.read_u8()? != 0;
+					let data = read_data(reader)?;
+
+					if id >= self.reliable_in_next && id - self.reliable_in_next < RELIABLE_WINDOW {
+						self.reliable_in.insert(id, (more, data));
+					}
+				}
+				BLOCK_UNRELIABLE => {
+					let data = read_data(reader)?;
+
+					// Only the newest unreliable data is of interest
+					if is_newest {
+						messages.push(R::try_read(&mut Cursor::new(data))?);
+					}
+				}
+				BLOCK_FRAGMENT => {
+					let group = reader.read_u16::<NE>()?;
+					let index = reader.read_u8()? as usize;
+					let count = reader.read_u8()? as usize;
+					let data = read_data(reader)?;
+					ensure!(index < count, "fragment index {} out of range", index);
+
+					let fragments = self
+						.fragments_in
+						.entry(group)
+						.or_insert_with(|| FragmentsIn {
+							parts: vec![None; count],
+							remaining: count,
+							first_received: now,
+						});
+					ensure!(
+						fragments.parts.len() == count,
+						"fragment count does not match"
+					);
+
+					if fragments.parts[index].is_none() {
+						fragments.parts[index] = Some(data);
+						fragments.remaining -= 1;
+					}
+
+					if fragments.remaining == 0 {
+						let fragments = self.fragments_in.remove(&group).unwrap();
+						let data: Vec<u8> =
+							fragments.parts.into_iter().flatten().flatten().collect();
+						messages.push(R::try_read(&mut Cursor::new(data))?);
+					}
+				}
+				_ => bail!("unknown block type {}", block_type),
+			}
+		}
+
+		Ok(())
 	}
 
-	pub fn out_sequence(&self) -> u32 {
-		self.out_sequence
+	fn process_acks(&mut self, ack: u32, ack_bits: u32, now: Instant) {
+		if ack > self.out_ack {
+			self.out_ack = ack;
+		}
+
+		let reliable_out = &mut self.reliable_out;
+		let loss_history = &mut self.loss_history;
+		let stats = &mut self.stats;
+		let out_ack = self.out_ack;
+
+		self.sent_packets.retain(|sent| {
+			let acked = sent.sequence == ack
+				|| sent.sequence < ack
+					&& ack - sent.sequence <= ACK_WINDOW
+					&& ack_bits & (1 << (ack - sent.sequence - 1)) != 0;
+
+			if acked {
+				// Smoothed round-trip time
+				let rtt = now - sent.time;
+
+				if stats.rtt == Duration::default() {
+					stats.rtt = rtt;
+				} else {
+					stats.rtt = stats.rtt.mul_f32(0.875) + rtt.mul_f32(0.125);
+				}
+
+				reliable_out.retain(|message| !sent.reliable_ids.contains(&message.id));
+				loss_history.push_back(false);
+				false
+			} else if sent.sequence + ACK_WINDOW < out_ack {
+				// Can no longer be acknowledged, so it was lost
+				loss_history.push_back(true);
+				false
+			} else {
+				true
+			}
+		});
+
+		while self.loss_history.len() > LOSS_HISTORY_LEN {
+			self.loss_history.pop_front();
+		}
+
+		if !self.loss_history.is_empty() {
+			let lost = self.loss_history.iter().filter(|x| **x).count();
+			self.stats.packet_loss = lost as f32 / self.loss_history.len() as f32;
+		}
 	}
 }
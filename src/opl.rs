@@ -0,0 +1,469 @@
+use std::f64::consts::PI;
+
+// Frequencies on the real chip are relative to its own sample rate
+const CHIP_RATE: f64 = 49716.0;
+
+const MULTIPLIERS: [f64; 16] = [
+	0.5, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0, 10.0, 10.0, 12.0, 12.0, 15.0, 15.0,
+];
+
+// Key scale level attenuation in dB for block 7, by the top 4 bits of the frequency number.
+// Each lower block has 3 dB less.
+const KSL_TABLE: [f64; 16] = [
+	0.0, 9.0, 12.0, 13.875, 15.0, 16.125, 16.875, 17.625, 18.0, 18.75, 19.125, 19.5, 19.875, 20.25,
+	20.625, 21.0,
+];
+const KSL_SCALE: [f64; 4] = [0.0, 1.0, 0.5, 2.0];
+
+// Operator number for each operator register offset, the gaps are unused
+const OPERATOR_SLOTS: [Option<usize>; 0x16] = [
+	Some(0),
+	Some(1),
+	Some(2),
+	Some(3),
+	Some(4),
+	Some(5),
+	None,
+	None,
+	Some(6),
+	Some(7),
+	Some(8),
+	Some(9),
+	Some(10),
+	Some(11),
+	None,
+	None,
+	Some(12),
+	Some(13),
+	Some(14),
+	Some(15),
+	Some(16),
+	Some(17),
+];
+
+// Register offset of the first operator of each channel, the second is 3 further
+pub const CHANNEL_OPERATORS: [u16; 9] = [0x00, 0x01, 0x02, 0x08, 0x09, 0x0A, 0x10, 0x11, 0x12];
+
+const MAX_ATTENUATION: f64 = 96.0;
+const TREMOLO_RATE: f64 = 3.7;
+const VIBRATO_RATE: f64 = 6.1;
+
+// Phase modulation in cycles, at full output of the modulating operator
+const MODULATION_DEPTH: f64 = 4.0;
+
+const OUTPUT_SCALE: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum EnvelopeStage {
+	Attack,
+	Decay,
+	Sustain,
+	Release,
+	Off,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Operator {
+	tremolo: bool,
+	vibrato: bool,
+	sustained: bool,
+	key_scale_rate: bool,
+	multiplier: u8,
+	key_scale_level: u8,
+	total_level: u8,
+	attack_rate: u8,
+	decay_rate: u8,
+	sustain_level: u8,
+	release_rate: u8,
+	waveform: u8,
+
+	// Position in the waveform, in cycles
+	phase: f64,
+	// Envelope attenuation in dB
+	envelope: f64,
+	stage: EnvelopeStage,
+	// The last two outputs, for feedback
+	output: [f64; 2],
+}
+
+impl Default for Operator {
+	fn default() -> Operator {
+		Operator {
+			tremolo: false,
+			vibrato: false,
+			sustained: false,
+			key_scale_rate: false,
+			multiplier: 0,
+			key_scale_level: 0,
+			total_level: 0,
+			attack_rate: 0,
+			decay_rate: 0,
+			sustain_level: 0,
+			release_rate: 0,
+			waveform: 0,
+			phase: 0.0,
+			envelope: MAX_ATTENUATION,
+			stage: EnvelopeStage::Off,
+			output: [0.0; 2],
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Channel {
+	frequency_number: u16,
+	block: u8,
+	key_on: bool,
+	feedback: u8,
+	additive: bool,
+	left: bool,
+	right: bool,
+}
+
+/// Emulates the Yamaha OPL2 and OPL3 FM synthesis chips, in two-operator mode.
+/// Registers are written as on the chip, with the second OPL3 register bank at 0x100.
+pub struct Opl {
+	channels: [Channel; 18],
+	operators: [Operator; 36],
+	opl3: bool,
+	sample_rate: f64,
+	tremolo_deep: bool,
+	tremolo_phase: f64,
+	vibrato_deep: bool,
+	vibrato_phase: f64,
+	waveform_select: bool,
+}
+
+impl Opl {
+	pub fn new(sample_rate: u32) -> Opl {
+		Opl {
+			channels: [Channel::default(); 18],
+			operators: [Operator::default(); 36],
+			opl3: false,
+			sample_rate: sample_rate as f64,
+			tremolo_deep: false,
+			tremolo_phase: 0.0,
+			vibrato_deep: false,
+			vibrato_phase: 0.0,
+			waveform_select: false,
+		}
+	}
+
+	pub fn write(&mut self, register: u16, value: u8) {
+		let bank = (register >> 8) as usize & 1;
+		let register = register as u8;
+
+		match register {
+			0x01 if bank == 0 => self.waveform_select = value & 0x20 != 0,
+			0x05 if bank == 1 => self.opl3 = value & 0x01 != 0,
+			0xBD if bank == 0 => {
+				self.tremolo_deep = value & 0x80 != 0;
+				self.vibrato_deep = value & 0x40 != 0;
+			}
+			0x20..=0x35 | 0x40..=0x55 | 0x60..=0x75 | 0x80..=0x95 | 0xE0..=0xF5 => {
+				let slot = match OPERATOR_SLOTS[(register & 0x1F) as usize] {
+					Some(slot) => slot,
+					None => return,
+				};
+				let operator = &mut self.operators[bank * 18 + slot];
+
+				match register & 0xE0 {
+					0x20 => {
+						operator.tremolo = value & 0x80 != 0;
+						operator.vibrato = value & 0x40 != 0;
+						operator.sustained = value & 0x20 != 0;
+						operator.key_scale_rate = value & 0x10 != 0;
+						operator.multiplier = value & 0x0F;
+					}
+					0x40 => {
+						operator.key_scale_level = value >> 6;
+						operator.total_level = value & 0x3F;
+					}
+					0x60 => {
+						operator.attack_rate = value >> 4;
+						operator.decay_rate = value & 0x0F;
+					}
+					0x80 => {
+						operator.sustain_level = value >> 4;
+						operator.release_rate = value & 0x0F;
+					}
+					_ => operator.waveform = value & 0x07,
+				}
+			}
+			0xA0..=0xA8 => {
+				let channel = &mut self.channels[bank * 9 + (register & 0x0F) as usize];
+				channel.frequency_number = (channel.frequency_number & 0x300) | value as u16;
+			}
+			0xB0..=0xB8 => {
+				let index = bank * 9 + (register & 0x0F) as usize;
+				let channel = &mut self.channels[index];
+				let key_on = value & 0x20 != 0;
+				let was_on = channel.key_on;
+				channel.frequency_number =
+					(channel.frequency_number & 0xFF) | ((value as u16 & 0x03) << 8);
+				channel.block = (value >> 2) & 0x07;
+				channel.key_on = key_on;
+
+				for operator in self.channel_operators(index).iter() {
+					let operator = &mut self.operators[*operator];
+
+					if key_on && !was_on {
+						operator.phase = 0.0;
+						operator.stage = EnvelopeStage::Attack;
+					} else if !key_on && was_on && operator.stage != EnvelopeStage::Off {
+						operator.stage = EnvelopeStage::Release;
+					}
+				}
+			}
+			0xC0..=0xC8 => {
+				let channel = &mut self.channels[bank * 9 + (register & 0x0F) as usize];
+				channel.feedback = (value >> 1) & 0x07;
+				channel.additive = value & 0x01 != 0;
+				channel.left = value & 0x10 != 0;
+				channel.right = value & 0x20 != 0;
+			}
+			_ => {}
+		}
+	}
+
+	/// Fills the buffer with interleaved stereo samples.
+	pub fn generate(&mut self, buffer: &mut [f32]) {
+		let dt = 1.0 / self.sample_rate;
+
+		for frame in buffer.chunks_exact_mut(2) {
+			let tremolo_depth = if self.tremolo_deep { 4.8 } else { 1.0 };
+			let tremolo = tremolo_depth * 0.5 * (1.0 - (2.0 * PI * self.tremolo_phase).cos());
+			let vibrato_cents = if self.vibrato_deep { 14.0 } else { 7.0 };
+			let vibrato = (vibrato_cents * (2.0 * PI * self.vibrato_phase).sin() / 1200.0).exp2();
+			self.tremolo_phase = (self.tremolo_phase + TREMOLO_RATE * dt).fract();
+			self.vibrato_phase = (self.vibrato_phase + VIBRATO_RATE * dt).fract();
+
+			let mut output = [0.0f64; 2];
+			let channel_count = if self.opl3 { 18 } else { 9 };
+
+			for index in 0..channel_count {
+				let channel = self.channels[index];
+				let [modulator, carrier] = self.channel_operators(index);
+
+				if self.operators[modulator].stage == EnvelopeStage::Off
+					&& self.operators[carrier].stage == EnvelopeStage::Off
+				{
+					continue;
+				}
+
+				let feedback = if channel.feedback == 0 {
+					0.0
+				} else {
+					let previous = self.operators[modulator].output;
+					(previous[0] + previous[1]) * 0.5 * (channel.feedback as f64 - 6.0).exp2()
+				};
+
+				let modulator_output =
+					self.run_operator(modulator, &channel, feedback, tremolo, vibrato, dt);
+
+				let value = if channel.additive {
+					modulator_output
+						+ self.run_operator(carrier, &channel, 0.0, tremolo, vibrato, dt)
+				} else {
+					self.run_operator(
+						carrier,
+						&channel,
+						modulator_output * MODULATION_DEPTH,
+						tremolo,
+						vibrato,
+						dt,
+					)
+				};
+
+				// OPL2 has no stereo, so it goes to both sides
+				if !self.opl3 || channel.left {
+					output[0] += value;
+				}
+
+				if !self.opl3 || channel.right {
+					output[1] += value;
+				}
+			}
+
+			frame[0] = output[0] as f32 * OUTPUT_SCALE;
+			frame[1] = output[1] as f32 * OUTPUT_SCALE;
+		}
+	}
+
+	fn channel_operators(&self, channel: usize) -> [usize; 2] {
+		let bank = channel / 9;
+		let channel = channel % 9;
+		let first = bank * 18 + (channel / 3) * 6 + channel % 3;
+		[first, first + 3]
+	}
+
+	/// Advances the operator by one sample and returns its output.
+	fn run_operator(
+		&mut self,
+		index: usize,
+		channel: &Channel,
+		modulation: f64,
+		tremolo: f64,
+		vibrato: f64,
+		dt: f64,
+	) -> f64 {
+		let waveform = if self.opl3 {
+			self.operators[index].waveform
+		} else if self.waveform_select {
+			self.operators[index].waveform & 0x03
+		} else {
+			0
+		};
+		let operator = &mut self.operators[index];
+
+		// Rates are faster for higher notes
+		let key_code = (channel.block << 1) | ((channel.frequency_number >> 9) & 1) as u8;
+		let rate_offset = if operator.key_scale_rate {
+			key_code
+		} else {
+			key_code >> 2
+		};
+		let effective_rate = |rate: u8| {
+			if rate == 0 {
+				None
+			} else {
+				Some((rate * 4 + rate_offset).min(63) as f64)
+			}
+		};
+
+		// Times in seconds to go through the whole 96 dB range, from the datasheet
+		let decay_speed = |rate: u8| {
+			effective_rate(rate).map_or(0.0, |rate| {
+				MAX_ATTENUATION / (39.28 * (-(rate - 4.0) / 4.0).exp2()) * dt
+			})
+		};
+
+		match operator.stage {
+			EnvelopeStage::Attack => match effective_rate(operator.attack_rate) {
+				Some(rate) if rate >= 60.0 => operator.envelope = 0.0,
+				Some(rate) => {
+					// Attack is exponential, reaching 0.1 dB at the end
+					let time = 2.826 * (-(rate - 4.0) / 4.0).exp2();
+					operator.envelope *= (-(MAX_ATTENUATION * 10.0).ln() / time * dt).exp();
+
+					if operator.envelope < 0.1 {
+						operator.envelope = 0.0;
+					}
+				}
+				None => {}
+			},
+			EnvelopeStage::Decay => {
+				operator.envelope += decay_speed(operator.decay_rate);
+			}
+			EnvelopeStage::Sustain => {
+				if !operator.sustained {
+					operator.envelope += decay_speed(operator.release_rate);
+				}
+			}
+			EnvelopeStage::Release => {
+				operator.envelope += decay_speed(operator.release_rate);
+			}
+			EnvelopeStage::Off => {}
+		}
+
+		let sustain_level = if operator.sustain_level == 15 {
+			93.0
+		} else {
+			operator.sustain_level as f64 * 3.0
+		};
+
+		match operator.stage {
+			EnvelopeStage::Attack if operator.envelope <= 0.0 => {
+				operator.stage = EnvelopeStage::Decay;
+			}
+			EnvelopeStage::Decay if operator.envelope >= sustain_level => {
+				operator.envelope = sustain_level;
+				operator.stage = EnvelopeStage::Sustain;
+			}
+			_ => {}
+		}
+
+		if operator.envelope >= MAX_ATTENUATION {
+			operator.envelope = MAX_ATTENUATION;
+			operator.stage = EnvelopeStage::Off;
+		}
+
+		// Total attenuation
+		let key_scale_level = (KSL_TABLE[(channel.frequency_number >> 6) as usize]
+			- 3.0 * (7 - channel.block) as f64)
+			.max(0.0) * KSL_SCALE[operator.key_scale_level as usize];
+		let mut attenuation =
+			operator.envelope + operator.total_level as f64 * 0.75 + key_scale_level;
+
+		if operator.tremolo {
+			attenuation += tremolo;
+		}
+
+		let output = if attenuation >= MAX_ATTENUATION {
+			0.0
+		} else {
+			waveform_value(waveform, (operator.phase + modulation).rem_euclid(1.0))
+				* 10f64.powf(-attenuation / 20.0)
+		};
+
+		// Advance the phase
+		let mut frequency =
+			channel.frequency_number as f64 * (channel.block as f64).exp2() * CHIP_RATE
+				/ (1 << 20) as f64
+				* MULTIPLIERS[operator.multiplier as usize];
+
+		if operator.vibrato {
+			frequency *= vibrato;
+		}
+
+		operator.phase = (operator.phase + frequency * dt).fract();
+		operator.output = [operator.output[1], output];
+		output
+	}
+}
+
+/// The value of a waveform, with the phase in cycles between 0 and 1.
+fn waveform_value(waveform: u8, phase: f64) -> f64 {
+	let sine = (2.0 * PI * phase).sin();
+
+	match waveform {
+		0 => sine,
+		1 => sine.max(0.0),
+		2 => sine.abs(),
+		3 => {
+			if phase.rem_euclid(0.5) < 0.25 {
+				sine.abs()
+			} else {
+				0.0
+			}
+		}
+		4 => {
+			if phase < 0.5 {
+				(4.0 * PI * phase).sin()
+			} else {
+				0.0
+			}
+		}
+		5 => {
+			if phase < 0.5 {
+				(4.0 * PI * phase).sin().abs()
+			} else {
+				0.0
+			}
+		}
+		6 => {
+			if phase < 0.5 {
+				1.0
+			} else {
+				-1.0
+			}
+		}
+		_ => {
+			if phase < 0.5 {
+				(-phase * 16.0).exp2()
+			} else {
+				-(-(1.0 - phase) * 16.0).exp2()
+			}
+		}
+	}
+}
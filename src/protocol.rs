@@ -1,13 +1,10 @@
-use anyhow::ensure;
+use crate::doom::input::UserCommand;
+use anyhow::{bail, ensure};
 use byteorder::{NetworkEndian as NE, ReadBytesExt, WriteBytesExt};
 use std::{
 	convert::TryFrom,
-	error::Error,
 	io::{Cursor, Read, Write},
-	str,
 };
-use crate::commands;
-
 
 #[derive(Debug)]
 pub enum Packet<T> {
@@ -50,11 +47,11 @@ impl<T: Into<Vec<u8>>> From<Packet<T>> for Vec<u8> {
 				writer.write_u32::<NE>(0xFFFFFFFF).unwrap();
 
 				for message in messages {
-					writer.write(&message.into()).unwrap();
+					writer.write_all(&message.into()).unwrap();
 				}
 
 				writer.into_inner()
-			},
+			}
 			Packet::Sequenced(p) => p.into(),
 		}
 	}
@@ -63,6 +60,8 @@ impl<T: Into<Vec<u8>>> From<Packet<T>> for Vec<u8> {
 #[derive(Debug)]
 pub struct SequencedPacket {
 	pub sequence: u32,
+	pub ack: u32,
+	pub ack_bits: u32,
 	pub data: Vec<u8>,
 }
 
@@ -74,10 +73,14 @@ impl TryFrom<Vec<u8>> for SequencedPacket {
 		let sequence = reader.read_u32::<NE>()?;
 
 		ensure!(sequence != 0xFFFFFFFF, "not a sequenced packet");
+		let ack = reader.read_u32::<NE>()?;
+		let ack_bits = reader.read_u32::<NE>()?;
 
 		Ok(SequencedPacket {
 			sequence,
-			data: reader.into_inner()[4..].to_owned(),
+			ack,
+			ack_bits,
+			data: reader.into_inner()[12..].to_owned(),
 		})
 	}
 }
@@ -86,7 +89,9 @@ impl From<SequencedPacket> for Vec<u8> {
 	fn from(packet: SequencedPacket) -> Vec<u8> {
 		let mut writer = Cursor::new(Vec::new());
 		writer.write_u32::<NE>(packet.sequence).unwrap();
-		writer.write(&packet.data).unwrap();
+		writer.write_u32::<NE>(packet.ack).unwrap();
+		writer.write_u32::<NE>(packet.ack_bits).unwrap();
+		writer.write_all(&packet.data).unwrap();
 		writer.into_inner()
 	}
 }
@@ -95,6 +100,31 @@ pub trait TryRead<T> {
 	fn try_read(reader: &mut Cursor<Vec<u8>>) -> anyhow::Result<T>;
 }
 
+pub fn read_data(reader: &mut Cursor<Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+	let length = reader.read_u32::<NE>()? as u64;
+	ensure!(
+		length <= reader.get_ref().len() as u64 - reader.position(),
+		"data length {} exceeds the end of the packet",
+		length
+	);
+
+	let mut data = vec![0u8; length as usize];
+	reader.read_exact(data.as_mut_slice())?;
+	Ok(data)
+}
+
+pub fn write_data(writer: &mut Cursor<Vec<u8>>, data: &[u8]) {
+	writer.write_u32::<NE>(data.len() as u32).unwrap();
+	writer.write_all(data).unwrap();
+}
+
+pub fn read_string(reader: &mut Cursor<Vec<u8>>) -> anyhow::Result<String> {
+	Ok(String::from_utf8(read_data(reader)?)?)
+}
+
+pub fn write_string(writer: &mut Cursor<Vec<u8>>, text: &str) {
+	write_data(writer, text.as_bytes());
+}
 
 /*
 	Client-to-server protocol
@@ -103,7 +133,10 @@ pub trait TryRead<T> {
 #[derive(Debug)]
 pub enum ClientMessage {
 	Connect,
-	RCon(String),
+	Disconnect,
+	RCon(String, String),
+	SnapshotAck(u32),
+	UserCommand(u32, UserCommand),
 }
 
 impl TryRead<ClientMessage> for ClientMessage {
@@ -111,16 +144,30 @@ impl TryRead<ClientMessage> for ClientMessage {
 		let message_type = reader.read_u8()?;
 
 		Ok(match message_type {
-			1 => {
-				ClientMessage::Connect
-			},
+			1 => ClientMessage::Connect,
 			2 => {
-				let length = reader.read_u32::<NE>()?;
-				let mut data = vec![0u8; length as usize];
-				reader.read_exact(data.as_mut_slice())?;
-				ClientMessage::RCon(String::from_utf8(data)?)
-			},
-			_ => unreachable!(),
+				let password = read_string(reader)?;
+				let command = read_string(reader)?;
+				ClientMessage::RCon(password, command)
+			}
+			3 => ClientMessage::Disconnect,
+			4 => {
+				let number = reader.read_u32::<NE>()?;
+				let actions = reader.read_u8()?;
+				ClientMessage::UserCommand(
+					number,
+					UserCommand {
+						action_attack: actions & 1 != 0,
+						action_use: actions & 2 != 0,
+						axis_forward: reader.read_f32::<NE>()?,
+						axis_pitch: reader.read_f32::<NE>()?,
+						axis_strafe: reader.read_f32::<NE>()?,
+						axis_yaw: reader.read_f32::<NE>()?,
+					},
+				)
+			}
+			5 => ClientMessage::SnapshotAck(reader.read_u32::<NE>()?),
+			_ => bail!("unknown client message type {}", message_type),
 		})
 	}
 }
@@ -133,10 +180,28 @@ impl From<ClientMessage> for Vec<u8> {
 			ClientMessage::Connect => {
 				writer.write_u8(1).unwrap();
 			}
-			ClientMessage::RCon(text) => {
+			ClientMessage::RCon(password, command) => {
 				writer.write_u8(2).unwrap();
-				writer.write_u32::<NE>(text.len() as u32).unwrap();
-				writer.write(text.as_bytes()).unwrap();
+				write_string(&mut writer, &password);
+				write_string(&mut writer, &command);
+			}
+			ClientMessage::Disconnect => {
+				writer.write_u8(3).unwrap();
+			}
+			ClientMessage::UserCommand(number, command) => {
+				writer.write_u8(4).unwrap();
+				writer.write_u32::<NE>(number).unwrap();
+				writer
+					.write_u8(command.action_attack as u8 | (command.action_use as u8) << 1)
+					.unwrap();
+				writer.write_f32::<NE>(command.axis_forward).unwrap();
+				writer.write_f32::<NE>(command.axis_pitch).unwrap();
+				writer.write_f32::<NE>(command.axis_strafe).unwrap();
+				writer.write_f32::<NE>(command.axis_yaw).unwrap();
+			}
+			ClientMessage::SnapshotAck(number) => {
+				writer.write_u8(5).unwrap();
+				writer.write_u32::<NE>(number).unwrap();
 			}
 		}
 
@@ -144,21 +209,39 @@ impl From<ClientMessage> for Vec<u8> {
 	}
 }
 
-
 /*
 	Server-to-client protocol
 */
 
 #[derive(Debug)]
 pub enum ServerMessage {
+	CommandAck(u32),
 	//ConfigVariable(String, String),
 	ConnectResponse,
-	ComponentDelete(u32, u8),
-	ComponentDelta(u32, u8, Vec<u8>),
-	ComponentNew(u32, u8),
 	Disconnect,
+	MapChange(String),
+	PlayerEntity(u32),
+	RConOutput(String),
+	Scoreboard(Vec<(u8, i32)>),
+	Snapshot(Snapshot),
+	/// A sound to play, from an entity and sound channel, or everywhere if `None`.
+	SoundPlay(Option<(u32, u8)>, String),
+}
+
+/// The changes to the world since the baseline snapshot, or since an empty world if the
+/// baseline is 0.
+#[derive(Debug)]
+pub struct Snapshot {
+	pub number: u32,
+	pub baseline: u32,
+	pub deltas: Vec<SnapshotDelta>,
+}
+
+#[derive(Debug)]
+pub enum SnapshotDelta {
 	EntityDelete(u32),
-	EntityNew(u32),
+	ComponentDelete(u32, u8),
+	ComponentDelta(u32, u8, Vec<u8>),
 }
 
 impl TryRead<ServerMessage> for ServerMessage {
@@ -166,39 +249,64 @@ impl TryRead<ServerMessage> for ServerMessage {
 		let message_type = reader.read_u8()?;
 
 		Ok(match message_type {
-			1 => {
-				ServerMessage::ConnectResponse
-			},
-			2 => {
-				let entity_id = reader.read_u32::<NE>()?;
-				let component_id = reader.read_u8()?;
-				ServerMessage::ComponentDelete(entity_id, component_id)
-			},
-			3 => {
-				let entity_id = reader.read_u32::<NE>()?;
-				let component_id = reader.read_u8()?;
-				let length = reader.read_u32::<NE>()?;
-				let mut data = vec![0u8; length as usize];
-				reader.read_exact(data.as_mut_slice())?;
-				ServerMessage::ComponentDelta(entity_id, component_id, data)
-			},
+			1 => ServerMessage::ConnectResponse,
+			2 => ServerMessage::Disconnect,
+			3 => ServerMessage::MapChange(read_string(reader)?),
 			4 => {
 				let entity_id = reader.read_u32::<NE>()?;
-				let component_id = reader.read_u8()?;
-				ServerMessage::ComponentNew(entity_id, component_id)
-			},
+				ServerMessage::PlayerEntity(entity_id)
+			}
 			5 => {
-				ServerMessage::Disconnect
-			},
+				let number = reader.read_u32::<NE>()?;
+				let baseline = reader.read_u32::<NE>()?;
+				let count = reader.read_u32::<NE>()?;
+				let mut deltas = Vec::new();
+
+				for _ in 0..count {
+					let delta_type = reader.read_u8()?;
+					let entity_id = reader.read_u32::<NE>()?;
+
+					deltas.push(match delta_type {
+						1 => SnapshotDelta::EntityDelete(entity_id),
+						2 => SnapshotDelta::ComponentDelete(entity_id, reader.read_u8()?),
+						3 => {
+							let component_id = reader.read_u8()?;
+							let data = read_data(reader)?;
+							SnapshotDelta::ComponentDelta(entity_id, component_id, data)
+						}
+						_ => bail!("unknown snapshot delta type {}", delta_type),
+					});
+				}
+
+				ServerMessage::Snapshot(Snapshot {
+					number,
+					baseline,
+					deltas,
+				})
+			}
 			6 => {
-				let entity_id = reader.read_u32::<NE>()?;
-				ServerMessage::EntityDelete(entity_id)
-			},
-			7 => {
-				let entity_id = reader.read_u32::<NE>()?;
-				ServerMessage::EntityNew(entity_id)
-			},
-			_ => unreachable!(),
+				let origin = match reader.read_u8()? {
+					0 => None,
+					_ => Some((reader.read_u32::<NE>()?, reader.read_u8()?)),
+				};
+				let name = read_string(reader)?;
+				ServerMessage::SoundPlay(origin, name)
+			}
+			7 => ServerMessage::CommandAck(reader.read_u32::<NE>()?),
+			8 => ServerMessage::RConOutput(read_string(reader)?),
+			9 => {
+				let count = reader.read_u8()?;
+				let mut scores = Vec::with_capacity(count as usize);
+
+				for _ in 0..count {
+					let player_num = reader.read_u8()?;
+					let frags = reader.read_i32::<NE>()?;
+					scores.push((player_num, frags));
+				}
+
+				ServerMessage::Scoreboard(scores)
+			}
+			_ => bail!("unknown server message type {}", message_type),
 		})
 	}
 }
@@ -208,39 +316,114 @@ impl From<ServerMessage> for Vec<u8> {
 		let mut writer = Cursor::new(Vec::new());
 
 		match message {
+			ServerMessage::CommandAck(number) => {
+				writer.write_u8(7).unwrap();
+				writer.write_u32::<NE>(number).unwrap();
+			}
 			ServerMessage::ConnectResponse => {
 				writer.write_u8(1).unwrap();
-			},
-			ServerMessage::ComponentDelete(entity_id, component_id) => {
+			}
+			ServerMessage::Disconnect => {
 				writer.write_u8(2).unwrap();
-				writer.write_u32::<NE>(entity_id).unwrap();
-				writer.write_u8(component_id).unwrap();
-			},
-			ServerMessage::ComponentDelta(entity_id, component_id, data) => {
+			}
+			ServerMessage::MapChange(name) => {
 				writer.write_u8(3).unwrap();
-				writer.write_u32::<NE>(entity_id).unwrap();
-				writer.write_u8(component_id).unwrap();
-				writer.write_u32::<NE>(data.len() as u32).unwrap();
-				writer.write(&data).unwrap();
-			},
-			ServerMessage::ComponentNew(entity_id, component_id) => {
+				write_string(&mut writer, &name);
+			}
+			ServerMessage::PlayerEntity(entity_id) => {
 				writer.write_u8(4).unwrap();
 				writer.write_u32::<NE>(entity_id).unwrap();
-				writer.write_u8(component_id).unwrap();
-			},
-			ServerMessage::Disconnect => {
+			}
+			ServerMessage::RConOutput(text) => {
+				writer.write_u8(8).unwrap();
+				write_string(&mut writer, &text);
+			}
+			ServerMessage::Scoreboard(scores) => {
+				writer.write_u8(9).unwrap();
+				writer.write_u8(scores.len() as u8).unwrap();
+
+				for (player_num, frags) in scores {
+					writer.write_u8(player_num).unwrap();
+					writer.write_i32::<NE>(frags).unwrap();
+				}
+			}
+			ServerMessage::Snapshot(snapshot) => {
 				writer.write_u8(5).unwrap();
-			},
-			ServerMessage::EntityDelete(entity_id) => {
+				writer.write_u32::<NE>(snapshot.number).unwrap();
+				writer.write_u32::<NE>(snapshot.baseline).unwrap();
+				writer
+					.write_u32::<NE>(snapshot.deltas.len() as u32)
+					.unwrap();
+
+				for delta in snapshot.deltas {
+					match delta {
+						SnapshotDelta::EntityDelete(entity_id) => {
+							writer.write_u8(1).unwrap();
+							writer.write_u32::<NE>(entity_id).unwrap();
+						}
+						SnapshotDelta::ComponentDelete(entity_id, component_id) => {
+							writer.write_u8(2).unwrap();
+							writer.write_u32::<NE>(entity_id).unwrap();
+							writer.write_u8(component_id).unwrap();
+						}
+						SnapshotDelta::ComponentDelta(entity_id, component_id, data) => {
+							writer.write_u8(3).unwrap();
+							writer.write_u32::<NE>(entity_id).unwrap();
+							writer.write_u8(component_id).unwrap();
+							write_data(&mut writer, &data);
+						}
+					}
+				}
+			}
+			ServerMessage::SoundPlay(origin, name) => {
 				writer.write_u8(6).unwrap();
-				writer.write_u32::<NE>(entity_id).unwrap();
-			},
-			ServerMessage::EntityNew(entity_id) => {
-				writer.write_u8(7).unwrap();
-				writer.write_u32::<NE>(entity_id).unwrap();
-			},
+
+				if let Some((entity_id, channel)) = origin {
+					writer.write_u8(1).unwrap();
+					writer.write_u32::<NE>(entity_id).unwrap();
+					writer.write_u8(channel).unwrap();
+				} else {
+					writer.write_u8(0).unwrap();
+				}
+
+				write_string(&mut writer, &name);
+			}
 		}
 
 		writer.into_inner()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::{ClientMessage, Packet, ServerMessage};
+	use crate::rcon_client;
+	use std::convert::TryFrom;
+
+	#[test]
+	fn rcon_client_matches() -> anyhow::Result<()> {
+		assert_eq!(rcon_client::DEFAULT_PORT, crate::net::DEFAULT_PORT);
+
+		let data = rcon_client::write_command("secret", "map e1m2")?;
+
+		match Packet::<ClientMessage>::try_from(data)? {
+			Packet::Unsequenced(messages) => match messages.as_slice() {
+				[ClientMessage::RCon(password, command)] => {
+					assert_eq!(password, "secret");
+					assert_eq!(command, "map e1m2");
+				}
+				_ => panic!("expected one rcon message, got {:?}", messages),
+			},
+			packet => panic!("expected an unsequenced packet, got {:?}", packet),
+		}
+
+		let data: Vec<u8> = Packet::Unsequenced(vec![
+			ServerMessage::RConOutput("first".to_owned()),
+			ServerMessage::RConOutput("second".to_owned()),
+		])
+		.into();
+		assert_eq!(rcon_client::read_output(&data)?, ["first", "second"]);
+
+		Ok(())
+	}
+}
@@ -18,6 +18,7 @@ pub struct RenderTarget {
 	depth_buffer: Option<Arc<AttachmentImage>>,
 	images: Vec<Arc<SwapchainImage<Window>>>,
 	swapchain: Arc<Swapchain<Window>>,
+	vsync: bool,
 }
 
 impl RenderTarget {
@@ -26,15 +27,12 @@ impl RenderTarget {
 		device: Arc<Device>,
 		dimensions: [u32; 2],
 		with_depth_buffer: bool,
+		vsync: bool,
 	) -> anyhow::Result<RenderTarget> {
 		let capabilities = surface.capabilities(device.physical_device())?;
 		let surface_format =
 			choose_format(&capabilities).context("No suitable swapchain format found")?;
-		let present_mode = [PresentMode::Mailbox, PresentMode::Fifo]
-			.iter()
-			.copied()
-			.find(|mode| capabilities.present_modes.supports(*mode))
-			.unwrap();
+		let present_mode = choose_present_mode(&capabilities, vsync);
 
 		let image_count = u32::min(
 			capabilities.min_image_count + 1,
@@ -80,6 +78,7 @@ impl RenderTarget {
 			depth_buffer,
 			images,
 			swapchain,
+			vsync,
 		})
 	}
 
@@ -103,6 +102,11 @@ impl RenderTarget {
 			.map(|b| ImageViewAccess::inner(&*b).format())
 	}
 
+	/// Takes effect when the target is next recreated.
+	pub fn set_vsync(&mut self, vsync: bool) {
+		self.vsync = vsync;
+	}
+
 	pub fn recreate(&mut self, dimensions: [u32; 2]) -> anyhow::Result<RenderTarget> {
 		let capabilities = self
 			.swapchain()
@@ -140,7 +144,7 @@ impl RenderTarget {
 			SharingMode::Exclusive,
 			capabilities.current_transform,
 			CompositeAlpha::Opaque,
-			self.swapchain.present_mode(),
+			choose_present_mode(&capabilities, self.vsync),
 			FullscreenExclusive::Default,
 			true,
 			ColorSpace::SrgbNonLinear,
@@ -152,6 +156,7 @@ impl RenderTarget {
 			depth_buffer,
 			images,
 			swapchain,
+			vsync: self.vsync,
 		})
 	}
 
@@ -168,6 +173,25 @@ impl RenderTarget {
 	}
 }
 
+fn choose_present_mode(capabilities: &Capabilities, vsync: bool) -> PresentMode {
+	// Fifo is always supported
+	let modes: &[PresentMode] = if vsync {
+		&[PresentMode::Fifo]
+	} else {
+		&[
+			PresentMode::Mailbox,
+			PresentMode::Immediate,
+			PresentMode::Fifo,
+		]
+	};
+
+	modes
+		.iter()
+		.copied()
+		.find(|mode| capabilities.present_modes.supports(*mode))
+		.unwrap()
+}
+
 fn choose_format(capabilities: &Capabilities) -> Option<Format> {
 	let srgb_formats = capabilities
 		.supported_formats